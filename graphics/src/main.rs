@@ -5,29 +5,60 @@ use std::{env, process};
 
 fn main() -> Result<(), Box<dyn Error>> {
     let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        eprintln!("Not enough arguments! Provide a script file!");
-        process::exit(1);
-    }
-    let filename = &args[1];
+    // `--workers N` draws the frames of animations N at a time
+    let (filename, workers) = match args.as_slice() {
+        [] | [_] => {
+            eprintln!("Not enough arguments! Provide a script file!");
+            process::exit(1);
+        },
+        [_, filename] => (filename, 1),
+        [_, filename, flag, workers] if flag == "--workers" => match workers.parse() {
+            Ok(workers) if workers > 0 => (filename, workers),
+            _ => {
+                eprintln!("--workers needs a positive number of threads!");
+                process::exit(1);
+            },
+        },
+        _ => {
+            eprintln!("Usage: {} <script> [--workers N]", args[0]);
+            process::exit(1);
+        },
+    };
 
-    let mut screen = ScreenBuilder::default();
-    // Default xres and yres is 500
-    screen.xres = 500;
-    screen.yres = 500;
-    // Example:
-    //screen.xres = 723;
-    //screen.yres = 501;
+    let screen = ScreenBuilder {
+        // Default xres and yres is 500
+        xres: 500,
+        yres: 500,
+        // Example:
+        //xres: 723,
+        //yres: 501,
+        // Anti-aliasing, with 4x4 samples per pixel:
+        //supersample: 4,
+        //downfilter: lib_graphics::screen::Downfilter::Tent,
+        //multisample: true,
+        // Wide, anti-aliased lines:
+        //line_style: lib_graphics::screen::LineStyle { width: 2., smooth: true, ..Default::default() },
+        // Transparent background in pngs:
+        //screen_color: lib_graphics::Color::rgba(0, 0, 0, 0),
+        // Tone mapping, for lights brighter than white:
+        //tone_map: lib_graphics::screen::ToneMap::Aces,
+        //srgb: true,
+        // Draw polygons on one thread instead of one per core:
+        //threads: 1,
+        ..Default::default()
+    };
 
     let mut screen = screen.create();
     let mut cstack = vec![IDENTITY];
 
-    //parse_graphics::file(filename)?.run(&mut screen, &mut cstack);
-    parse_graphics::file(filename)
-        // TODO FIXME: Fix this UGLY HIDEOUS HACK
-        .unwrap_or_else(|err| {
-            let err = err.to_string().replace("NEWLINE", "\n"); panic!("{}", err)
-        })
-        .run(&mut screen, &mut cstack);
+    let result = parse_graphics::file(filename).and_then(|mut todo| {
+        todo.workers = workers;
+        todo.run(&mut screen, &mut cstack).map_err(Into::into)
+    });
+    // Errors show where they are in the script
+    if let Err(err) = result {
+        eprint!("{}", err);
+        process::exit(1);
+    }
     Ok(())
 }
@@ -0,0 +1,199 @@
+// How knobs change over a range of frames: easing curves between 2 values, and splines
+// through any number of keyframes
+use crate::draw::Curve;
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Easing {
+    // The same change every frame
+    Linear,
+    // Starts slow
+    EaseIn,
+    // Ends slow
+    EaseOut,
+    // Starts and ends slow
+    EaseInOut,
+    // Like `EaseInOut`, but slower at the ends and faster in the middle
+    Cubic,
+    // Half a cosine wave, which is gentler than `EaseInOut`
+    Sine,
+    // Bounces into the end value
+    Bounce,
+    // Overshoots the end value and springs back to it
+    Elastic,
+    // Holds the start value, then jumps to the end value on the last frame
+    Step,
+}
+
+#[derive(Debug)]
+pub struct ParseEasingError;
+
+impl FromStr for Easing {
+    type Err = ParseEasingError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "linear" => Ok(Easing::Linear),
+            "ease_in" => Ok(Easing::EaseIn),
+            "ease_out" => Ok(Easing::EaseOut),
+            "ease_in_out" => Ok(Easing::EaseInOut),
+            "cubic" => Ok(Easing::Cubic),
+            "sine" => Ok(Easing::Sine),
+            "bounce" => Ok(Easing::Bounce),
+            "elastic" => Ok(Easing::Elastic),
+            "step" => Ok(Easing::Step),
+            _ => Err(ParseEasingError),
+        }
+    }
+}
+
+impl Easing {
+    // How far from the start value to the end value a knob is, `t` of the way through
+    // its frames. Always 0 at the start and 1 at the end
+    pub fn apply(self, t: f64) -> f64 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => 1. - (1. - t) * (1. - t),
+            Easing::EaseInOut if t < 0.5 => 2. * t * t,
+            Easing::EaseInOut => 1. - 2. * (1. - t) * (1. - t),
+            Easing::Cubic if t < 0.5 => 4. * t.powi(3),
+            Easing::Cubic => 1. - 4. * (1. - t).powi(3),
+            Easing::Sine => (1. - (PI * t).cos()) / 2.,
+            Easing::Bounce => bounce(t),
+            Easing::Elastic if t <= 0. || t >= 1. => t.clamp(0., 1.),
+            Easing::Elastic => 1. + 2_f64.powf(-10. * t) * ((10. * t - 0.75) * 2. * PI / 3.).sin(),
+            Easing::Step if t < 1. => 0.,
+            Easing::Step => 1.,
+        }
+    }
+}
+
+// 4 parabolas, each smaller than the one before
+fn bounce(t: f64) -> f64 {
+    const N: f64 = 7.5625;
+    const D: f64 = 2.75;
+    let (t, floor) = if t < 1. / D {
+        (t, 0.)
+    } else if t < 2. / D {
+        (t - 1.5 / D, 0.75)
+    } else if t < 2.5 / D {
+        (t - 2.25 / D, 0.9375)
+    } else {
+        (t - 2.625 / D, 0.984_375)
+    };
+    N * t * t + floor
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Spline {
+    // Passes through every keyframe, heading from the keyframe before it to the one after
+    CatmullRom,
+    // Passes through every keyframe with the slope given for it
+    Hermite,
+}
+
+#[derive(Debug)]
+pub struct ParseSplineError;
+
+impl FromStr for Spline {
+    type Err = ParseSplineError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "catmull_rom" => Ok(Spline::CatmullRom),
+            "hermite" => Ok(Spline::Hermite),
+            _ => Err(ParseSplineError),
+        }
+    }
+}
+
+// A knob's value at a frame, and how fast it's changing there in units per frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keyframe {
+    pub frame: f64,
+    pub value: f64,
+    pub slope: f64,
+}
+
+// Catmull-Rom slopes for `(frame, value)` points. The first and last points head
+// straight towards their only neighbor
+pub fn catmull_rom(points: &[(f64, f64)]) -> Vec<Keyframe> {
+    let last = points.len().saturating_sub(1);
+    points
+        .iter()
+        .enumerate()
+        .map(|(i, &(frame, value))| {
+            let (before, after) = (points[i.saturating_sub(1)], points[(i + 1).min(last)]);
+            let slope = if after.0 > before.0 { (after.1 - before.1) / (after.0 - before.0) } else { 0. };
+            Keyframe { frame, value, slope }
+        })
+        .collect()
+}
+
+// The value of the spline through `keyframes`, which are sorted by frame. Before the
+// first keyframe and after the last, their values are held
+pub fn interpolate(keyframes: &[Keyframe], frame: f64) -> f64 {
+    let next = keyframes.iter().position(|key| key.frame > frame);
+    let (k0, k1) = match next {
+        Some(0) => return keyframes[0].value,
+        Some(i) => (keyframes[i - 1], keyframes[i]),
+        None => return keyframes.last().map_or(0., |key| key.value),
+    };
+    // With x as the frame and y as the value, x moves evenly from one keyframe to the
+    // next, so the curve's `t` is how far between the frames we are
+    let length = k1.frame - k0.frame;
+    let curve = Curve::Hermite {
+        p0x: k0.frame,
+        p0y: k0.value,
+        p1x: k1.frame,
+        p1y: k1.value,
+        r0x: length,
+        r0y: k0.slope * length,
+        r1x: length,
+        r1y: k1.slope * length,
+    };
+    Curve::point(&curve.gen_coefs(), (frame - k0.frame) / length).1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn easing_ends() {
+        let easings = [
+            Easing::Linear,
+            Easing::EaseIn,
+            Easing::EaseOut,
+            Easing::EaseInOut,
+            Easing::Cubic,
+            Easing::Sine,
+            Easing::Bounce,
+            Easing::Elastic,
+            Easing::Step,
+        ];
+        for easing in &easings {
+            assert!(easing.apply(0.).abs() < 1e-9, "{:?}", easing);
+            assert!((easing.apply(1.) - 1.).abs() < 1e-9, "{:?}", easing);
+        }
+        assert!((Easing::Sine.apply(0.5) - 0.5).abs() < 1e-9);
+        assert_eq!(0., Easing::Step.apply(0.99));
+    }
+
+    #[test]
+    fn spline_through_keyframes() {
+        let keyframes = catmull_rom(&[(0., 0.), (10., 5.), (20., 0.), (30., 5.)]);
+        for key in &keyframes {
+            assert!((interpolate(&keyframes, key.frame) - key.value).abs() < 1e-9);
+        }
+        // The slope through the middle keyframes is flat
+        assert_eq!(0., keyframes[1].slope);
+        assert!(interpolate(&keyframes, 9.) < 5. && interpolate(&keyframes, 11.) < 5.);
+        // Held outside of the keyframes
+        assert_eq!(0., interpolate(&keyframes, -5.));
+        assert_eq!(5., interpolate(&keyframes, 40.));
+        // A straight line stays straight
+        let line = catmull_rom(&[(0., 0.), (4., 8.), (10., 20.)]);
+        assert!((interpolate(&line, 7.) - 14.).abs() < 1e-9);
+    }
+}
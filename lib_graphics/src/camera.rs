@@ -0,0 +1,116 @@
+use crate::matrix::{RawMatrix, SquareMatrix, COLS};
+use crate::Vector;
+use std::f64;
+
+#[derive(Debug, Clone)]
+pub struct Camera {
+    pub eye: Vector,
+    pub aim: Vector,
+    pub focal: f64,
+    // Distance from the eye to the near and far clipping planes
+    pub near: f64,
+    pub far: f64,
+    view: SquareMatrix,
+}
+
+impl Camera {
+    // If `focal` isn't given, it defaults to the distance between `eye` and `aim`.
+    // That way objects on the plane through `aim` keep their size in pixels.
+    // There's no direction to look in if `eye` and `aim` are the same point
+    pub fn new(eye: Vector, aim: Vector, focal: Option<f64>) -> Option<Camera> {
+        let forward = &aim - &eye;
+        let distance = forward.dot_product(&forward).sqrt();
+        if !distance.is_finite() || distance < 1e-9 {
+            return None;
+        }
+        let focal = focal.unwrap_or(distance);
+        let view = Camera::look_at(&eye, &forward);
+        Some(Camera { eye, aim, focal, near: 1., far: f64::INFINITY, view })
+    }
+
+    // World space to view space matrix. In view space the eye is at the origin,
+    // looking down the -z axis with +y up
+    fn look_at(eye: &Vector, forward: &Vector) -> SquareMatrix {
+        let forward = Vector::normalized(forward);
+        // Keep +y as up unless we're looking straight up or down
+        let right = forward.cross_product(&Vector::new(0., 1., 0.));
+        let right = if right.dot_product(&right) < 1e-12 {
+            forward.cross_product(&Vector::new(0., 0., -1.))
+        } else {
+            right
+        };
+        let right = Vector::normalized(&right);
+        let up = right.cross_product(&forward);
+
+        // Each column is the dot product with one of the camera's axes
+        #[rustfmt::skip]
+        let m = [
+            [right.x, up.x, -forward.x, 0.],
+            [right.y, up.y, -forward.y, 0.],
+            [right.z, up.z, -forward.z, 0.],
+            [-right.dot_product(eye), -up.dot_product(eye), forward.dot_product(eye), 1.],
+        ];
+        SquareMatrix::from(m)
+    }
+
+    pub fn view_matrix(&self) -> &SquareMatrix {
+        &self.view
+    }
+
+    // Unit vector pointing from `point` towards the eye
+    pub fn view_vector(&self, point: &Vector) -> Vector {
+        Vector::normalized(&(&self.eye - point))
+    }
+
+    // World space to view space
+    pub fn to_view(&self, point: &[f64; COLS]) -> [f64; COLS] {
+        let m = self.view.raw();
+        let mut out = [0.; COLS];
+        for (col, out) in out.iter_mut().enumerate() {
+            *out = (0..COLS).map(|row| m[row][col] * point[row]).sum();
+        }
+        out
+    }
+
+    // View space to homogeneous clip space, where the screen spans -w..w in x and y.
+    // w is the distance from the eye and z is its negation, so that after the
+    // perspective divide bigger z values are still closer
+    pub fn to_clip(&self, view: &[f64; COLS], xres: usize, yres: usize) -> [f64; COLS] {
+        let (half_x, half_y) = (xres as f64 / 2., yres as f64 / 2.);
+        [view[0] * self.focal / half_x, view[1] * self.focal / half_y, view[2], -view[2]]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(expected: &[f64], actual: &[f64]) -> bool {
+        expected.iter().zip(actual).all(|(e, a)| (e - a).abs() < 1e-9)
+    }
+
+    #[test]
+    fn projection() {
+        // 10 units back from the origin, looking down -z
+        let camera = Camera::new(Vector::new(0., 0., 10.), Vector::new(0., 0., 0.), None).unwrap();
+        assert_eq!(10., camera.focal);
+        let view = camera.to_view(&[5., -2., 0., 1.]);
+        assert!(close(&[5., -2., -10., 1.], &view), "{:?}", view);
+        // On the plane through `aim`, things keep their size: 5 units is 5 pixels from the center
+        let clip = camera.to_clip(&view, 100, 50);
+        assert!(close(&[1., -0.8, -10., 10.], &clip), "{:?}", clip);
+        assert!(close(&[55., 23.], &[50. * (1. + clip[0] / clip[3]), 25. * (1. + clip[1] / clip[3])]));
+        // Twice as far away is half the size
+        let clip = camera.to_clip(&camera.to_view(&[5., 0., -10., 1.]), 100, 50);
+        assert!(close(&[0.05], &[clip[0] / clip[3]]));
+
+        // Looking straight down still works
+        let down = Camera::new(Vector::new(0., 10., 0.), Vector::new(0., 0., 0.), None).unwrap();
+        let view = down.to_view(&[1., 0., 2., 1.]);
+        assert!(view.iter().all(|v| v.is_finite()));
+        assert!(close(&[-10.], &view[2..3]));
+
+        // But there's nowhere to look from the point being looked at
+        assert!(Camera::new(Vector::new(1., 2., 3.), Vector::new(1., 2., 3.), None).is_none());
+    }
+}
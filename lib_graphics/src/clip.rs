@@ -0,0 +1,131 @@
+// Sutherland–Hodgman clipping of homogeneous clip space points.
+// In clip space the visible volume is -w <= x <= w and -w <= y <= w,
+// plus any near/far limits on w.
+use crate::matrix::COLS;
+
+pub type Point = [f64; COLS];
+
+// Points where `distance` is >= 0 are on the visible side of the plane
+#[derive(Debug, Clone)]
+pub struct Plane {
+    pub normal: [f64; COLS],
+    pub offset: f64,
+}
+
+impl Plane {
+    pub const fn new(normal: [f64; COLS], offset: f64) -> Plane {
+        Plane { normal, offset }
+    }
+
+    // The left, right, bottom and top planes of the view volume
+    pub fn sides() -> [Plane; 4] {
+        [
+            Plane::new([1., 0., 0., 1.], 0.),
+            Plane::new([-1., 0., 0., 1.], 0.),
+            Plane::new([0., 1., 0., 1.], 0.),
+            Plane::new([0., -1., 0., 1.], 0.),
+        ]
+    }
+
+    // w >= near
+    pub fn near(near: f64) -> Plane {
+        Plane::new([0., 0., 0., 1.], -near)
+    }
+
+    // w <= far
+    pub fn far(far: f64) -> Plane {
+        Plane::new([0., 0., 0., -1.], far)
+    }
+
+    pub fn distance(&self, p: &Point) -> f64 {
+        self.normal.iter().zip(p).map(|(n, p)| n * p).sum::<f64>() + self.offset
+    }
+}
+
+// Anything with a clip space position that can be interpolated along an edge
+pub trait Clip: Copy {
+    fn position(&self) -> &Point;
+    fn lerp(&self, other: &Self, t: f64) -> Self;
+}
+
+impl Clip for Point {
+    fn position(&self) -> &Point {
+        self
+    }
+
+    fn lerp(&self, other: &Point, t: f64) -> Point {
+        let mut out = [0.; COLS];
+        for (i, out) in out.iter_mut().enumerate() {
+            *out = self[i] + (other[i] - self[i]) * t;
+        }
+        out
+    }
+}
+
+// Clip a convex polygon against every plane. The result is empty if the
+// polygon is completely outside
+pub fn clip_polygon<V: Clip>(polygon: &[V], planes: &[Plane]) -> Vec<V> {
+    let mut output = polygon.to_vec();
+    let mut input = Vec::with_capacity(polygon.len() + planes.len());
+    for plane in planes {
+        if output.is_empty() {
+            break;
+        }
+        std::mem::swap(&mut input, &mut output);
+        output.clear();
+
+        let mut prev = input[input.len() - 1];
+        let mut prev_dist = plane.distance(prev.position());
+        for &current in &input {
+            let dist = plane.distance(current.position());
+            if dist >= 0. {
+                if prev_dist < 0. {
+                    output.push(prev.lerp(&current, prev_dist / (prev_dist - dist)));
+                }
+                output.push(current);
+            } else if prev_dist >= 0. {
+                output.push(prev.lerp(&current, prev_dist / (prev_dist - dist)));
+            }
+            prev = current;
+            prev_dist = dist;
+        }
+    }
+    output
+}
+
+// Clip a line segment against every plane
+pub fn clip_line<V: Clip>(mut p0: V, mut p1: V, planes: &[Plane]) -> Option<(V, V)> {
+    for plane in planes {
+        let (d0, d1) = (plane.distance(p0.position()), plane.distance(p1.position()));
+        match (d0 >= 0., d1 >= 0.) {
+            (true, true) => {},
+            (false, false) => return None,
+            (true, false) => p1 = p0.lerp(&p1, d0 / (d0 - d1)),
+            (false, true) => p0 = p0.lerp(&p1, d0 / (d0 - d1)),
+        }
+    }
+    Some((p0, p1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clip_triangle_corner() {
+        // A triangle poking out of the right side becomes a quad
+        let triangle = [[0., 0., 0., 1.], [2., 0., 0., 1.], [0., 0.5, 0., 1.]];
+        let clipped = clip_polygon(&triangle, &Plane::sides());
+        assert_eq!(4, clipped.len());
+        assert!(clipped.iter().all(|p| p[0] <= 1. + 1e-12));
+    }
+
+    #[test]
+    fn clip_line_near() {
+        let planes = [Plane::near(1.)];
+        let (p0, p1) = clip_line([0., 0., 0., -1.], [0., 0., 0., 3.], &planes).unwrap();
+        assert_eq!(1., p0[3]);
+        assert_eq!(3., p1[3]);
+        assert!(clip_line([0., 0., 0., -1.], [0., 0., 0., 0.5], &planes).is_none());
+    }
+}
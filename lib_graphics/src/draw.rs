@@ -33,6 +33,12 @@ impl Curve {
         (coefs_x, coefs_y)
     }
 
+    // The point `t` of the way along the curve, from the coefficients made by `gen_coefs`
+    pub fn point((xs, ys): &(Matrix, Matrix), t: f64) -> (f64, f64) {
+        let cubic = |coefs: &Matrix| coefs.m[0][0] * t.powi(3) + coefs.m[0][1] * t.powi(2) + coefs.m[0][2] * t + coefs.m[0][3];
+        (cubic(xs), cubic(ys))
+    }
+
     fn gen_coef_helper(&self) -> Matrix {
         match self {
             Curve::Hermite { .. } => {
@@ -91,6 +97,11 @@ pub fn add_polygon(
     add_point(polygons, x2, y2, z2);
 }
 
+// Texture coordinates for the 3 points of a polygon
+fn add_uv(uvs: &mut Vec<[f64; 2]>, uv0: [f64; 2], uv1: [f64; 2], uv2: [f64; 2]) {
+    uvs.extend_from_slice(&[uv0, uv1, uv2]);
+}
+
 pub fn add_circle(edges: &mut Matrix, cx: f64, cy: f64, cz: f64, r: f64, steps: usize) {
     // Draw a circle using parametric equations
     let mut x_prev = cx + r;
@@ -113,18 +124,11 @@ pub fn add_curve(edges: &mut Matrix, curve: &Curve, steps: usize) {
         Curve::Hermite { p0x, p0y, .. } => (p0x, p0y),
         Curve::Bezier { p0x, p0y, .. } => (p0x, p0y),
     };
-    let (xs, ys) = curve.gen_coefs();
+    let coefs = curve.gen_coefs();
 
     for t in 0..=steps {
         let progress = t as f64 / steps as f64;
-        let x_next = xs.m[0][0] * progress.powi(3)
-            + xs.m[0][1] * progress.powi(2)
-            + xs.m[0][2] * progress
-            + xs.m[0][3];
-        let y_next = ys.m[0][0] * progress.powi(3)
-            + ys.m[0][1] * progress.powi(2)
-            + ys.m[0][2] * progress
-            + ys.m[0][3];
+        let (x_next, y_next) = Curve::point(&coefs, progress);
 
         add_edge(edges, x_prev, y_prev, 0.0, x_next, y_next, 0.0);
 
@@ -133,7 +137,17 @@ pub fn add_curve(edges: &mut Matrix, curve: &Curve, steps: usize) {
     }
 }
 
-pub fn add_box(polygons: &mut Matrix, x: f64, y: f64, z: f64, width: f64, height: f64, depth: f64) {
+#[allow(clippy::too_many_arguments)]
+pub fn add_box(
+    polygons: &mut Matrix,
+    uvs: &mut Vec<[f64; 2]>,
+    x: f64,
+    y: f64,
+    z: f64,
+    width: f64,
+    height: f64,
+    depth: f64,
+) {
     let x1 = x + width;
     let y1 = y - height;
     let z1 = z - depth;
@@ -156,6 +170,13 @@ pub fn add_box(polygons: &mut Matrix, x: f64, y: f64, z: f64, width: f64, height
     // bottom face
     add_polygon(polygons, (x, y1, z), (x1, y1, z1), (x1, y1, z));
     add_polygon(polygons, (x, y1, z), (x, y1, z1), (x1, y1, z1));
+
+    // Every face is covered by the whole texture, upright when looking at
+    // the face from outside of the box
+    for _ in 0..6 {
+        add_uv(uvs, [0., 1.], [1., 0.], [1., 1.]);
+        add_uv(uvs, [0., 1.], [0., 0.], [1., 0.]);
+    }
 }
 
 pub fn gen_sphere(points: &mut Matrix, cx: f64, cy: f64, cz: f64, r: f64, steps: usize) {
@@ -181,9 +202,11 @@ pub fn gen_sphere(points: &mut Matrix, cx: f64, cy: f64, cz: f64, r: f64, steps:
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn add_sphere(
     polygons: &mut Matrix,
     points: &mut Matrix,
+    uvs: &mut Vec<[f64; 2]>,
     cx: f64,
     cy: f64,
     cz: f64,
@@ -195,6 +218,9 @@ pub fn add_sphere(
 
     let end = steps;
     let steps = steps + 1;
+    // u goes around the sphere with phi and v from one pole to the other with theta.
+    // Unlike the points, the texture coordinates don't wrap around at phi = 2PI
+    let uv = |lat: usize, longt: usize| [lat as f64 / end as f64, 1. - longt as f64 / end as f64];
 
     for lat in 0..end {
         for longt in 0..end {
@@ -210,6 +236,7 @@ pub fn add_sphere(
                     (points.m[p1][0], points.m[p1][1], points.m[p1][2]),
                     (points.m[p2][0], points.m[p2][1], points.m[p2][2]),
                 );
+                add_uv(uvs, uv(lat, longt), uv(lat, longt + 1), uv(lat + 1, longt + 1));
             }
             if longt != 0 {
                 add_polygon(
@@ -218,6 +245,7 @@ pub fn add_sphere(
                     (points.m[p2][0], points.m[p2][1], points.m[p2][2]),
                     (points.m[p3][0], points.m[p3][1], points.m[p3][2]),
                 );
+                add_uv(uvs, uv(lat, longt), uv(lat + 1, longt + 1), uv(lat + 1, longt));
             }
         }
     }
@@ -246,7 +274,7 @@ pub fn gen_torus(
             let point = [
                 cos_phi * (minor_r * cos_theta + major_r) + cx,
                 minor_r * sin_theta + cy,
-                -1.0 * sin_phi * (minor_r * cos_theta + major_r) + cz,
+                -sin_phi * (minor_r * cos_theta + major_r) + cz,
                 1.0,
             ];
             points.push(point);
@@ -258,6 +286,7 @@ pub fn gen_torus(
 pub fn add_torus(
     polygons: &mut Matrix,
     points: &mut Matrix,
+    uvs: &mut Vec<[f64; 2]>,
     cx: f64,
     cy: f64,
     cz: f64,
@@ -269,6 +298,8 @@ pub fn add_torus(
     gen_torus(points, cx, cy, cz, minor_r, major_r, steps);
 
     let end = steps;
+    // u goes around the ring with phi and v around the tube with theta
+    let uv = |lat: usize, longt: usize| [lat as f64 / end as f64, longt as f64 / end as f64];
 
     for lat in 0..end {
         for longt in 0..end {
@@ -283,6 +314,7 @@ pub fn add_torus(
                 (points.m[p3][0], points.m[p3][1], points.m[p3][2]),
                 (points.m[p2][0], points.m[p2][1], points.m[p2][2]),
             );
+            add_uv(uvs, uv(lat, longt), uv(lat + 1, longt), uv(lat + 1, longt + 1));
 
             add_polygon(
                 polygons,
@@ -290,6 +322,7 @@ pub fn add_torus(
                 (points.m[p2][0], points.m[p2][1], points.m[p2][2]),
                 (points.m[p1][0], points.m[p1][1], points.m[p1][2]),
             );
+            add_uv(uvs, uv(lat, longt), uv(lat + 1, longt + 1), uv(lat, longt + 1));
         }
     }
 }
@@ -0,0 +1,159 @@
+use crate::screen::ShadowMap;
+use crate::{Color, Vector};
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+pub enum LightKind {
+    // Light coming from very far away, like the sun. `pos` is a unit vector
+    // pointing towards the light
+    Directional,
+    // Light shining out of `pos` in every direction
+    Point { attenuation: Attenuation },
+    // Light shining out of `pos` in a cone around `direction`. `inner` and `outer` are
+    // the cosines of the angles between `direction` and the edges of the fully lit
+    // cone and the cone outside of which there's no light
+    Spot { attenuation: Attenuation, direction: Vector, inner: f64, outer: f64 },
+}
+
+// At a distance d, lights are `constant + linear * d + quadratic * d^2` times dimmer
+#[derive(Debug, Clone)]
+pub struct Attenuation {
+    pub constant: f64,
+    pub linear: f64,
+    pub quadratic: f64,
+}
+
+impl Attenuation {
+    pub const NONE: Attenuation = Attenuation::new([1., 0., 0.]);
+
+    pub const fn new([constant, linear, quadratic]: [f64; 3]) -> Attenuation {
+        Attenuation { constant, linear, quadratic }
+    }
+
+    // How much of the light is left at distance `dist`
+    pub fn at(&self, dist: f64) -> f64 {
+        1. / (self.constant + self.linear * dist + self.quadratic * dist * dist)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Light {
+    pub pos: Vector,
+    pub color: Color,
+    pub kind: LightKind,
+    // Depth of the scene from the light, when shadows are on
+    pub shadow: Option<Arc<ShadowMap>>,
+}
+
+impl Light {
+    pub const fn new(pos: Vector, color: Color) -> Light {
+        Light { pos, color, kind: LightKind::Directional, shadow: None }
+    }
+
+    pub const fn point(pos: Vector, color: Color, attenuation: [f64; 3]) -> Light {
+        let kind = LightKind::Point { attenuation: Attenuation::new(attenuation) };
+        Light { pos, color, kind, shadow: None }
+    }
+
+    // `inner` and `outer` are the angles in degrees between `direction` and the edges
+    // of the cones. The light fades out smoothly between the two
+    pub fn spot(
+        pos: Vector,
+        color: Color,
+        direction: Vector,
+        (inner, outer): (f64, f64),
+        attenuation: [f64; 3],
+    ) -> Light {
+        let kind = LightKind::Spot {
+            attenuation: Attenuation::new(attenuation),
+            direction: Vector::normalized(&direction),
+            inner: inner.to_radians().cos(),
+            outer: outer.to_radians().cos(),
+        };
+        Light { pos, color, kind, shadow: None }
+    }
+
+    // Unit vector from `point` towards the light, and how much of the light reaches `point`
+    pub fn incident(&self, point: &Vector) -> (Vector, f64) {
+        let visibility = self.shadow.as_ref().map_or(1., |shadow| shadow.visibility(point));
+        let (attenuation, spot) = match &self.kind {
+            LightKind::Directional => return (self.pos.clone(), visibility),
+            LightKind::Point { attenuation } => (attenuation, None),
+            LightKind::Spot { attenuation, direction, inner, outer } => {
+                (attenuation, Some((direction, *inner, *outer)))
+            },
+        };
+        let to_light = &self.pos - point;
+        let dist = to_light.dot_product(&to_light).sqrt();
+        // There's no direction to a light from the point it's at
+        if dist == 0. {
+            return (to_light, 0.);
+        }
+        let light_v = to_light * (1. / dist);
+        let mut intensity = attenuation.at(dist) * visibility;
+        if let Some((direction, inner, outer)) = spot {
+            // Cosine of the angle between the spot's direction and the light hitting `point`
+            let cos = -light_v.dot_product(direction);
+            intensity *= if cos >= inner {
+                1.
+            } else if cos <= outer {
+                0.
+            } else {
+                // smoothstep
+                let t = (cos - outer) / (inner - outer);
+                t * t * (3. - 2. * t)
+            };
+        }
+        (light_v, intensity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attenuation() {
+        let light = Light::point(Vector::new(0., 0., 10.), Color::new(255, 255, 255), [1., 0.5, 0.25]);
+        // 1 / (1 + 0.5 * d + 0.25 * d^2)
+        for &(z, expected) in &[(9., 1. / 1.75), (8., 1. / 3.), (6., 1. / 7.)] {
+            let (light_v, intensity) = light.incident(&Vector::new(0., 0., z));
+            assert!((intensity - expected).abs() < 1e-9);
+            assert_eq!((0., 0., 1.), (light_v.x, light_v.y, light_v.z));
+        }
+        // Further away is always dimmer
+        let intensities: Vec<_> = (0..10).map(|d| light.incident(&Vector::new(f64::from(d), 0., 0.)).1).collect();
+        assert!(intensities.windows(2).all(|pair| pair[0] > pair[1]));
+    }
+
+    #[test]
+    fn spot_cone() {
+        let light = Light::spot(
+            Vector::new(0., 0., 0.),
+            Color::new(255, 255, 255),
+            Vector::new(2., 0., 0.),
+            (10., 20.),
+            [1., 0., 0.],
+        );
+        let at = |degrees: f64| {
+            let (sin, cos) = degrees.to_radians().sin_cos();
+            light.incident(&Vector::new(10. * cos, 10. * sin, 0.)).1
+        };
+        // Fully lit inside the inner cone
+        assert_eq!((1., 1.), (at(0.), at(9.)));
+        // Fading out between the cones
+        let fading: Vec<_> = [11., 15., 19.].iter().map(|&degrees| at(degrees)).collect();
+        assert!(fading.iter().all(|&intensity| intensity > 0. && intensity < 1.));
+        assert!(fading.windows(2).all(|pair| pair[0] > pair[1]));
+        // And dark outside the outer one, including behind the light
+        assert_eq!((0., 0., 0.), (at(21.), at(90.), at(180.)));
+    }
+
+    #[test]
+    fn at_the_light() {
+        let light = Light::point(Vector::new(1., 2., 3.), Color::new(255, 255, 255), [0., 0., 1.]);
+        let (light_v, intensity) = light.incident(&Vector::new(1., 2., 3.));
+        assert_eq!(0., intensity);
+        assert!([light_v.x, light_v.y, light_v.z].iter().all(|c| c.is_finite()));
+    }
+}
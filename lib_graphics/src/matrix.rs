@@ -29,7 +29,7 @@ pub trait RawMatrix {
 pub trait MatrixMult: RawMatrix {
     // Modifies other matrix to be = self * other
     #[allow(clippy::needless_range_loop)]
-    fn mult(&self, other: &mut MatrixMult) {
+    fn mult(&self, other: &mut dyn MatrixMult) {
         // columns and rows are switched
         // First check that both matrices can be multiplied
         // Graphical lens: LEFT.cols == RIGHT.rows
@@ -67,7 +67,7 @@ pub trait MatrixMult: RawMatrix {
 
 impl<T: RawMatrix> MatrixMult for T {}
 
-impl fmt::Display for RawMatrix {
+impl fmt::Display for dyn RawMatrix {
     // Print 2d array so that each point is a column
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         // precision of the floating point
@@ -197,7 +197,7 @@ impl SquareMatrix {
         let m = [
             [1., 0., 0., 0.],
             [0., cos, sin, 0.],
-            [0., -1. * sin, cos, 0.],
+            [0., -sin, cos, 0.],
             [0., 0., 0., 1.],
         ];
         SquareMatrix::from(m)
@@ -214,7 +214,7 @@ impl SquareMatrix {
         let (sin, cos) = radians.sin_cos();
         #[rustfmt::skip]
         let m = [
-            [cos, 0., -1. * sin, 0.],
+            [cos, 0., -sin, 0.],
             [0., 1., 0., 0.],
             [sin, 0., cos, 0.],
             [0., 0., 0., 1.],
@@ -234,7 +234,7 @@ impl SquareMatrix {
         #[rustfmt::skip]
         let m = [
             [cos, sin, 0., 0.],
-            [-1. * sin, cos, 0., 0.],
+            [-sin, cos, 0., 0.],
             [0., 0., 1., 0.],
             [0., 0., 0., 1.],
         ];
@@ -1,32 +1,92 @@
+use crate::clip::{self, Plane};
 use crate::matrix::{Matrix, COLS};
 use crate::PICTURE_DIR;
-use crate::{Light, Vector};
+use crate::{Camera, Light, LightKind, Vector};
+use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::f64;
 use std::fmt;
-use std::fs::{self, DirBuilder, File};
-use std::io::{self, prelude::*};
+use std::fs::{DirBuilder, File};
+use std::io::{self, prelude::*, BufWriter};
 use std::mem;
 use std::ops::{Index, IndexMut};
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::process::{Command, Stdio};
 use std::slice::{ChunksExact, ChunksExactMut};
+use std::str::FromStr;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::{Arc, Mutex};
+use std::thread;
 
 pub mod color;
-pub use color::{consts, Color, Reflection, Shine};
+pub mod gif;
+pub mod pattern;
+pub mod png;
+pub mod shadow;
+pub mod texture;
+mod zlib;
+pub use color::{consts, Color, HdrColor, Reflection, Shine, ToneMap};
+pub use pattern::{Pattern, PatternKind};
+pub use shadow::{ShadowMap, Shadows};
+pub use texture::{Filter, Texture};
 
-type Pixel = (Color, f64);
+type Pixel = (HdrColor, f64);
+// Values interpolated across a triangle while it's being drawn
+const ATTRS: usize = 9;
+// Where the texture coordinates start in `Attributes`
+const UV: usize = 6;
+// The last attribute starts out as 1, so it's 1 / w once they're divided by w
+const INV_W: usize = 8;
+type Attributes = [f64; ATTRS];
 
+// How far lines drawn over polygons are moved towards the viewer, scaled by the
+// polygon's depth slope, so that they aren't hidden by the polygon they're on
+const EDGE_BIAS: f64 = 1.;
+const MAX_DEPTH_SLOPE: f64 = 16.;
+// Fractional bits of the fixed point coordinates triangles are rasterized with
+const SUBPIXEL_BITS: u32 = 8;
+// Width and height in pixels of the tiles of the screen that are drawn on separate threads
+const TILE_SIZE: usize = 32;
+
+#[derive(Clone)]
 pub struct Screen {
     // Screen properties
+    // Every sample, `supersample` times the resolution in each direction
     pub pixels: Vec<Pixel>,
     pub xres: usize,
     pub yres: usize,
+    // Samples per pixel in each direction. See `ScreenBuilder`
+    supersample: usize,
+    pub downfilter: Downfilter,
+    pub multisample: bool,
+    // How colors are brought down to what the screen can show. See `ScreenBuilder`
+    pub tone_map: ToneMap,
+    pub srgb: bool,
+    // Polygons are drawn with this many threads
+    pub threads: usize,
     pub screen_color: Color,
     pub line_color: Color,
+    pub line_style: LineStyle,
     pub steps_2d: usize,
     pub steps_3d: usize,
     pub specular_exp: i32,
     pub view_vector: Vector,
+    // Perspective camera. Without one, the view is orthographic along `view_vector`
+    pub camera: Option<Camera>,
+    // The closest and farthest z drawn without a camera. See `ScreenBuilder`
+    pub depth_range: (f64, f64),
+    pub shading: Shading,
+    // Draw the edges of shaded polygons on top of them
+    pub overlay: bool,
+    // Lights cast shadows when set
+    pub shadows: Option<Shadows>,
+    // Polygons saved for the shadow maps instead of being drawn. See `collect_casters`
+    casters: Option<Matrix>,
+    // Fragments that aren't opaque, as the index of their sample, their depth and
+    // color. They're blended in back to front when the screen is resolved
+    translucent: Vec<(usize, f64, HdrColor)>,
+    // Image mapped onto polygons that have texture coordinates
+    pub texture: Option<Arc<Texture>>,
     pub ambient_light: Color,
     // These are default values if light(s) or reflection(s) aren't provided
     pub lights: Vec<Light>,
@@ -35,9 +95,7 @@ pub struct Screen {
 
 impl Screen {
     pub fn new(c: Color) -> Screen {
-        let mut builder = ScreenBuilder::default();
-        builder.screen_color = c;
-        builder.create()
+        ScreenBuilder { screen_color: c, ..Default::default() }.create()
     }
 
     pub fn xres(&self) -> usize {
@@ -48,86 +106,283 @@ impl Screen {
         self.yres
     }
 
-    pub fn rows(&self) -> ChunksExact<Pixel> {
-        self.pixels.chunks_exact(self.xres)
+    pub fn supersample(&self) -> usize {
+        self.supersample
     }
 
-    pub fn rows_mut(&mut self) -> ChunksExactMut<Pixel> {
-        self.pixels.chunks_exact_mut(self.xres)
+    // Size of the sample buffer
+    fn width(&self) -> usize {
+        self.xres * self.supersample
     }
 
-    fn write_ppm(&self, f: &Path) -> io::Result<()> {
-        let mut file = File::create(f)?;
-        file.write_all(self.to_string().as_bytes())
+    fn height(&self) -> usize {
+        self.yres * self.supersample
     }
 
-    pub fn write(&self, f: &[&str]) -> io::Result<()> {
-        let mut path = PathBuf::from(PICTURE_DIR);
+    // Rows of samples
+    pub fn rows(&self) -> ChunksExact<'_, Pixel> {
+        self.pixels.chunks_exact(self.width())
+    }
+
+    pub fn rows_mut(&mut self) -> ChunksExactMut<'_, Pixel> {
+        let width = self.width();
+        self.pixels.chunks_exact_mut(width)
+    }
 
-        // Separate f into the file name and its parent(s)
-        let last = f.len() - 1;
-        let parents = &f[..last];
-        let file_name = f[last];
-        for parent in parents {
-            path.push(parent);
+    // The pixels at the screen's resolution, with the translucent fragments blended in.
+    // Supersampled screens are filtered down, and each pixel keeps the depth of its
+    // closest sample. Colors are tone mapped and ready to be written out
+    pub fn resolve(&self) -> Vec<(Color, f64)> {
+        self.tone_map(&self.resolve_over(false))
+    }
+
+    // Like `resolve`, but the colors are left linear
+    pub fn resolve_hdr(&self) -> Cow<'_, [Pixel]> {
+        self.resolve_over(false)
+    }
+
+    fn tone_map(&self, pixels: &[Pixel]) -> Vec<(Color, f64)> {
+        pixels.iter().map(|(c, z)| (c.to_color(self.tone_map, self.srgb), *z)).collect()
+    }
+
+    // Like `resolve`, where `clear` makes samples that were never drawn to transparent
+    // instead of the screen color
+    fn resolve_over(&self, clear: bool) -> Cow<'_, [Pixel]> {
+        let samples = self.composite(clear);
+        let ss = self.supersample;
+        if ss == 1 {
+            return samples;
         }
+        let width = self.width() as i64;
+        let height = self.height() as i64;
+        // Samples around each pixel and how much each of them counts, along one axis.
+        // The tent filter reaches halfway into the neighboring pixels
+        let weights: Vec<(i64, f64)> = match self.downfilter {
+            Downfilter::Box => (0..ss as i64).map(|i| (i, 1.)).collect(),
+            Downfilter::Tent => (-(ss as i64)..2 * ss as i64)
+                .map(|i| {
+                    let d = (i as f64 + 0.5 - ss as f64 / 2.) / ss as f64;
+                    (i, 1. - d.abs())
+                })
+                .filter(|&(_, w)| w > 0.)
+                .collect(),
+        };
 
-        // Make sure that output directory exists. Create it if not.
-        DirBuilder::new().recursive(true).create(&path).unwrap_or_else(|e| {
-            panic!("Failed to create image output directory: `{}/`\nError: {}", PICTURE_DIR, e)
-        });
+        let mut pixels = Vec::with_capacity(self.xres * self.yres);
+        for py in 0..self.yres as i64 {
+            for px in 0..self.xres as i64 {
+                // Colors are weighted by their alpha too, so transparent samples don't
+                // darken the pixel
+                let (mut sum, mut covered, mut total) = ([0.; 4], 0., 0.);
+                for &(dy, wy) in &weights {
+                    let y = py * ss as i64 + dy;
+                    if y < 0 || y >= height {
+                        continue;
+                    }
+                    for &(dx, wx) in &weights {
+                        let x = px * ss as i64 + dx;
+                        if x < 0 || x >= width {
+                            continue;
+                        }
+                        let (c, _) = samples[(y * width + x) as usize];
+                        let w = wx * wy;
+                        let cw = w * f64::from(c.alpha);
+                        sum[0] += f64::from(c.red) * cw;
+                        sum[1] += f64::from(c.green) * cw;
+                        sum[2] += f64::from(c.blue) * cw;
+                        sum[3] += f64::from(c.alpha) * w;
+                        covered += cw;
+                        total += w;
+                    }
+                }
+                let channel = |i: usize| if covered > 0. { (sum[i] / covered) as f32 } else { 0. };
+                let z = (0..ss)
+                    .flat_map(|dy| (0..ss).map(move |dx| (dx, dy)))
+                    .map(|(dx, dy)| self[py as usize * ss + dy][px as usize * ss + dx].1)
+                    .fold(f64::NEG_INFINITY, f64::max);
+                let alpha = (sum[3] / total) as f32;
+                pixels.push((HdrColor { red: channel(0), green: channel(1), blue: channel(2), alpha }, z));
+            }
+        }
+        Cow::Owned(pixels)
+    }
 
-        // Add file name to path
-        path.push(file_name);
+    // Every sample with the translucent fragments in front of it blended on top,
+    // from back to front
+    fn composite(&self, clear: bool) -> Cow<'_, [Pixel]> {
+        if self.translucent.is_empty() && !clear {
+            return Cow::Borrowed(&self.pixels);
+        }
+        let mut samples = self.pixels.clone();
+        if clear {
+            for (color, z) in &mut samples {
+                if *z == f64::NEG_INFINITY {
+                    color.alpha = 0.;
+                }
+            }
+        }
+        let mut fragments: Vec<_> = self.translucent.iter().collect();
+        fragments.sort_by(|(i0, z0, _), (i1, z1, _)| i0.cmp(i1).then(z0.partial_cmp(z1).unwrap_or(Ordering::Equal)));
+        for &&(i, z, c) in &fragments {
+            let (color, depth) = &mut samples[i];
+            if z > *depth {
+                *color = c.over(color);
+            }
+        }
+        Cow::Owned(samples)
+    }
 
-        // Save a copy of the original extension before we replace it
-        let extension =
-            path.extension().map(|s| s.to_str().expect("Filename isn't valid unicode!").to_owned());
+    // Binary (P6) ppm
+    pub fn write_ppm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "P6\n{} {}\n255\n", self.xres, self.yres)?;
+        let mut line = Vec::with_capacity(self.xres * 3);
+        for row in self.resolve().chunks_exact(self.xres) {
+            line.clear();
+            for (color, _) in row {
+                line.extend_from_slice(&[color.red, color.green, color.blue]);
+            }
+            out.write_all(&line)?;
+        }
+        out.flush()
+    }
 
-        // Change ext to ".ppm" and write the image as a ppm
-        let success = path.set_extension("ppm");
-        assert!(success, "Failed to change extension of file name: {:?}", path);
-        self.write_ppm(&path)?;
+    // Binary (P5) pgm of the z-buffer. Closer pixels are brighter and
+    // pixels that were never drawn to are black
+    pub fn write_pgm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let pixels = self.resolve();
+        let (far, near) = pixels
+            .iter()
+            .map(|&(_, z)| z)
+            .filter(|z| z.is_finite())
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(far, near), z| (far.min(z), near.max(z)));
+        let range = near - far;
 
-        // If the file originally had an extension, use imagemagick to convert it
-        // then remove the ppm file
-        if let Some(extension) = extension {
-            let ppm = path.as_path().to_owned();
-            path.set_extension(extension);
+        write!(out, "P5\n{} {}\n255\n", self.xres, self.yres)?;
+        let mut line = Vec::with_capacity(self.xres);
+        for row in pixels.chunks_exact(self.xres) {
+            line.clear();
+            for &(_, z) in row {
+                line.push(match z {
+                    z if !z.is_finite() => 0,
+                    _ if range <= 0. => 255,
+                    z => 1 + ((z - far) / range * 254.).round() as u8,
+                });
+            }
+            out.write_all(&line)?;
+        }
+        out.flush()
+    }
 
-            // convert the file
-            if let Ok(mut proc) = Command::new("convert").arg(&ppm).arg(path).spawn() {
-                proc.wait().unwrap();
-            } else {
-                eprintln!(
-                    "Error running the `convert` command! \
-                     Is Image Magick installed?"
-                );
+    // RGBA pam. Pixels that were never drawn to are transparent
+    pub fn write_pam<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let pixels = self.tone_map(&self.resolve_over(true));
+        write!(
+            out,
+            "P7\nWIDTH {}\nHEIGHT {}\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n",
+            self.xres, self.yres
+        )?;
+        let mut line = Vec::with_capacity(self.xres * 4);
+        for row in pixels.chunks_exact(self.xres) {
+            line.clear();
+            for (color, _) in row {
+                line.extend_from_slice(&[color.red, color.green, color.blue, color.alpha]);
             }
-            // remove the ppm file
-            if let Err(error) = fs::remove_file(&ppm) {
-                eprint!("Error removing \"{:?}\": {}", ppm, error);
+            out.write_all(&line)?;
+        }
+        out.flush()
+    }
+
+    // RGBA if any of the pixels aren't opaque, which needs a transparent screen color
+    pub fn write_png<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let pixels = self.resolve();
+        let color_type = if pixels.iter().all(|(c, _)| c.alpha == 255) {
+            png::ColorType::Rgb
+        } else {
+            png::ColorType::Rgba
+        };
+        let mut data = Vec::with_capacity(pixels.len() * color_type.channels());
+        for (color, _) in pixels.iter() {
+            data.extend_from_slice(&[color.red, color.green, color.blue, color.alpha][..color_type.channels()]);
+        }
+        png::encode(out, self.xres, self.yres, color_type, &data)
+    }
+
+    // Radiance rgbe, uncompressed. Colors are linear and aren't tone mapped
+    pub fn write_hdr<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", self.yres, self.xres)?;
+        let mut line = Vec::with_capacity(self.xres * 4);
+        for row in self.resolve_hdr().chunks_exact(self.xres) {
+            line.clear();
+            for (c, _) in row {
+                line.extend_from_slice(&rgbe(c));
             }
+            out.write_all(&line)?;
         }
+        out.flush()
+    }
 
+    // Color pfm, linear like `write_hdr`. Rows go from the bottom up
+    pub fn write_pfm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        // A negative scale means little endian
+        write!(out, "PF\n{} {}\n-1.0\n", self.xres, self.yres)?;
+        let mut line = Vec::with_capacity(self.xres * 12);
+        for row in self.resolve_hdr().chunks_exact(self.xres).rev() {
+            line.clear();
+            for (c, _) in row {
+                for v in &[c.red, c.green, c.blue] {
+                    line.extend_from_slice(&v.to_le_bytes());
+                }
+            }
+            out.write_all(&line)?;
+        }
+        out.flush()
+    }
+
+    pub fn write(&self, f: &[&str]) -> io::Result<()> {
+        let mut path = picture_path(f)?;
+
+        // Pick the image format from the extension. Files without one are saved as a ppm
+        let extension = path.extension().map(|s| s.to_string_lossy().to_lowercase());
+        let extension = extension.unwrap_or_else(|| {
+            path.set_extension("ppm");
+            String::from("ppm")
+        });
+        let write = match extension.as_str() {
+            "ppm" => Screen::write_ppm,
+            "pgm" => Screen::write_pgm,
+            "pam" => Screen::write_pam,
+            "png" => Screen::write_png,
+            "hdr" => Screen::write_hdr,
+            "pfm" => Screen::write_pfm,
+            extension => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Unsupported image format `.{}`: {:?}", extension, path),
+                ))
+            },
+        };
+        write(self, &mut BufWriter::new(File::create(&path)?))
+    }
+
+    // Pipes the image to `command` as a binary ppm
+    fn pipe_ppm(&self, command: &mut Command) -> io::Result<()> {
+        let mut proc = command.stdin(Stdio::piped()).spawn()?;
+        // Taken so that `command` sees the end of the image before it's waited on
+        let stdin = proc.stdin.take().expect("stdin is piped");
+        self.write_ppm(&mut BufWriter::new(stdin))?;
+        proc.wait()?;
         Ok(())
     }
 
-    pub fn display(&self) {
-        if let Ok(mut proc) = Command::new("display").stdin(Stdio::piped()).spawn() {
-            #[rustfmt::skip]
-            proc.stdin
-                .as_mut()
-                .unwrap()
-                .write_all(self.to_string().as_bytes())
-                .unwrap();
-            proc.wait().unwrap();
-        } else {
+    // Shows the image with `display`, or saves it if that can't be run
+    pub fn display(&self) -> io::Result<()> {
+        if self.pipe_ppm(&mut Command::new("display")).is_err() {
             eprintln!("Error running `display` command! Saving file instead.");
             let name = "pic.png";
-            self.write(&[name]).unwrap();
+            self.write(&[name])?;
             eprintln!("Saved to `{}/{}`", PICTURE_DIR, name);
         }
+        Ok(())
     }
 
     pub fn clear(&mut self) {
@@ -135,26 +390,28 @@ impl Screen {
     }
 
     pub fn fill(&mut self, c: Color) {
+        self.translucent.clear();
         for row in self.rows_mut() {
             for (color, z) in row {
-                color.color(c);
+                *color = c.into();
                 *z = f64::NEG_INFINITY;
             }
         }
     }
 
-    pub fn plot(&mut self, px: i32, py: i32, z: f64, c: Color) {
+    // Coordinates are in samples, which are pixels unless the screen is supersampled
+    pub fn plot(&mut self, px: i32, py: i32, z: f64, c: HdrColor) {
         // Can't plot points outside the screen
-        if px < 0 || px >= (self.xres as i32) || py < 0 || py >= (self.yres as i32) {
+        if px < 0 || px >= (self.width() as i32) || py < 0 || py >= (self.height() as i32) {
             return;
         }
         // Cast the coordinates to `usize` and
         // make (0, 0) the bottom left corner instead of the top left corner
-        let (px, py) = (px as usize, self.yres - 1 - (py as usize));
+        let (px, py) = (px as usize, self.height() - 1 - (py as usize));
         // Get the pixel and change its color and zbuffer values
         let (color, zbuffer) = &mut self[py][px];
         if z > *zbuffer {
-            color.color(c);
+            *color = c;
             *zbuffer = z;
         }
     }
@@ -173,6 +430,7 @@ impl Screen {
             return;
         }
 
+        let c = HdrColor::from(c);
         let (mut x, mut y, mut z) = (x0, y0, z0);
         let a = 2 * (y1 - y0);
         let b = -2 * (x1 - x0);
@@ -252,95 +510,954 @@ impl Screen {
         self.plot(x1, y1, z1, c);
     }
 
+    // Line `style.width` pixels wide from `p0` to `p1`. Coordinates are in samples,
+    // like `plot`, and the pixel (x, y) covers x..x + 1 and y..y + 1
+    pub fn draw_styled_line(&mut self, p0: [f64; 3], p1: [f64; 3], c: Color, style: &LineStyle) {
+        let width = style.width * self.supersample as f64;
+        if width > 1. {
+            self.draw_thick_line(p0, p1, c, width, style.cap, style.smooth);
+        } else if style.smooth {
+            self.draw_smooth_line(p0, p1, c);
+        } else {
+            self.draw_line((p0[0] as i32, p0[1] as i32, p0[2]), (p1[0] as i32, p1[1] as i32, p1[2]), c);
+        }
+    }
+
+    // Xiaolin Wu's line algorithm. Every step along the line covers the 2 pixels closest
+    // to it, in proportion to how close they are
+    fn draw_smooth_line(&mut self, p0: [f64; 3], p1: [f64; 3], c: Color) {
+        // Walk along x, swapping x and y for lines that are taller than they are wide.
+        // Pixel centers are moved to whole numbers
+        let steep = (p1[1] - p0[1]).abs() > (p1[0] - p0[0]).abs();
+        let swap = |p: [f64; 3]| {
+            let (x, y) = (p[0] - 0.5, p[1] - 0.5);
+            if steep {
+                [y, x, p[2]]
+            } else {
+                [x, y, p[2]]
+            }
+        };
+        let (mut a, mut b) = (swap(p0), swap(p1));
+        if a[0] > b[0] {
+            std::mem::swap(&mut a, &mut b);
+        }
+        let dx = b[0] - a[0];
+        let (gradient, dz) = if dx > 0. { ((b[1] - a[1]) / dx, (b[2] - a[2]) / dx) } else { (0., 0.) };
+        let plot = |screen: &mut Screen, x: i32, y: f64, coverage: f64| {
+            let z = a[2] + dz * (f64::from(x) - a[0]);
+            let (low, t) = (y.floor(), y - y.floor());
+            for (y, weight) in [(low as i32, 1. - t), (low as i32 + 1, t)] {
+                let (px, py) = if steep { (y, x) } else { (x, y) };
+                screen.blend(px, py, z, c, weight * coverage);
+            }
+        };
+
+        // The end pixels are only covered by the part of the line past their centers
+        let (x0, x1) = (a[0].round(), b[0].round());
+        if x0 == x1 {
+            plot(self, x0 as i32, a[1] + gradient * (x0 - a[0]), 1.);
+            return;
+        }
+        plot(self, x0 as i32, a[1] + gradient * (x0 - a[0]), 0.5 - (a[0] - x0));
+        plot(self, x1 as i32, a[1] + gradient * (x1 - a[0]), 0.5 + (b[0] - x1));
+        for x in x0 as i32 + 1..x1 as i32 {
+            plot(self, x, a[1] + gradient * (f64::from(x) - a[0]), 1.);
+        }
+    }
+
+    // Lines wider than a pixel are filled in around the segment from `p0` to `p1`.
+    // Butt caps end flat at `p0` and `p1`, and round caps add a half circle to each end
+    #[allow(clippy::too_many_arguments)]
+    fn draw_thick_line(&mut self, p0: [f64; 3], p1: [f64; 3], c: Color, width: f64, cap: LineCap, smooth: bool) {
+        let radius = width / 2.;
+        let (dx, dy) = (p1[0] - p0[0], p1[1] - p0[1]);
+        let length = dx.hypot(dy);
+        // Pixels that might be touched, with a pixel to spare for the smooth edges
+        let reach = radius + 1.;
+        let clamp = |v: f64, max: usize| (v.max(-1.) as i32).min(max as i32);
+        let (left, right) = (clamp(p0[0].min(p1[0]) - reach, self.width()), clamp(p0[0].max(p1[0]) + reach, self.width()));
+        let (bottom, top) = (clamp(p0[1].min(p1[1]) - reach, self.height()), clamp(p0[1].max(p1[1]) + reach, self.height()));
+
+        for py in bottom..=top {
+            for px in left..=right {
+                let (x, y) = (f64::from(px) + 0.5 - p0[0], f64::from(py) + 0.5 - p0[1]);
+                // Distance along the line and away from it
+                let (along, across) = if length > 0. {
+                    ((x * dx + y * dy) / length, (x * dy - y * dx).abs() / length)
+                } else {
+                    (0., x.hypot(y))
+                };
+                // How far outside of the line the pixel's center is
+                let outside = match cap {
+                    LineCap::Butt if length > 0. => (across - radius).max(-along).max(along - length),
+                    LineCap::Butt => continue,
+                    LineCap::Round => {
+                        let past = (-along).max(along - length).max(0.);
+                        past.hypot(across) - radius
+                    },
+                };
+                let coverage = if smooth {
+                    (0.5 - outside).min(1.)
+                } else if outside <= 0. {
+                    1.
+                } else {
+                    0.
+                };
+                if coverage > 0. {
+                    let t = if length > 0. { (along / length).clamp(0., 1.) } else { 0. };
+                    self.blend(px, py, p0[2] + (p1[2] - p0[2]) * t, c, coverage);
+                }
+            }
+        }
+    }
+
+    // Mix `c` into what's at (px, py) if it passes the z test. Partly covered pixels
+    // only claim the z-buffer when they're at least half covered, so that what's
+    // drawn behind them later can still show through
+    fn blend(&mut self, px: i32, py: i32, z: f64, c: Color, coverage: f64) {
+        let c = HdrColor::from(c);
+        if coverage >= 1. {
+            self.plot(px, py, z, c);
+            return;
+        }
+        if coverage <= 0. || !self.visible(px, py, z) {
+            return;
+        }
+        let py = self.height() - 1 - py as usize;
+        let (color, zbuffer) = &mut self[py][px as usize];
+        *color = HdrColor { alpha: c.alpha * coverage as f32, ..c }.over(color);
+        if coverage >= 0.5 {
+            *zbuffer = z;
+        }
+    }
+
+    // Clip space position of a point that's been transformed into world space.
+    // Without a camera the view is orthographic and we clip in screen space
+    fn to_clip(&self, point: &[f64; COLS]) -> clip::Point {
+        match &self.camera {
+            Some(camera) => camera.to_clip(&camera.to_view(point), self.xres, self.yres),
+            None => *point,
+        }
+    }
+
+    // Screen space position of a point in world space, unless it's behind the camera
+    fn project(&self, point: &[f64; COLS]) -> Option<[f64; COLS]> {
+        let clip = self.to_clip(point);
+        match self.camera {
+            Some(_) if clip[3] <= 0. => None,
+            _ => Some(self.to_screen(&clip)),
+        }
+    }
+
+    // Perspective divide and viewport transform from clip space to screen space,
+    // measured in samples. With a camera, z becomes 1 / w. Unlike the distance from the
+    // eye, it changes linearly across the screen, and bigger is still closer
+    fn to_screen(&self, point: &clip::Point) -> [f64; COLS] {
+        let ss = self.supersample as f64;
+        if self.camera.is_none() {
+            return [point[0] * ss, point[1] * ss, point[2], point[3]];
+        }
+        let (half_x, half_y) = (self.width() as f64 / 2., self.height() as f64 / 2.);
+        [half_x * (1. + point[0] / point[3]), half_y * (1. + point[1] / point[3]), 1. / point[3], 1.]
+    }
+
+    // Depth `z` from the z-buffer, moved `distance` units towards the viewer
+    fn toward_viewer(&self, z: f64, distance: f64) -> f64 {
+        match self.camera {
+            // Points that end up behind the eye are in front of everything
+            Some(_) if 1. / z <= distance => f64::INFINITY,
+            Some(_) => 1. / (1. / z - distance),
+            None => z + distance,
+        }
+    }
+
+    // How many units further from the viewer a polygon that's been projected onto the
+    // screen gets per sample. Capped so that edges of polygons seen edge on don't show
+    // through everything
+    fn depth_slope(&self, polygon: &[Vertex]) -> f64 {
+        let (p0, p1, p2) = (&polygon[0].pos, &polygon[1].pos, &polygon[2].pos);
+        let a = Vector::new(p1[0] - p0[0], p1[1] - p0[1], p1[2] - p0[2]);
+        let b = Vector::new(p2[0] - p0[0], p2[1] - p0[1], p2[2] - p0[2]);
+        let n = a.cross_product(&b);
+        let slope = n.x.abs().max(n.y.abs()) / n.z.abs();
+        // With a camera z is 1 / w, which changes by about 1 / w² per unit
+        let slope = match self.camera {
+            Some(_) => slope / ((p0[2] + p1[2] + p2[2]) / 3.).powi(2),
+            None => slope,
+        };
+        slope.min(MAX_DEPTH_SLOPE)
+    }
+
+    // Sides of the screen, plus the near and far planes
+    fn clip_planes(&self) -> Vec<Plane> {
+        let mut planes = match &self.camera {
+            Some(camera) => {
+                let mut planes = Plane::sides().to_vec();
+                planes.push(Plane::near(camera.near));
+                if camera.far.is_finite() {
+                    planes.push(Plane::far(camera.far));
+                }
+                planes
+            },
+            None => vec![
+                Plane::new([1., 0., 0., 0.], 0.),
+                Plane::new([-1., 0., 0., 0.], self.xres as f64),
+                Plane::new([0., 1., 0., 0.], 0.),
+                Plane::new([0., -1., 0., 0.], self.yres as f64),
+            ],
+        };
+        // Bigger z is closer to the viewer
+        let (near, far) = self.depth_range;
+        if self.camera.is_none() && near.is_finite() {
+            planes.push(Plane::new([0., 0., -1., 0.], near));
+        }
+        if self.camera.is_none() && far.is_finite() {
+            planes.push(Plane::new([0., 0., 1., 0.], -far));
+        }
+        planes
+    }
+
+    // Until `take_casters` is called, polygons are saved instead of being drawn, so
+    // that they can cast shadows. Lines are ignored
+    pub fn collect_casters(&mut self) {
+        self.casters = Some(Matrix::default());
+    }
+
+    pub fn take_casters(&mut self) -> Option<Matrix> {
+        self.casters.take()
+    }
+
     pub fn draw_lines(&mut self, edges: &Matrix, c: Color) {
+        if self.casters.is_some() {
+            return;
+        }
+        let planes = self.clip_planes();
         // Iterate over the edge list 2 points at a time
         for edge in edges.m.chunks_exact(2) {
-            self.draw_line(
-                (edge[0][0] as i32, edge[0][1] as i32, edge[0][2]),
-                (edge[1][0] as i32, edge[1][1] as i32, edge[1][2]),
-                c,
-            );
+            self.draw_edge(&edge[0], &edge[1], c, 0., &planes);
         }
     }
 
+    // Clip and draw a line that's been transformed into world space. The line is
+    // moved `bias` units towards the viewer, so that the edges of a polygon can be drawn
+    // over it without losing the z test
+    fn draw_edge(&mut self, p0: &[f64; COLS], p1: &[f64; COLS], c: Color, bias: f64, planes: &[Plane]) {
+        let (p0, p1) = (self.to_clip(p0), self.to_clip(p1));
+        if let Some((p0, p1)) = clip::clip_line(p0, p1, planes) {
+            let (p0, p1) = (self.to_screen(&p0), self.to_screen(&p1));
+            let (z0, z1) = (self.toward_viewer(p0[2], bias), self.toward_viewer(p1[2], bias));
+            let style = self.line_style;
+            self.draw_styled_line([p0[0], p0[1], z0], [p1[0], p1[1], z1], c, &style);
+        }
+    }
+
+    // `uvs` has the texture coordinates of every point in `polygons`
     pub fn draw_polygons(
         &mut self,
         polygons: &Matrix,
+        uvs: Option<&[[f64; 2]]>,
         reflect: Option<&Reflection>,
         lights: &[Light],
     ) {
+        if let Some(casters) = &mut self.casters {
+            casters.m.extend_from_slice(&polygons.m);
+            return;
+        }
+        let shading = self.shading;
+        let planes = self.clip_planes();
+        if shading == Shading::Wireframe {
+            // Draw every edge, including the ones on the far side of the object
+            let c = self.line_color;
+            for triangle in polygons.m.chunks_exact(3) {
+                for i in 0..3 {
+                    self.draw_edge(&triangle[i], &triangle[(i + 1) % 3], c, 0., &planes);
+                }
+            }
+            return;
+        }
+        let normals = match shading {
+            Shading::Gouraud | Shading::Phong => Vector::vertex_normals(&polygons.m),
+            _ => vec![],
+        };
+        let edges = shading == Shading::HiddenLine || self.overlay;
+        // Only shaded polygons are textured
+        let texture = match (shading, uvs, &self.texture) {
+            (Shading::Flat | Shading::Gouraud | Shading::Phong, Some(uvs), Some(texture))
+                if uvs.len() == polygons.m.len() =>
+            {
+                Some(Arc::clone(texture))
+            },
+            _ => None,
+        };
+        let alpha = reflect.unwrap_or(&self.reflection).alpha.clamp(0., 1.) as f32;
+        // Unit vector from a point towards the viewer
+        let view = |screen: &Screen, point: &Vector| match &screen.camera {
+            Some(camera) => camera.view_vector(point),
+            None => screen.view_vector.clone(),
+        };
+        let shade: &Shader = match shading {
+            // Hide whatever is behind the polygon, leaving only the edges in front
+            Shading::Wireframe | Shading::HiddenLine => &|screen, _, _| screen.screen_color.into(),
+            Shading::Flat => &|_, primitive, _| primitive.color,
+            Shading::Gouraud => &|_, _, f| {
+                let a = f.values();
+                HdrColor::new(a[0] as f32, a[1] as f32, a[2] as f32)
+            },
+            // The light and view vectors change across the polygon too
+            Shading::Phong => &|screen, _, f| {
+                let a = f.values();
+                let normal = Vector::new(a[0], a[1], a[2]);
+                let point = Vector::new(a[3], a[4], a[5]);
+                Shine::get_shine(screen, &normal, &point, &view(screen, &point), reflect, lights)
+            },
+        };
+        // Tint the lit color with the texture
+        let textured;
+        let shade: &Shader = match &texture {
+            Some(texture) => {
+                textured = move |screen: &Screen, primitive: &Primitive, f: &Fragment| {
+                    let uv = |a: &Attributes| [a[UV] / a[INV_W], a[UV + 1] / a[INV_W]];
+                    // Mip levels are chosen from how much the texture coordinates
+                    // change within the 2x2 quad of pixels
+                    let [q0, q1, q2] = f.quad();
+                    let (uv0, uv1, uv2) = (uv(&q0), uv(&q1), uv(&q2));
+                    let ddx = [uv1[0] - uv0[0], uv1[1] - uv0[1]];
+                    let ddy = [uv2[0] - uv0[0], uv2[1] - uv0[1]];
+                    let [u, v] = uv(&f.attr);
+                    let t = texture.sample_grad(u, v, ddx, ddy);
+                    let tint = Shine::new(f64::from(t.red) / 255., f64::from(t.green) / 255., f64::from(t.blue) / 255.);
+                    shade(screen, primitive, f) * &tint
+                };
+                &textured
+            },
+            None => shade,
+        };
+        let translucent;
+        let shade: &Shader = match shading {
+            Shading::Flat | Shading::Gouraud | Shading::Phong if alpha < 1. => {
+                translucent = move |screen: &Screen, primitive: &Primitive, f: &Fragment| HdrColor {
+                    alpha,
+                    ..shade(screen, primitive, f)
+                };
+                &translucent
+            },
+            _ => shade,
+        };
+
+        let mut primitives = vec![];
         // Iterate over the edge list 3 points at a time
-        for edge in polygons.m.chunks_exact(3) {
-            // Get normal vector for backface culling
-            let normal = Vector::calculate_normal(edge);
+        for (i, triangle) in polygons.m.chunks_exact(3).enumerate() {
+            let vertices: Vec<_> = triangle
+                .iter()
+                .enumerate()
+                .map(|(j, point)| {
+                    let normal = normals.get(3 * i + j);
+                    let world = Vector::from(&point[..3]);
+                    let mut attr = match (shading, normal) {
+                        (Shading::Gouraud, Some(normal)) => {
+                            let c = Shine::get_shine(self, normal, &world, &view(self, &world), reflect, lights);
+                            [c.red.into(), c.green.into(), c.blue.into(), 0., 0., 0., 0., 0., 0.]
+                        },
+                        (Shading::Phong, Some(normal)) => {
+                            [normal.x, normal.y, normal.z, world.x, world.y, world.z, 0., 0., 0.]
+                        },
+                        _ => [0.; ATTRS],
+                    };
+                    if let (Some(_), Some(uvs)) = (&texture, uvs) {
+                        attr[UV..INV_W].copy_from_slice(&uvs[3 * i + j]);
+                    }
+                    attr[INV_W] = 1.;
+                    Vertex { pos: self.to_clip(point), attr }
+                })
+                .collect();
+            let polygon = match self.project_polygon(&vertices, &planes) {
+                Some(polygon) => polygon,
+                None => continue,
+            };
+
+            // Lighting is done in world space
+            let color = match shading {
+                Shading::Flat => {
+                    let center = Vector::new(
+                        (triangle[0][0] + triangle[1][0] + triangle[2][0]) / 3.,
+                        (triangle[0][1] + triangle[1][1] + triangle[2][1]) / 3.,
+                        (triangle[0][2] + triangle[1][2] + triangle[2][2]) / 3.,
+                    );
+                    let normal = Vector::calculate_normal(triangle);
+                    Shine::get_shine(self, &normal, &center, &view(self, &center), reflect, lights)
+                },
+                _ => HdrColor::default(),
+            };
+            Primitive::fan(&polygon, color, &mut primitives);
 
-            if normal.z > 0.0 {
-                let c = Shine::get_shine(&self, &normal, reflect, lights);
-                self.scanline_convert(edge, c);
+            // The edges go on top of each polygon as soon as it's drawn, so the
+            // polygons have to be drawn one at a time
+            if edges {
+                self.fill_primitives(&primitives, shade, 1);
+                primitives.clear();
+                let c = self.line_color;
+                let bias = EDGE_BIAS * (1. + self.depth_slope(&polygon));
+                for i in 0..3 {
+                    self.draw_edge(&triangle[i], &triangle[(i + 1) % 3], c, bias, &planes);
+                }
             }
         }
+        self.fill_primitives(&primitives, shade, self.threads);
     }
 
-    fn scanline_convert(&mut self, triangle: &[[f64; COLS]], c: Color) {
-        assert_eq!(3, triangle.len(), "Triangles must have 3 points!");
-        // order the 3 points from lowest to highest y value
-        let (mut bot, mid, mut top);
-        if triangle[0][1] < triangle[1][1] {
-            bot = triangle[0];
-            top = triangle[1];
+    // Only fill the z-buffer with the polygons, in the screen color. Every polygon is
+    // pushed away from the viewer by `slope_bias` times how much its z changes per pixel
+    pub fn draw_depth(&mut self, polygons: &Matrix, slope_bias: f64) {
+        let planes = self.clip_planes();
+        let mut primitives = vec![];
+        for triangle in polygons.m.chunks_exact(3) {
+            let vertices: Vec<_> =
+                triangle.iter().map(|p| Vertex { pos: self.to_clip(p), attr: [0.; ATTRS] }).collect();
+            if let Some(mut polygon) = self.project_polygon(&vertices, &planes) {
+                let offset = slope_bias * self.depth_slope(&polygon);
+                for v in &mut polygon {
+                    v.pos[2] = self.toward_viewer(v.pos[2], -offset);
+                }
+                Primitive::fan(&polygon, self.screen_color.into(), &mut primitives);
+            }
+        }
+        self.fill_primitives(&primitives, &|_, primitive, _| primitive.color, self.threads);
+    }
+
+    // Clip a polygon and move it to screen space. Returns None if it's off the screen
+    // or facing away from the viewer
+    fn project_polygon(&self, vertices: &[Vertex], planes: &[Plane]) -> Option<Vec<Vertex>> {
+        let polygon: Vec<_> = clip::clip_polygon(vertices, planes)
+            .iter()
+            .map(|v| {
+                // Attributes divided by w interpolate linearly on the screen.
+                // Dividing by the interpolated 1 / w undoes it for each pixel
+                let attr = v.attr.map(|a| a / v.pos[3]);
+                Vertex { pos: self.to_screen(&v.pos), attr }
+            })
+            .collect();
+        if polygon.len() < 3 {
+            return None;
+        }
+
+        // Backface culling: skip polygons that are clockwise on the screen
+        let area: f64 = polygon
+            .iter()
+            .zip(polygon.iter().cycle().skip(1))
+            .map(|(v0, v1)| v0.pos[0] * v1.pos[1] - v1.pos[0] * v0.pos[1])
+            .sum();
+        if area <= 0.0 {
+            None
         } else {
-            bot = triangle[1];
-            top = triangle[0];
-        }
-        if triangle[2][1] > top[1] {
-            mid = top;
-            top = triangle[2]
-        } else if triangle[2][1] < bot[1] {
-            mid = bot;
-            bot = triangle[2]
+            Some(polygon)
+        }
+    }
+
+    // Draw triangles in order. The screen is split into tiles, and each triangle is
+    // binned into the tiles it covers so that the tiles can be drawn on separate
+    // threads. Every sample still sees its triangles in the same order, so the result
+    // doesn't depend on how many threads there are
+    fn fill_primitives(&mut self, primitives: &[Primitive], shade: &Shader, threads: usize) {
+        let (width, height) = (self.width(), self.height());
+        if primitives.is_empty() || self.pixels.is_empty() {
+            return;
+        }
+        // Tiles are whole pixels, so multisampling shades each pixel in one tile
+        let size = if threads > 1 { TILE_SIZE * self.supersample } else { width.max(height) };
+        let columns = width.div_ceil(size);
+        // The shaders only need the rest of the screen
+        let mut pixels = mem::take(&mut self.pixels);
+        let mut tiles = vec![];
+        for (i, rows) in pixels.chunks_mut(size * width).enumerate() {
+            let mut band: Vec<_> = (0..columns)
+                .map(|j| Tile { left: j * size, top: i * size, width, height, rows: vec![], triangles: vec![], translucent: vec![] })
+                .collect();
+            for row in rows.chunks_mut(width) {
+                for (tile, pixels) in band.iter_mut().zip(row.chunks_mut(size)) {
+                    tile.rows.push(pixels);
+                }
+            }
+            tiles.extend(band);
+        }
+        for (i, primitive) in primitives.iter().enumerate() {
+            let bound = |axis: usize, f: fn(i64, i64) -> i64| {
+                let v = primitive.triangle.iter().map(|v| to_fixed(v.pos[axis])).reduce(f).unwrap();
+                v >> SUBPIXEL_BITS
+            };
+            let (left, right) = (bound(0, i64::min).max(0), bound(0, i64::max).min(width as i64 - 1));
+            let (bottom, top) = (bound(1, i64::min).max(0), bound(1, i64::max).min(height as i64 - 1));
+            if left > right || bottom > top {
+                continue;
+            }
+            // Rows count down from the top of the screen
+            let (first, last) = ((height - 1 - top as usize) / size, (height - 1 - bottom as usize) / size);
+            for row in first..=last {
+                for tile in &mut tiles[row * columns + left as usize / size..=row * columns + right as usize / size] {
+                    tile.triangles.push(i);
+                }
+            }
+        }
+
+        let screen = &*self;
+        let workers = threads.min(tiles.len());
+        let tiles = if workers > 1 {
+            let tiles: Vec<_> = tiles.into_iter().map(Mutex::new).collect();
+            let next = AtomicUsize::new(0);
+            thread::scope(|scope| {
+                for _ in 0..workers {
+                    scope.spawn(|| {
+                        while let Some(tile) = tiles.get(next.fetch_add(1, atomic::Ordering::Relaxed)) {
+                            tile.lock().unwrap().draw(screen, primitives, shade);
+                        }
+                    });
+                }
+            });
+            tiles.into_iter().map(|tile| tile.into_inner().unwrap()).collect()
+        } else {
+            for tile in &mut tiles {
+                tile.draw(screen, primitives, shade);
+            }
+            tiles
+        };
+        let translucent: Vec<_> = tiles.into_iter().flat_map(|tile| tile.translucent).collect();
+        self.pixels = pixels;
+        self.translucent.extend(translucent);
+    }
+
+    // Value of the z-buffer at (px, py), if it's on the screen
+    fn depth(&self, px: i32, py: i32) -> Option<f64> {
+        if px < 0 || px >= (self.width() as i32) || py < 0 || py >= (self.height() as i32) {
+            return None;
+        }
+        Some(self[self.height() - 1 - py as usize][px as usize].1)
+    }
+
+    // Whether a point at depth `z` would be drawn over what's already at (px, py)
+    fn visible(&self, px: i32, py: i32, z: f64) -> bool {
+        if px < 0 || px >= (self.width() as i32) || py < 0 || py >= (self.height() as i32) {
+            return false;
+        }
+        let (px, py) = (px as usize, self.height() - 1 - (py as usize));
+        z > self[py][px].1
+    }
+}
+
+
+// A point on its way to the screen, along with the values interpolated across the
+// triangle: the vertex color for gouraud shading, or the vertex normal followed by
+// the world space position for phong shading. Then the texture coordinates and 1,
+// which are all divided by w once the point is on the screen
+#[derive(Debug, Clone, Copy)]
+struct Vertex {
+    pos: [f64; COLS],
+    attr: Attributes,
+}
+
+impl clip::Clip for Vertex {
+    fn position(&self) -> &clip::Point {
+        &self.pos
+    }
+
+    fn lerp(&self, other: &Vertex, t: f64) -> Vertex {
+        let mut attr = [0.; ATTRS];
+        for (i, attr) in attr.iter_mut().enumerate() {
+            *attr = self.attr[i] + (other.attr[i] - self.attr[i]) * t;
+        }
+        Vertex { pos: self.pos.lerp(&other.pos, t), attr }
+    }
+}
+
+// A triangle ready to be rasterized, in screen space. `color` is the lit color of
+// the polygon it came from for flat shading
+struct Primitive {
+    triangle: [Vertex; 3],
+    color: HdrColor,
+}
+
+impl Primitive {
+    // Clipping can turn a triangle into a convex polygon. It's drawn as a fan
+    fn fan(polygon: &[Vertex], color: HdrColor, primitives: &mut Vec<Primitive>) {
+        for i in 1..polygon.len() - 1 {
+            primitives.push(Primitive { triangle: [polygon[0], polygon[i], polygon[i + 1]], color });
+        }
+    }
+}
+
+// Shades a fragment of a primitive. Shared by every thread drawing the primitives
+type Shader<'a> = dyn Fn(&Screen, &Primitive, &Fragment) -> HdrColor + Sync + 'a;
+
+// A rectangle of samples, starting `left` samples from the left of the screen and `top`
+// rows down from the top, and the triangles that cover it
+struct Tile<'a> {
+    left: usize,
+    top: usize,
+    // Size of the screen in samples
+    width: usize,
+    height: usize,
+    // The tile's part of each of its rows, from the top
+    rows: Vec<&'a mut [Pixel]>,
+    // Indices of the primitives that cover the tile, in the order they're drawn
+    triangles: Vec<usize>,
+    // Translucent fragments for the screen, with indices into the whole screen
+    translucent: Vec<(usize, f64, HdrColor)>,
+}
+
+impl Tile<'_> {
+    fn draw(&mut self, screen: &Screen, primitives: &[Primitive], shade: &Shader) {
+        for i in mem::take(&mut self.triangles) {
+            let primitive = &primitives[i];
+            self.rasterize(screen, &primitive.triangle, &|screen, f| shade(screen, primitive, f));
+        }
+    }
+
+    // Half-space rasterizer. A sample is drawn when its center is inside all three
+    // edges. Centers right on an edge only count for top and left edges, so triangles
+    // that share an edge never both draw (or both miss) a sample along it
+    fn rasterize(
+        &mut self,
+        screen: &Screen,
+        triangle: &[Vertex; 3],
+        shade: &dyn Fn(&Screen, &Fragment) -> HdrColor,
+    ) {
+        let fixed = |v: &Vertex| [to_fixed(v.pos[0]), to_fixed(v.pos[1])];
+        let p = [fixed(&triangle[0]), fixed(&triangle[1]), fixed(&triangle[2])];
+        // Twice the area, which is positive since backfaces were culled. Triangles that
+        // snapped to a line cover nothing
+        let area = edge_function(&p[0], &p[1], &p[2]);
+        if area <= 0 {
+            return;
+        }
+
+        // Bounding box in samples, clipped to the tile
+        let bound = |i: usize, f: fn(i64, i64) -> i64| {
+            let v = p.iter().map(|q| q[i]).reduce(f).unwrap();
+            (v >> SUBPIXEL_BITS) as i32
+        };
+        let (left, right) = (self.left as i32, (self.left + self.columns()) as i32 - 1);
+        let (bottom, top) = ((self.height - self.top - self.rows.len()) as i32, (self.height - 1 - self.top) as i32);
+        let (x0, x1) = (bound(0, i64::min).max(left), bound(0, i64::max).min(right));
+        let (y0, y1) = (bound(1, i64::min).max(bottom), bound(1, i64::max).min(top));
+        if x0 > x1 || y0 > y1 {
+            return;
+        }
+
+        // Edge i is the one across from vertex i, so its value is vertex i's weight
+        let edges = [(&p[1], &p[2]), (&p[2], &p[0]), (&p[0], &p[1])];
+        let bias = edges.map(|(a, b)| if is_top_left(a, b) { 0 } else { -1 });
+        let step_x = edges.map(|(a, b)| -(b[1] - a[1]) << SUBPIXEL_BITS);
+        let step_y = edges.map(|(a, b)| (b[0] - a[0]) << SUBPIXEL_BITS);
+
+        let (ddx, ddy) = gradients(triangle);
+        let mut cache = if screen.multisample && screen.supersample > 1 {
+            Some(ShadeCache::new(triangle, screen.supersample as i32))
         } else {
-            mid = triangle[2]
-        }
-
-        // make vars immutable
-        let (bot, top) = (bot, top);
-
-        // Given a ▲BMT where b.y <= m.y <= t.y:
-        // for y in b.y..t.y, draw a line from (x0, y) to (x1, y), where
-        // x0 := the point along line BT with a y value of `y`
-        // x1 := the point along either line BM or MT with a y value of `y`
-        let (mut x0, mut x1, mut z0, mut z1) = (bot[0], bot[0], bot[2], bot[2]);
-        let delta_x0 = (top[0] - bot[0]) / (top[1] - bot[1]);
-        let delta_z0 = (top[2] - bot[2]) / (top[1] - bot[1]);
-        // Case where bottom and middle don't have the same y value
-        // check that bot[1] != mid[1]
-        if (bot[1] - mid[1]).abs() > f64::EPSILON {
-            let delta_x1 = (mid[0] - bot[0]) / (mid[1] - bot[1]);
-            let delta_z1 = (mid[2] - bot[2]) / (mid[1] - bot[1]);
-            for y in (bot[1] as i32)..(mid[1] as i32) {
-                self.draw_line((x0 as i32, y, z0), (x1 as i32, y, z1), c);
-                x0 += delta_x0;
-                x1 += delta_x1;
-                z0 += delta_z0;
-                z1 += delta_z1;
-            }
-        }
-        x1 = mid[0];
-        z1 = mid[2];
-        // Case where middle and top don't have the same y value
-        // check that mid[1] != top[1]
-        if (mid[1] - top[1]).abs() > f64::EPSILON {
-            let delta_x1 = (top[0] - mid[0]) / (top[1] - mid[1]);
-            let delta_z1 = (top[2] - mid[2]) / (top[1] - mid[1]);
-            for y in (mid[1] as i32)..(top[1] as i32) {
-                self.draw_line((x0 as i32, y, z0), (x1 as i32, y, z1), c);
-                x0 += delta_x0;
-                x1 += delta_x1;
-                z0 += delta_z0;
-                z1 += delta_z1;
+            None
+        };
+
+        let center = |s: i32| (i64::from(s) << SUBPIXEL_BITS) + (1 << (SUBPIXEL_BITS - 1));
+        let mut row = edges.map(|(a, b)| edge_function(a, b, &[center(x0), center(y0)]));
+        for y in y0..=y1 {
+            let mut w = row;
+            for x in x0..=x1 {
+                if (0..3).all(|i| w[i] + bias[i] >= 0) {
+                    let weights = w.map(|w| w as f64 / area as f64);
+                    let z = (0..3).map(|i| weights[i] * triangle[i].pos[2]).sum();
+                    if self.visible(x, y, z) {
+                        let mut attr = [0.; ATTRS];
+                        for (i, a) in attr.iter_mut().enumerate() {
+                            *a = (0..3).map(|v| weights[v] * triangle[v].attr[i]).sum();
+                        }
+                        let c = match &mut cache {
+                            Some(cache) => cache.shade(screen, x, y, attr, (&ddx, &ddy), shade),
+                            None => shade(screen, &Fragment { x, y, attr, ddx, ddy }),
+                        };
+                        self.draw_fragment(x, y, z, c);
+                    }
+                }
+                for i in 0..3 {
+                    w[i] += step_x[i];
+                }
+            }
+            for i in 0..3 {
+                row[i] += step_y[i];
             }
         }
     }
+
+    fn columns(&self) -> usize {
+        self.rows.first().map_or(0, |row| row.len())
+    }
+
+    // Row and column of the sample at (px, py) in the tile, if it's in the tile
+    fn local(&self, px: i32, py: i32) -> Option<(usize, usize)> {
+        if px < 0 || px >= (self.width as i32) || py < 0 || py >= (self.height as i32) {
+            return None;
+        }
+        let (row, col) = (self.height - 1 - py as usize, px as usize);
+        if row < self.top || row >= self.top + self.rows.len() || col < self.left || col >= self.left + self.columns() {
+            return None;
+        }
+        Some((row - self.top, col - self.left))
+    }
+
+    // Whether a point at depth `z` would be drawn over what's already at (px, py)
+    fn visible(&self, px: i32, py: i32, z: f64) -> bool {
+        match self.local(px, py) {
+            Some((row, col)) => z > self.rows[row][col].1,
+            None => false,
+        }
+    }
+
+    // Opaque fragments are plotted right away. Translucent ones are saved to be blended
+    // over the samples that are still behind them once everything has been drawn
+    fn draw_fragment(&mut self, px: i32, py: i32, z: f64, c: HdrColor) {
+        if !self.visible(px, py, z) {
+            return;
+        }
+        let (row, col) = self.local(px, py).unwrap();
+        if c.alpha >= 1. {
+            self.rows[row][col] = (c, z);
+        } else if c.alpha > 0. {
+            let index = (self.top + row) * self.width + self.left + col;
+            self.translucent.push((index, z, c));
+        }
+    }
+}
+
+// Converts a screen coordinate to fixed point with `SUBPIXEL_BITS` fractional bits
+fn to_fixed(v: f64) -> i64 {
+    (v * f64::from(1 << SUBPIXEL_BITS)).round() as i64
+}
+
+// Twice the signed area of ▲ABC: positive when C is left of the edge from A to B
+fn edge_function(a: &[i64; 2], b: &[i64; 2], c: &[i64; 2]) -> i64 {
+    (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])
+}
+
+// Whether the edge from A to B is a top or left edge of a counterclockwise triangle.
+// With y going up, left edges go down, and top edges are flat and go left
+fn is_top_left(a: &[i64; 2], b: &[i64; 2]) -> bool {
+    let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+    dy < 0 || (dy == 0 && dx < 0)
+}
+
+// How much each attribute changes per pixel across the screen, in x and in y
+fn gradients(triangle: &[Vertex; 3]) -> (Attributes, Attributes) {
+    let (p0, p1, p2) = (&triangle[0].pos, &triangle[1].pos, &triangle[2].pos);
+    let (x1, y1, x2, y2) = (p1[0] - p0[0], p1[1] - p0[1], p2[0] - p0[0], p2[1] - p0[1]);
+    let det = x1 * y2 - x2 * y1;
+    let (mut ddx, mut ddy) = ([0.; ATTRS], [0.; ATTRS]);
+    if det.abs() > f64::EPSILON {
+        for i in 0..ATTRS {
+            let a0 = triangle[0].attr[i];
+            let (a1, a2) = (triangle[1].attr[i] - a0, triangle[2].attr[i] - a0);
+            ddx[i] = (a1 * y2 - a2 * y1) / det;
+            ddy[i] = (a2 * x1 - a1 * x2) / det;
+        }
+    }
+    (ddx, ddy)
+}
+
+// A pixel being shaded: its position, attributes, and their gradients
+struct Fragment {
+    x: i32,
+    y: i32,
+    attr: Attributes,
+    ddx: Attributes,
+    ddy: Attributes,
+}
+
+impl Fragment {
+    // The attributes at this pixel, undoing the divide by w
+    fn values(&self) -> Attributes {
+        self.attr.map(|a| a / self.attr[INV_W])
+    }
+
+    // Attributes of the bottom left, bottom right and top left pixels of the
+    // 2x2 quad that this pixel is in. Every pixel in a quad gets the same
+    // differences between them, like on a GPU
+    #[allow(clippy::needless_range_loop)]
+    fn quad(&self) -> [Attributes; 3] {
+        let (dx, dy) = (f64::from(self.x.rem_euclid(2)), f64::from(self.y.rem_euclid(2)));
+        let mut quad = [[0.; ATTRS]; 3];
+        for i in 0..ATTRS {
+            let corner = self.attr[i] - dx * self.ddx[i] - dy * self.ddy[i];
+            quad[0][i] = corner;
+            quad[1][i] = corner + self.ddx[i];
+            quad[2][i] = corner + self.ddy[i];
+        }
+        quad
+    }
+}
+
+// Colors of the pixels a triangle covers, for multisampling. Only the latest row
+// of pixels is kept for each column, since triangles are drawn bottom to top
+struct ShadeCache {
+    samples: i32,
+    // Pixel column of the first entry
+    left: i32,
+    colors: Vec<Option<(i32, HdrColor)>>,
+}
+
+impl ShadeCache {
+    fn new(triangle: &[Vertex; 3], samples: i32) -> ShadeCache {
+        let xs = triangle.iter().map(|v| v.pos[0]);
+        let min = xs.clone().fold(f64::INFINITY, f64::min).floor() as i32;
+        let max = xs.fold(f64::NEG_INFINITY, f64::max).ceil() as i32;
+        let left = min.div_euclid(samples);
+        let width = max.div_euclid(samples) - left + 1;
+        ShadeCache { samples, left, colors: vec![None; width as usize] }
+    }
+
+    // Color of the pixel holding the sample at (x, y), shaded at the pixel's center
+    // the first time one of its samples is drawn
+    fn shade(
+        &mut self,
+        screen: &Screen,
+        x: i32,
+        y: i32,
+        attr: Attributes,
+        (ddx, ddy): (&Attributes, &Attributes),
+        shade: &dyn Fn(&Screen, &Fragment) -> HdrColor,
+    ) -> HdrColor {
+        let n = self.samples;
+        let (px, py) = (x.div_euclid(n), y.div_euclid(n));
+        let slot = (px - self.left) as usize;
+        if let Some(Some((row, c))) = self.colors.get(slot) {
+            if *row == py {
+                return *c;
+            }
+        }
+        // Distance from the sample to the pixel's center, in samples
+        let center = |p: i32, s: i32| f64::from(p * n) + f64::from(n - 1) / 2. - f64::from(s);
+        let (dx, dy) = (center(px, x), center(py, y));
+        let mut fragment = Fragment { x: px, y: py, attr, ddx: *ddx, ddy: *ddy };
+        for i in 0..ATTRS {
+            fragment.attr[i] += dx * ddx[i] + dy * ddy[i];
+            fragment.ddx[i] *= f64::from(n);
+            fragment.ddy[i] *= f64::from(n);
+        }
+        let c = shade(screen, &fragment);
+        if let Some(entry) = self.colors.get_mut(slot) {
+            *entry = Some((py, c));
+        }
+        c
+    }
+}
+
+// How the ends of lines wider than a pixel look
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineCap {
+    // Square, right at the end points
+    Butt,
+    // Half circles around the end points
+    Round,
+}
+
+#[derive(Debug)]
+pub struct ParseLineCapError;
+
+impl FromStr for LineCap {
+    type Err = ParseLineCapError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "butt" => Ok(LineCap::Butt),
+            "round" => Ok(LineCap::Round),
+            _ => Err(ParseLineCapError),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineStyle {
+    // In pixels
+    pub width: f64,
+    pub cap: LineCap,
+    // Anti-aliased
+    pub smooth: bool,
+}
+
+impl Default for LineStyle {
+    fn default() -> Self {
+        LineStyle { width: 1., cap: LineCap::Butt, smooth: false }
+    }
+}
+
+// How supersampled screens are filtered down to their resolution
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Downfilter {
+    // Average of the samples inside each pixel
+    Box,
+    // Weighted by distance from the pixel's center, reaching halfway into the
+    // neighboring pixels. A bit softer than box
+    Tent,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shading {
+    // Only the edges of every triangle, drawn with the line color
+    Wireframe,
+    // Edges of the triangles that face the viewer and aren't behind other polygons
+    HiddenLine,
+    // One color per triangle
+    Flat,
+    // Light the vertices and interpolate their colors
+    Gouraud,
+    // Interpolate the vertex normals and light every pixel
+    Phong,
+}
+
+#[derive(Debug)]
+pub struct ParseShadingError;
+
+impl FromStr for Shading {
+    type Err = ParseShadingError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "wireframe" => Ok(Shading::Wireframe),
+            "hidden" => Ok(Shading::HiddenLine),
+            "flat" => Ok(Shading::Flat),
+            "gouraud" => Ok(Shading::Gouraud),
+            "phong" => Ok(Shading::Phong),
+            _ => Err(ParseShadingError),
+        }
+    }
+}
+
+// Shared exponent encoding used by Radiance hdr files
+fn rgbe(c: &HdrColor) -> [u8; 4] {
+    let max = c.red.max(c.green).max(c.blue);
+    if max < 1e-32 {
+        return [0; 4];
+    }
+    // max = mantissa * 2^exponent, with the mantissa in 0.5..1
+    let exponent = max.log2().floor() as i32 + 1;
+    let scale = 256. / 2f32.powi(exponent);
+    let channel = |v: f32| (v.max(0.) * scale).min(255.) as u8;
+    [channel(c.red), channel(c.green), channel(c.blue), (exponent + 128) as u8]
+}
+
+// Path of `f` inside of the picture directory. `f` is the file name preceded by its
+// parent directories, which are created if they don't exist yet
+pub fn picture_path(f: &[&str]) -> io::Result<PathBuf> {
+    let mut path = PathBuf::from(PICTURE_DIR);
+
+    // Separate f into the file name and its parent(s)
+    let (file_name, parents) = f
+        .split_last()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No file name to save the picture as!"))?;
+    for parent in parents {
+        path.push(parent);
+    }
+
+    // Make sure that output directory exists. Create it if not.
+    DirBuilder::new().recursive(true).create(&path).map_err(|e| {
+        io::Error::new(e.kind(), format!("Failed to create image output directory `{}/`: {}", path.display(), e))
+    })?;
+
+    // Add file name to path
+    path.push(file_name);
+    Ok(path)
 }
 
 impl fmt::Display for Screen {
@@ -349,11 +1466,11 @@ impl fmt::Display for Screen {
         let row = self.xres * mem::size_of_val(&(Color::new(255, 255, 255).to_string() + " ")) + 1;
         let size = header + self.yres * row;
         let mut contents = String::with_capacity(size);
-        for row in self.rows() {
+        for row in self.resolve().chunks_exact(self.xres) {
             for (color, _) in row {
                 contents.push_str(&color.to_string());
             }
-            contents.push_str("\n");
+            contents.push('\n');
         }
         write!(f, "P3 {} {} 255\n{}", self.xres, self.yres, contents)
     }
@@ -369,16 +1486,16 @@ impl Index<usize> for Screen {
     type Output = [Pixel];
 
     fn index(&self, index: usize) -> &[Pixel] {
-        let start = self.xres() * index;
-        let end = start + self.xres();
+        let start = self.width() * index;
+        let end = start + self.width();
         &self.pixels[start..end]
     }
 }
 
 impl IndexMut<usize> for Screen {
     fn index_mut(&mut self, index: usize) -> &mut [Pixel] {
-        let start = self.xres() * index;
-        let end = start + self.xres();
+        let start = self.width() * index;
+        let end = start + self.width();
         &mut self.pixels[start..end]
     }
 }
@@ -387,12 +1504,34 @@ pub struct ScreenBuilder {
     // Screen properties
     pub xres: usize,
     pub yres: usize,
+    // Anti-aliasing: draw with this many samples per pixel in each direction,
+    // then filter them down to the resolution when writing or displaying
+    pub supersample: usize,
+    pub downfilter: Downfilter,
+    // Shade each pixel once per triangle, at its center, and share the color with
+    // every sample the triangle covers. Much faster than shading every sample
+    pub multisample: bool,
+    // Lighting adds up past what the screen can show. The tone map brings it back
+    // down when the screen is written out, then the colors are encoded with the
+    // sRGB curve if `srgb` is set. Otherwise they're written as they are
+    pub tone_map: ToneMap,
+    pub srgb: bool,
+    // Threads that draw polygons. 1 draws them on the calling thread
+    pub threads: usize,
     pub screen_color: Color,
     pub line_color: Color,
+    pub line_style: LineStyle,
     pub steps_2d: usize,
     pub steps_3d: usize,
     pub specular_exp: i32,
     pub view_vector: Vector,
+    pub camera: Option<Camera>,
+    // The closest and farthest z drawn without a camera. A camera has its own near
+    // and far planes
+    pub depth_range: (f64, f64),
+    pub shading: Shading,
+    pub overlay: bool,
+    pub shadows: Option<Shadows>,
     pub ambient_light: Color,
     // Default values
     pub lights: Vec<Light>,
@@ -404,20 +1543,39 @@ impl ScreenBuilder {
         // normalize the vectors
         self.view_vector.normalize();
         for light in &mut self.lights {
-            light.pos.normalize();
+            if let LightKind::Directional = light.kind {
+                light.pos.normalize();
+            }
         }
+        assert!(self.supersample > 0, "Need at least 1 sample per pixel!");
+        let samples = self.xres * self.yres * self.supersample * self.supersample;
         Screen {
-            pixels: vec![(self.screen_color, f64::NEG_INFINITY); self.xres * self.yres],
+            pixels: vec![(self.screen_color.into(), f64::NEG_INFINITY); samples],
             xres: self.xres,
             yres: self.yres,
+            supersample: self.supersample,
+            downfilter: self.downfilter,
+            multisample: self.multisample,
+            tone_map: self.tone_map,
+            srgb: self.srgb,
+            threads: self.threads,
             screen_color: self.screen_color,
             line_color: self.line_color,
+            line_style: self.line_style,
             steps_2d: self.steps_2d,
             steps_3d: self.steps_3d,
             specular_exp: self.specular_exp,
             ambient_light: self.ambient_light,
             lights: self.lights,
             view_vector: self.view_vector,
+            camera: self.camera,
+            depth_range: self.depth_range,
+            shading: self.shading,
+            overlay: self.overlay,
+            shadows: self.shadows,
+            casters: None,
+            translucent: vec![],
+            texture: None,
             reflection: self.reflection,
         }
     }
@@ -428,14 +1586,26 @@ impl Default for ScreenBuilder {
         ScreenBuilder {
             xres: 500,
             yres: 500,
+            supersample: 1,
+            downfilter: Downfilter::Box,
+            multisample: false,
+            tone_map: ToneMap::Clamp,
+            srgb: false,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
             screen_color: consts::BLACK,
             line_color: consts::GREEN,
+            line_style: LineStyle::default(),
             steps_2d: 100,
             steps_3d: 100,
             specular_exp: 4,
             ambient_light: Color::new(50, 50, 50),
             lights: vec![Light::new(Vector::new(0.5, 0.75, 1.), consts::WHITE)],
             view_vector: Vector::new(0., 0., 1.),
+            camera: None,
+            depth_range: (f64::INFINITY, f64::NEG_INFINITY),
+            shading: Shading::Flat,
+            overlay: false,
+            shadows: None,
             reflection: Reflection::new(
                 Shine::new(0.1, 0.1, 0.1), // Ambient
                 Shine::new(0.5, 0.5, 0.5), // Diffuse
@@ -444,3 +1614,398 @@ impl Default for ScreenBuilder {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::draw;
+
+    // Draws a fragment the way polygons do
+    fn draw_fragment(screen: &mut Screen, px: i32, py: i32, z: f64, c: HdrColor) {
+        let (width, height) = (screen.width(), screen.height());
+        let rows = screen.pixels.chunks_mut(width).collect();
+        let mut tile = Tile { left: 0, top: 0, width, height, rows, triangles: vec![], translucent: vec![] };
+        tile.draw_fragment(px, py, z, c);
+        screen.translucent.append(&mut tile.translucent);
+    }
+
+    #[test]
+    fn binary_output() {
+        let mut screen = ScreenBuilder { xres: 3, yres: 2, screen_color: Color::new(0, 0, 0), ..Default::default() }.create();
+        // Top left and bottom right
+        screen.plot(0, 1, 5., Color::new(255, 128, 1).into());
+        screen.plot(2, 0, 1., Color::new(10, 20, 30).into());
+
+        let mut ppm = vec![];
+        screen.write_ppm(&mut ppm).unwrap();
+        let header = b"P6\n3 2\n255\n";
+        assert_eq!(header.len() + 3 * 2 * 3, ppm.len());
+        assert_eq!(&header[..], &ppm[..header.len()]);
+        assert_eq!(&[255, 128, 1, 0, 0, 0], &ppm[header.len()..header.len() + 6]);
+        assert_eq!(&[10, 20, 30], &ppm[ppm.len() - 3..]);
+
+        // The closest pixel is white, the farthest is just above black
+        let mut pgm = vec![];
+        screen.write_pgm(&mut pgm).unwrap();
+        let header = b"P5\n3 2\n255\n";
+        assert_eq!(header.len() + 3 * 2, pgm.len());
+        assert_eq!(&header[..], &pgm[..header.len()]);
+        assert_eq!(&[255, 0, 0, 0, 0, 1], &pgm[header.len()..]);
+
+        let mut pam = vec![];
+        screen.write_pam(&mut pam).unwrap();
+        let header = b"P7\nWIDTH 3\nHEIGHT 2\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n";
+        assert_eq!(header.len() + 3 * 2 * 4, pam.len());
+        assert_eq!(&header[..], &pam[..header.len()]);
+        assert_eq!(&[255, 128, 1, 255, 0, 0, 0, 0], &pam[header.len()..header.len() + 8]);
+
+        // `display` gets the same binary ppm
+        let path = std::env::temp_dir().join(format!("binary_output_{}.ppm", std::process::id()));
+        screen.pipe_ppm(Command::new("sh").arg("-c").arg(format!("cat > {:?}", path))).unwrap();
+        let piped = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(ppm, piped);
+    }
+
+    #[test]
+    fn depth_clipping() {
+        // A square leaning back, from z = -10 on the left to z = 10 on the right
+        let square = Matrix::from(
+            &[
+                [0., 0., -10., 1.],
+                [10., 0., 10., 1.],
+                [10., 10., 10., 1.],
+                [0., 0., -10., 1.],
+                [10., 10., 10., 1.],
+                [0., 10., -10., 1.],
+            ][..],
+        );
+        let drawn = |screen: &Screen| screen[5].iter().map(|&(_, z)| z.is_finite()).collect::<Vec<_>>();
+
+        // z is 2x - 10, so only 3 < x < 7 is between -4 and 4
+        let mut screen = ScreenBuilder { xres: 10, yres: 10, depth_range: (4., -4.), ..Default::default() }.create();
+        screen.draw_polygons(&square, None, None, &screen.lights.clone());
+        let expected = [false, false, false, true, true, true, true, false, false, false];
+        assert_eq!(&expected[..], &drawn(&screen)[..]);
+
+        // 40 units in front of the plane z = 0, the far plane cuts off everything behind it
+        let mut camera = Camera::new(Vector::new(5., 5., 40.), Vector::new(5., 5., 0.), None).unwrap();
+        camera.far = 40.;
+        let mut screen = ScreenBuilder { xres: 10, yres: 10, camera: Some(camera), ..Default::default() }.create();
+        screen.draw_polygons(&square, None, None, &screen.lights.clone());
+        let expected = [false, false, false, false, false, true, true, true, true, true];
+        assert_eq!(&expected[..], &drawn(&screen)[..]);
+        // And the near plane cuts off everything in front of it. The left edge is at 1,
+        // since the square is farther away there
+        let camera = screen.camera.as_mut().unwrap();
+        camera.near = 35.;
+        camera.far = f64::INFINITY;
+        screen.clear();
+        screen.draw_polygons(&square, None, None, &screen.lights.clone());
+        let expected = [false, true, true, true, true, true, true, true, false, false];
+        assert_eq!(&expected[..], &drawn(&screen)[..]);
+    }
+
+    #[test]
+    fn perspective_depth() {
+        // A wall 200 units away, and a floor going from 100 to 400 units away through it
+        let quad = |[a, b, c, d]: [[f64; 3]; 4]| {
+            let p = |[x, y, z]: [f64; 3]| [x, y, z, 1.];
+            Matrix::from(&[p(a), p(b), p(c), p(a), p(c), p(d)][..])
+        };
+        let wall = quad([[-300., -300., -200.], [300., -300., -200.], [300., 300., -200.], [-300., 300., -200.]]);
+        let floor = quad([[-300., -100., -100.], [300., -100., -100.], [300., 100., -400.], [-300., 100., -400.]]);
+        let glow = |emissive| {
+            let none = Shine::new(0., 0., 0.);
+            Reflection { emissive, ..Reflection::new(none.clone(), none.clone(), none) }
+        };
+        let camera = Camera::new(Vector::new(0., 0., 0.), Vector::new(0., 0., -100.), None).unwrap();
+        let mut screen = ScreenBuilder { xres: 100, yres: 100, camera: Some(camera), ..Default::default() }.create();
+        let lights = screen.lights.clone();
+        screen.draw_polygons(&wall, None, Some(&glow(consts::RED)), &lights);
+        screen.draw_polygons(&floor, None, Some(&glow(consts::BLUE)), &lights);
+
+        // They cross 33 units below the eye, which is 16.7 pixels below the center.
+        // Going by the depth at the floor's corners instead, they'd cross off the screen
+        let pixels = screen.resolve();
+        let blue = |y: usize| pixels[(99 - y) * 100 + 50].0.blue;
+        assert_eq!([255, 255, 0, 0], [blue(10), blue(32), blue(34), blue(45)]);
+    }
+
+    #[test]
+    fn perspective_attributes() {
+        // The floor from `perspective_depth`, with its world space position interpolated
+        // across it like phong shading does
+        let camera = Camera::new(Vector::new(0., 0., 0.), Vector::new(0., 0., -100.), None).unwrap();
+        let mut screen = ScreenBuilder { xres: 100, yres: 100, camera: Some(camera), ..Default::default() }.create();
+        let floor = [[-300., -100., -100., 1.], [300., -100., -100., 1.], [300., 100., -400., 1.], [-300., 100., -400., 1.]];
+        let vertices: Vec<_> = floor
+            .iter()
+            .map(|p| {
+                let mut attr = [0.; ATTRS];
+                attr[3..6].copy_from_slice(&p[..3]);
+                attr[INV_W] = 1.;
+                Vertex { pos: screen.to_clip(p), attr }
+            })
+            .collect();
+        let polygon = screen.project_polygon(&vertices, &screen.clip_planes()).unwrap();
+        let mut primitives = vec![];
+        Primitive::fan(&polygon, HdrColor::default(), &mut primitives);
+        let shade: &Shader = &|_, _, f| {
+            let a = f.values();
+            HdrColor::new(a[3] as f32, a[4] as f32, a[5] as f32)
+        };
+        screen.fill_primitives(&primitives, shade, 1);
+
+        // Every pixel gets the point on the floor that's behind its center
+        for y in [10, 40, 60] {
+            let s = f64::from(y) + 0.5 - 50.;
+            let t = (100. + s) / (200. - 3. * s);
+            let (wy, wz) = (-100. + 200. * t, -100. - 300. * t);
+            let c = screen[99 - y as usize][50].0;
+            let actual = [f64::from(c.red), f64::from(c.green), f64::from(c.blue)];
+            let expected = [0.5 * -wz / 100., wy, wz];
+            assert!(actual.iter().zip(&expected).all(|(a, e)| (a - e).abs() < 1e-3), "{:?} {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn wireframes() {
+        let (black, white) = (Color::new(0, 0, 0), Color::new(255, 255, 255));
+        let builder = || ScreenBuilder {
+            xres: 20,
+            yres: 20,
+            screen_color: black,
+            line_color: white,
+            ..Default::default()
+        };
+        let red = |screen: &Screen, x: usize, y: usize| screen.resolve()[(19 - y) * 20 + x].0.red;
+        let triangle = Matrix::from(&[[2., 2., 0., 1.], [18., 2., 0., 1.], [2., 18., 0., 1.]][..]);
+
+        // Only the edges are drawn
+        let mut screen = ScreenBuilder { shading: Shading::Wireframe, ..builder() }.create();
+        screen.draw_polygons(&triangle, None, None, &screen.lights.clone());
+        assert_eq!((255, 255), (red(&screen, 10, 2), red(&screen, 2, 10)));
+        assert_eq!(0, red(&screen, 6, 6));
+        assert!(!screen[19 - 6][6].1.is_finite());
+
+        // A square in front of the right side of the triangle hides its edges there
+        let square = Matrix::from(
+            &[
+                [8., -5., 10., 1.],
+                [30., -5., 10., 1.],
+                [30., 30., 10., 1.],
+                [8., -5., 10., 1.],
+                [30., 30., 10., 1.],
+                [8., 30., 10., 1.],
+            ][..],
+        );
+        for polygons in &[[&triangle, &square], [&square, &triangle]] {
+            let mut screen = ScreenBuilder { shading: Shading::HiddenLine, ..builder() }.create();
+            for polygons in polygons {
+                screen.draw_polygons(polygons, None, None, &screen.lights.clone());
+            }
+            assert_eq!((255, 255), (red(&screen, 4, 2), red(&screen, 2, 10)));
+            assert_eq!((0, 0), (red(&screen, 16, 2), red(&screen, 10, 10)));
+            // Along with the square's own left edge
+            assert_eq!(255, red(&screen, 8, 12));
+            assert_eq!(0, red(&screen, 5, 5));
+        }
+    }
+
+    #[test]
+    fn downfilter() {
+        let mut screen = ScreenBuilder { xres: 2, yres: 1, supersample: 2, ..Default::default() }.create();
+        assert_eq!(4 * 2, screen.pixels.len());
+        screen.fill(Color::new(0, 0, 0));
+        // A white sample in the bottom right of the left pixel
+        screen.plot(1, 0, 1., Color::new(255, 255, 255).into());
+
+        let pixels = screen.resolve();
+        assert_eq!(2, pixels.len());
+        assert_eq!((64, 1.), (pixels[0].0.red, pixels[0].1));
+        assert_eq!((0, f64::NEG_INFINITY), (pixels[1].0.red, pixels[1].1));
+
+        // The tent spreads it into the next pixel
+        screen.downfilter = Downfilter::Tent;
+        let pixels = screen.resolve();
+        assert!(pixels[0].0.red > pixels[1].0.red && pixels[1].0.red > 0);
+    }
+
+    #[test]
+    fn translucency() {
+        let (red, blue) = (HdrColor::new(1., 0., 0.), HdrColor::new(0., 0., 1.));
+        let mut screen = ScreenBuilder { xres: 2, yres: 1, ..Default::default() }.create();
+        // Half transparent blue, then opaque red in front of it on the right
+        draw_fragment(&mut screen, 0, 0, 1., HdrColor { alpha: 0.5, ..blue });
+        draw_fragment(&mut screen, 1, 0, 1., HdrColor { alpha: 0.5, ..blue });
+        draw_fragment(&mut screen, 1, 0, 2., red);
+        let pixels = screen.resolve();
+        let c = pixels[0].0;
+        assert_eq!((0, 0, 128, 255), (c.red, c.green, c.blue, c.alpha));
+        let c = pixels[1].0;
+        assert_eq!((255, 0, 0, 255), (c.red, c.green, c.blue, c.alpha));
+        // Nothing opaque was drawn on the left, so it's partly transparent in a pam
+        let pixels = screen.tone_map(&screen.resolve_over(true));
+        assert_eq!((0, 0, 255, 128), (pixels[0].0.red, pixels[0].0.green, pixels[0].0.blue, pixels[0].0.alpha));
+
+        // pngs only have alpha when they need it
+        let mut data = vec![];
+        screen.write_png(&mut data).unwrap();
+        assert_eq!(png::ColorType::Rgb, png::decode(&data).unwrap().2);
+        screen.screen_color = Color::rgba(0, 0, 0, 0);
+        screen.clear();
+        draw_fragment(&mut screen, 0, 0, 1., red);
+        data.clear();
+        screen.write_png(&mut data).unwrap();
+        let (_, _, color_type, pixels) = png::decode(&data).unwrap();
+        assert_eq!((png::ColorType::Rgba, &[255, 0, 0, 255, 0, 0, 0, 0][..]), (color_type, &pixels[..]));
+    }
+
+    #[test]
+    fn line_styles() {
+        let (black, white) = (Color::new(0, 0, 0), Color::new(255, 255, 255));
+        let mut screen = ScreenBuilder { xres: 10, yres: 10, screen_color: black, ..Default::default() }.create();
+        let red = |screen: &Screen, x: usize, y: usize| screen.resolve()[(9 - y) * 10 + x].0.red;
+
+        // Halfway between 2 rows, so both get half of the line
+        let mut style = LineStyle { smooth: true, ..Default::default() };
+        screen.draw_styled_line([1., 5., 0.], [9., 5., 0.], white, &style);
+        assert_eq!((128, 128), (red(&screen, 4, 4), red(&screen, 4, 5)));
+        assert_eq!(0, red(&screen, 4, 6));
+
+        // Round caps reach past the ends, butt caps don't
+        screen.clear();
+        style = LineStyle { width: 3., cap: LineCap::Round, smooth: false };
+        screen.draw_styled_line([3.5, 5.5, 0.], [6.5, 5.5, 0.], white, &style);
+        assert_eq!((255, 255, 0), (red(&screen, 2, 5), red(&screen, 5, 6), red(&screen, 5, 7)));
+        screen.clear();
+        style.cap = LineCap::Butt;
+        screen.draw_styled_line([3.5, 5.5, 0.], [6.5, 5.5, 0.], white, &style);
+        assert_eq!((0, 255), (red(&screen, 2, 5), red(&screen, 3, 5)));
+
+        // Lines don't cover what's in front of them
+        screen.plot(5, 5, 1., black.into());
+        screen.draw_styled_line([3.5, 5.5, 0.], [6.5, 5.5, 0.], white, &style);
+        assert_eq!(0, red(&screen, 5, 5));
+    }
+
+    #[test]
+    fn hdr_output() {
+        let mut screen = ScreenBuilder { xres: 2, yres: 1, ..Default::default() }.create();
+        // Twice as bright as white on the left
+        screen.plot(0, 0, 1., HdrColor::new(2., 1., 0.25));
+        let c = screen.resolve()[0].0;
+        assert_eq!((255, 255, 64), (c.red, c.green, c.blue));
+        screen.tone_map = ToneMap::Reinhard;
+        let c = screen.resolve()[0].0;
+        assert_eq!((170, 128, 51), (c.red, c.green, c.blue));
+        screen.srgb = true;
+        let c = screen.resolve()[0].0;
+        assert_eq!((213, 188, 124), (c.red, c.green, c.blue));
+
+        let mut hdr = vec![];
+        screen.write_hdr(&mut hdr).unwrap();
+        assert!(hdr.starts_with(b"#?RADIANCE\n"));
+        // 2 = 0.5 * 2^2
+        assert_eq!(&[128, 64, 16, 130, 0, 0, 0, 0], &hdr[hdr.len() - 8..]);
+
+        let mut pfm = vec![];
+        screen.write_pfm(&mut pfm).unwrap();
+        assert_eq!(b"PF\n2 1\n-1.0\n", &pfm[..12]);
+        assert_eq!(2f32.to_le_bytes(), pfm[12..16]);
+    }
+    #[test]
+    fn shared_edges() {
+        // Split into tiles that are drawn on separate threads
+        let mut screen = ScreenBuilder { xres: 48, yres: 48, threads: 3, ..Default::default() }.create();
+        // A fan of triangles around the center of a hexagon, each one closer than the last
+        let center = [21.9, 24.3];
+        let ring = [[3.6, 5.1], [28.5, 1.8], [44.4, 18.75], [39., 43.5], [17.25, 45.3], [1.2, 27.]];
+        let counts = Mutex::new([[0; 48]; 48]);
+        let shade = |_: &Screen, _: &Primitive, f: &Fragment| {
+            counts.lock().unwrap()[f.y as usize][f.x as usize] += 1;
+            HdrColor::new(1., 1., 1.)
+        };
+        let mut primitives = vec![];
+        for i in 0..ring.len() {
+            let vertex = |p: [f64; 2]| Vertex { pos: [p[0], p[1], i as f64, 1.], attr: [0.; ATTRS] };
+            let polygon = [vertex(center), vertex(ring[i]), vertex(ring[(i + 1) % ring.len()])];
+            Primitive::fan(&polygon, HdrColor::default(), &mut primitives);
+        }
+        screen.fill_primitives(&primitives, &shade, screen.threads);
+
+        // Every sample inside the hexagon is drawn exactly once, and none outside it are
+        let counts = counts.into_inner().unwrap();
+        for (y, row) in counts.iter().enumerate() {
+            for (x, &count) in row.iter().enumerate() {
+                let p = [x as f64 + 0.5, y as f64 + 0.5];
+                let sides = (0..ring.len()).map(|i| {
+                    let (a, b) = (ring[i], ring[(i + 1) % ring.len()]);
+                    (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0])
+                });
+                let (inside, outside) = (sides.clone().all(|s| s > 1e-9), sides.clone().any(|s| s < -1e-9));
+                assert!(count <= 1, "({}, {}) was drawn {} times", x, y, count);
+                if inside {
+                    assert_eq!(1, count, "({}, {}) wasn't drawn", x, y);
+                } else if outside {
+                    assert_eq!(0, count, "({}, {}) is outside", x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn threads() {
+        // Translucent, textured and multisampled, with every tile split across pixels
+        let sphere = |polygons: &mut Matrix, uvs: &mut Vec<_>, center: [f64; 3], r| {
+            let [x, y, z] = center;
+            draw::add_sphere(polygons, &mut Matrix::default(), uvs, x, y, z, r, 20);
+        };
+        let (mut polygons, mut uvs) = (Matrix::default(), vec![]);
+        sphere(&mut polygons, &mut uvs, [30., 30., 0.], 25.);
+        sphere(&mut polygons, &mut uvs, [40., 25., 10.], 15.);
+        let mut glass = Matrix::default();
+        sphere(&mut glass, &mut vec![], [25., 35., 40.], 20.);
+        let reflection = Reflection { alpha: 0.5, ..ScreenBuilder::default().reflection };
+        let draw = |threads| {
+            let mut screen = ScreenBuilder {
+                xres: 60,
+                yres: 60,
+                supersample: 2,
+                multisample: true,
+                threads,
+                shading: Shading::Phong,
+                ..Default::default()
+            }
+            .create();
+            let texels = vec![consts::RED, consts::CYAN, consts::CYAN, consts::RED];
+            screen.texture = Some(Arc::new(Texture::new(2, 2, texels, Filter::Trilinear)));
+            screen.draw_polygons(&polygons, Some(&uvs), None, &screen.lights.clone());
+            screen.draw_polygons(&glass, None, Some(&reflection), &screen.lights.clone());
+            screen
+        };
+        let (serial, parallel) = (draw(1), draw(4));
+        let bytes = |screen: &Screen| {
+            let mut data = vec![];
+            screen.write_pfm(&mut data).unwrap();
+            data
+        };
+        assert_eq!(bytes(&serial), bytes(&parallel));
+        assert!(serial.translucent.len() == parallel.translucent.len() && !serial.translucent.is_empty());
+    }
+
+    #[test]
+    fn picture_path_errors() {
+        let err = picture_path(&[]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+
+        // A file where a parent directory should be is an error instead of a panic
+        let parent = "picture_path_errors";
+        let file = picture_path(&[parent]).unwrap();
+        File::create(&file).unwrap();
+        let result = picture_path(&[parent, "pic.png"]);
+        std::fs::remove_file(&file).unwrap();
+        assert!(result.unwrap_err().to_string().contains("Failed to create image output directory"));
+    }
+}
@@ -2,6 +2,7 @@ use crate::{Light, Screen, Vector};
 use std::f64;
 use std::fmt;
 use std::ops::{Add, AddAssign, Mul};
+use std::str::FromStr;
 
 pub mod consts {
     use super::Color;
@@ -17,22 +18,37 @@ pub mod consts {
 }
 
 // TODO: Remove `Copy` trait
-#[derive(Debug, Default, Copy, Clone)]
+#[derive(Debug, Copy, Clone)]
 pub struct Color {
     pub red: u8,
     pub green: u8,
     pub blue: u8,
+    // 0 is fully transparent and 255 is opaque. Math on colors keeps the alpha
+    // of the color on the left
+    pub alpha: u8,
 }
 
 impl Color {
     pub const fn new(r: u8, g: u8, b: u8) -> Color {
-        Color { red: r, green: g, blue: b }
+        Color::rgba(r, g, b, 255)
+    }
+
+    pub const fn rgba(r: u8, g: u8, b: u8, a: u8) -> Color {
+        Color { red: r, green: g, blue: b, alpha: a }
     }
 
     pub fn color(&mut self, c: Color) {
         self.red = c.red;
         self.green = c.green;
         self.blue = c.blue;
+        self.alpha = c.alpha;
+    }
+}
+
+// Opaque black
+impl Default for Color {
+    fn default() -> Self {
+        consts::BLACK
     }
 }
 
@@ -54,10 +70,11 @@ impl AddAssign for Color {
 impl Add for &Color {
     type Output = Color;
     fn add(self, rhs: &Color) -> Color {
-        Color::new(
+        Color::rgba(
             self.red.saturating_add(rhs.red),
             self.green.saturating_add(rhs.green),
             self.blue.saturating_add(rhs.blue),
+            self.alpha,
         )
     }
 }
@@ -73,10 +90,11 @@ impl Add<&Self> for Color {
 impl Mul<&Shine> for &Color {
     type Output = Color;
     fn mul(self, rhs: &Shine) -> Color {
-        Color::new(
+        Color::rgba(
             as_u8(f64::from(self.red) * rhs.red),
             as_u8(f64::from(self.green) * rhs.green),
             as_u8(f64::from(self.blue) * rhs.blue),
+            self.alpha,
         )
     }
 }
@@ -92,10 +110,11 @@ impl Mul<&Shine> for Color {
 impl Mul<f64> for &Color {
     type Output = Color;
     fn mul(self, rhs: f64) -> Color {
-        Color::new(
+        Color::rgba(
             as_u8(f64::from(self.red) * rhs),
             as_u8(f64::from(self.green) * rhs),
             as_u8(f64::from(self.blue) * rhs),
+            self.alpha,
         )
     }
 }
@@ -108,6 +127,145 @@ impl Mul<f64> for Color {
     }
 }
 
+// Linear color, where 1 is as bright as the screen can show. Lighting can add up past
+// that, and screens keep every sample in these until they're tone mapped down to
+// a `Color` when written out
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct HdrColor {
+    pub red: f32,
+    pub green: f32,
+    pub blue: f32,
+    // From 0 for transparent to 1 for opaque
+    pub alpha: f32,
+}
+
+impl HdrColor {
+    pub const fn new(r: f32, g: f32, b: f32) -> HdrColor {
+        HdrColor { red: r, green: g, blue: b, alpha: 1. }
+    }
+
+    // `self` drawn on top of `below`
+    pub fn over(&self, below: &HdrColor) -> HdrColor {
+        let (a, b) = (self.alpha, below.alpha);
+        let alpha = a + b * (1. - a);
+        if alpha <= 0. {
+            return HdrColor { alpha: 0., ..*below };
+        }
+        let mix = |top: f32, bottom: f32| (top * a + bottom * b * (1. - a)) / alpha;
+        HdrColor {
+            red: mix(self.red, below.red),
+            green: mix(self.green, below.green),
+            blue: mix(self.blue, below.blue),
+            alpha,
+        }
+    }
+
+    // Bring the color into 0..=1 with `tone_map`, then encode it for the screen
+    pub fn to_color(&self, tone_map: ToneMap, srgb: bool) -> Color {
+        let encode = |v: f32| {
+            let v = tone_map.map(v);
+            let v = if srgb { srgb_encode(v) } else { v };
+            (v * 255.).round() as u8
+        };
+        Color::rgba(encode(self.red), encode(self.green), encode(self.blue), (self.alpha.clamp(0., 1.) * 255.).round() as u8)
+    }
+}
+
+// Opaque black
+impl Default for HdrColor {
+    fn default() -> Self {
+        HdrColor::new(0., 0., 0.)
+    }
+}
+
+impl From<Color> for HdrColor {
+    fn from(c: Color) -> HdrColor {
+        let f = |v: u8| f32::from(v) / 255.;
+        HdrColor { red: f(c.red), green: f(c.green), blue: f(c.blue), alpha: f(c.alpha) }
+    }
+}
+
+impl AddAssign for HdrColor {
+    fn add_assign(&mut self, rhs: HdrColor) {
+        self.red += rhs.red;
+        self.green += rhs.green;
+        self.blue += rhs.blue;
+    }
+}
+
+impl Add for HdrColor {
+    type Output = HdrColor;
+    fn add(mut self, rhs: HdrColor) -> HdrColor {
+        self += rhs;
+        self
+    }
+}
+
+impl Mul<&Shine> for HdrColor {
+    type Output = HdrColor;
+    fn mul(self, rhs: &Shine) -> HdrColor {
+        HdrColor {
+            red: self.red * rhs.red as f32,
+            green: self.green * rhs.green as f32,
+            blue: self.blue * rhs.blue as f32,
+            alpha: self.alpha,
+        }
+    }
+}
+
+impl Mul<f64> for HdrColor {
+    type Output = HdrColor;
+    fn mul(self, rhs: f64) -> HdrColor {
+        self * &Shine::new(rhs, rhs, rhs)
+    }
+}
+
+// How colors brighter than the screen can show are brought back down
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMap {
+    // Cut off at 1
+    Clamp,
+    // x / (1 + x). Never quite reaches white
+    Reinhard,
+    // Fit of the ACES filmic curve by Krzysztof Narkowicz
+    Aces,
+}
+
+#[derive(Debug)]
+pub struct ParseToneMapError;
+
+impl FromStr for ToneMap {
+    type Err = ParseToneMapError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "clamp" => Ok(ToneMap::Clamp),
+            "reinhard" => Ok(ToneMap::Reinhard),
+            "aces" => Ok(ToneMap::Aces),
+            _ => Err(ParseToneMapError),
+        }
+    }
+}
+
+impl ToneMap {
+    pub fn map(self, v: f32) -> f32 {
+        let v = v.max(0.);
+        match self {
+            ToneMap::Clamp => v.min(1.),
+            ToneMap::Reinhard => v / (1. + v),
+            ToneMap::Aces => ((v * (2.51 * v + 0.03)) / (v * (2.43 * v + 0.59) + 0.14)).min(1.),
+        }
+    }
+}
+
+// Linear to the sRGB transfer curve
+fn srgb_encode(v: f32) -> f32 {
+    if v <= 0.003_130_8 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1. / 2.4) - 0.055
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct Shine {
     pub red: f64,
@@ -130,59 +288,67 @@ impl Shine {
     pub fn get_shine(
         screen: &Screen,
         normal: &Vector,
+        point: &Vector,
+        view: &Vector,
         reflect: Option<&Reflection>,
         lights: &[Light],
-    ) -> Color {
+    ) -> HdrColor {
         assert!(!lights.is_empty(), "Must have at least 1 light!");
 
         // If `reflect` isn't provided use the default
         let reflect = reflect.unwrap_or(&screen.reflection);
 
         let normal_v = Vector::normalized(normal);
-        Shine::get_ambient(screen.ambient_light, &reflect.ambient)
-            + &Shine::get_diffuse(&normal_v, lights, &reflect.diffuse)
-            + &Shine::get_specular(
+        HdrColor::from(reflect.emissive)
+            + Shine::get_ambient(screen.ambient_light, &reflect.ambient)
+            + Shine::get_diffuse(&normal_v, point, lights, &reflect.diffuse)
+            + Shine::get_specular(
                 &normal_v,
+                point,
                 lights,
-                &screen.view_vector,
+                view,
                 &reflect.specular,
                 screen.specular_exp,
             )
     }
 
-    fn get_ambient(ambient_light: Color, reflect: &Shine) -> Color {
-        ambient_light * reflect
+    fn get_ambient(ambient_light: Color, reflect: &Shine) -> HdrColor {
+        HdrColor::from(ambient_light) * reflect
     }
 
-    fn get_diffuse(normal_v: &Vector, lights: &[Light], reflect: &Shine) -> Color {
-        let mut diffuse = Color::default();
+    fn get_diffuse(normal_v: &Vector, point: &Vector, lights: &[Light], reflect: &Shine) -> HdrColor {
+        let mut diffuse = HdrColor::default();
         for light in lights {
-            diffuse += light.color * light.pos.dot_product(normal_v);
+            let (light_v, intensity) = light.incident(point);
+            // Lights behind the surface don't take any light away
+            diffuse += HdrColor::from(light.color) * (light_v.dot_product(normal_v).max(0.) * intensity);
         }
         diffuse * reflect
     }
 
     fn get_specular(
         normal_v: &Vector,
+        point: &Vector,
         lights: &[Light],
         view_v: &Vector,
         reflect: &Shine,
         specular_exp: i32,
-    ) -> Color {
+    ) -> HdrColor {
         //let reflected = normal_v * 2. * light.pos.dot_product(normal_v) - &light.pos;
-        //let angle = match reflected.dot_product(&view_v) {
+        //let angle = match reflected.dot_product(view_v) {
         //    neg if neg < 0. => 0.,
         //    others => others.powi(SPECULAR_EXP),
         //};
         //light.color * reflect * angle;
-        let mut specular = Color::default();
+        let mut specular = HdrColor::default();
         for light in lights {
-            let reflected = normal_v * 2. * light.pos.dot_product(normal_v) - &light.pos;
-            let angle = match reflected.dot_product(&view_v) {
+            let (light_v, intensity) = light.incident(point);
+            let reflected = normal_v * 2. * light_v.dot_product(normal_v) - &light_v;
+            let angle = match reflected.dot_product(view_v) {
                 neg if neg < 0. => 0.,
                 others => others.powi(specular_exp),
             };
-            specular += light.color * angle;
+            specular += HdrColor::from(light.color) * (angle * intensity);
         }
         specular * reflect
     }
@@ -201,10 +367,14 @@ pub struct Reflection {
     pub ambient: Shine,
     pub diffuse: Shine,
     pub specular: Shine,
+    // Color given off by the surface itself, no matter how it's lit
+    pub emissive: Color,
+    // Opacity, from 0 for invisible to 1 for opaque
+    pub alpha: f64,
 }
 
 impl Reflection {
     pub const fn new(ambient: Shine, diffuse: Shine, specular: Shine) -> Reflection {
-        Reflection { ambient, diffuse, specular }
+        Reflection { ambient, diffuse, specular, emissive: consts::BLACK, alpha: 1. }
     }
 }
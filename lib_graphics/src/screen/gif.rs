@@ -0,0 +1,367 @@
+use super::zlib::BitWriter;
+use super::{Color, Screen};
+use std::collections::HashMap;
+use std::io::{self, prelude::*};
+
+const MAX_COLORS: usize = 256;
+// LZW codes can't be wider than 12 bits
+const MAX_CODES: u16 = 1 << 12;
+
+// Animated GIF89a writer. Each frame gets its own color table
+pub struct GifEncoder<W: Write> {
+    out: W,
+    width: u16,
+    height: u16,
+}
+
+impl<W: Write> GifEncoder<W> {
+    // `loops` is how many times the animation repeats. 0 loops forever
+    pub fn new(mut out: W, width: usize, height: usize, loops: u16) -> io::Result<GifEncoder<W>> {
+        let (width, height) = (dimension(width)?, dimension(height)?);
+
+        out.write_all(b"GIF89a")?;
+        // Logical screen descriptor without a global color table
+        out.write_all(&width.to_le_bytes())?;
+        out.write_all(&height.to_le_bytes())?;
+        out.write_all(&[0, 0, 0])?;
+        // Netscape application extension with the loop count
+        out.write_all(&[0x21, 0xff, 0x0b])?;
+        out.write_all(b"NETSCAPE2.0")?;
+        out.write_all(&[0x03, 0x01])?;
+        out.write_all(&loops.to_le_bytes())?;
+        out.write_all(&[0x00])?;
+
+        Ok(GifEncoder { out, width, height })
+    }
+
+    // `delay` is in hundredths of a second
+    pub fn add_frame(&mut self, screen: &Screen, delay: u16) -> io::Result<()> {
+        if (screen.xres, screen.yres) != (usize::from(self.width), usize::from(self.height)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Every frame of a gif must be the same size!",
+            ));
+        }
+        let colors: Vec<Color> = screen.resolve().iter().map(|&(color, _)| color).collect();
+        let (palette, indices) = quantize(&colors);
+
+        // The color table must have a power of 2 number of entries, from 2 to 256
+        let bits = (palette.len().max(2) as f64).log2().ceil() as u8;
+
+        // Graphic control extension with the frame delay
+        self.out.write_all(&[0x21, 0xf9, 0x04, 0x00])?;
+        self.out.write_all(&delay.to_le_bytes())?;
+        self.out.write_all(&[0x00, 0x00])?;
+
+        // Image descriptor covering the whole screen, followed by its color table
+        self.out.write_all(&[0x2c, 0, 0, 0, 0])?;
+        self.out.write_all(&self.width.to_le_bytes())?;
+        self.out.write_all(&self.height.to_le_bytes())?;
+        self.out.write_all(&[0x80 | (bits - 1)])?;
+        let mut table = vec![0; 3 << bits];
+        for (entry, color) in table.chunks_exact_mut(3).zip(&palette) {
+            entry.copy_from_slice(&[color.red, color.green, color.blue]);
+        }
+        self.out.write_all(&table)?;
+
+        // The minimum code size can't be less than 2
+        let min_code_size = bits.max(2);
+        self.out.write_all(&[min_code_size])?;
+        for block in lzw(&indices, min_code_size).chunks(255) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0x00])
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(&[0x3b])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+fn dimension(size: usize) -> io::Result<u16> {
+    match size {
+        0 => Err(io::Error::new(io::ErrorKind::InvalidInput, "Gifs can't be empty!")),
+        size if size > usize::from(u16::MAX) => {
+            Err(io::Error::new(io::ErrorKind::InvalidInput, "Image is too large for a gif!"))
+        },
+        size => Ok(size as u16),
+    }
+}
+
+fn key(c: Color) -> u32 {
+    u32::from(c.red) << 16 | u32::from(c.green) << 8 | u32::from(c.blue)
+}
+
+// Reduce the image to at most 256 colors using median cut.
+// Returns the palette and the palette index of every pixel
+fn quantize(pixels: &[Color]) -> (Vec<Color>, Vec<u8>) {
+    let mut histogram: HashMap<u32, (Color, u32)> = HashMap::new();
+    for &c in pixels {
+        histogram.entry(key(c)).or_insert((c, 0)).1 += 1;
+    }
+    let mut colors: Vec<(Color, u32)> = histogram.values().copied().collect();
+    // HashMap iteration order is random. Sort so the output is reproducible
+    colors.sort_by_key(|&(c, _)| key(c));
+
+    let palette = if colors.len() <= MAX_COLORS {
+        colors.iter().map(|&(c, _)| c).collect()
+    } else {
+        median_cut(colors)
+    };
+
+    // Map every distinct color to its closest palette entry
+    let mut lookup: HashMap<u32, u8> = HashMap::with_capacity(histogram.len());
+    let indices = pixels
+        .iter()
+        .map(|&c| *lookup.entry(key(c)).or_insert_with(|| closest(&palette, c)))
+        .collect();
+    (palette, indices)
+}
+
+fn channel(c: Color, i: usize) -> u8 {
+    match i {
+        0 => c.red,
+        1 => c.green,
+        _ => c.blue,
+    }
+}
+
+fn median_cut(colors: Vec<(Color, u32)>) -> Vec<Color> {
+    // Returns the channel with the largest range and that range
+    let widest = |bucket: &[(Color, u32)]| {
+        (0..3)
+            .map(|i| {
+                let values = bucket.iter().map(|&(c, _)| channel(c, i));
+                let (min, max) = values.fold((255, 0), |(min, max), v| (v.min(min), v.max(max)));
+                (i, max.saturating_sub(min))
+            })
+            .max_by_key(|&(_, range)| range)
+            .unwrap()
+    };
+
+    let mut buckets = vec![colors];
+    while buckets.len() < MAX_COLORS {
+        // Split the bucket with the widest range of colors
+        let (index, (axis, range)) = buckets
+            .iter()
+            .map(|bucket| widest(bucket))
+            .enumerate()
+            .max_by_key(|&(_, (_, range))| range)
+            .unwrap();
+        if range == 0 {
+            break;
+        }
+        let mut bucket = buckets.swap_remove(index);
+        bucket.sort_by_key(|&(c, _)| channel(c, axis));
+        // Split at the weighted median, keeping at least one color on each side
+        let total: u64 = bucket.iter().map(|&(_, n)| u64::from(n)).sum();
+        let mut seen = 0;
+        let mut split = bucket.len() - 1;
+        for (i, &(_, n)) in bucket.iter().enumerate() {
+            seen += u64::from(n);
+            if seen * 2 >= total {
+                split = i + 1;
+                break;
+            }
+        }
+        let split = split.max(1).min(bucket.len() - 1);
+        let upper = bucket.split_off(split);
+        buckets.push(bucket);
+        buckets.push(upper);
+    }
+
+    // Each bucket becomes the weighted average of its colors
+    buckets
+        .iter()
+        .map(|bucket| {
+            let mut sum = [0u64; 3];
+            let mut total = 0;
+            for &(c, n) in bucket {
+                for (i, s) in sum.iter_mut().enumerate() {
+                    *s += u64::from(channel(c, i)) * u64::from(n);
+                }
+                total += u64::from(n);
+            }
+            let avg = |s: u64| ((s + total / 2) / total) as u8;
+            Color::new(avg(sum[0]), avg(sum[1]), avg(sum[2]))
+        })
+        .collect()
+}
+
+fn closest(palette: &[Color], c: Color) -> u8 {
+    let dist = |p: &Color| {
+        let d = |a: u8, b: u8| (i32::from(a) - i32::from(b)).pow(2);
+        d(p.red, c.red) + d(p.green, c.green) + d(p.blue, c.blue)
+    };
+    let (index, _) = palette.iter().enumerate().min_by_key(|&(_, p)| dist(p)).unwrap();
+    index as u8
+}
+
+// Variable code width LZW as used by GIF
+fn lzw(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut writer = BitWriter::new(indices.len() / 2 + 16);
+    let mut codes: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut size = u32::from(min_code_size) + 1;
+
+    writer.write(u32::from(clear), size);
+    let mut prefix = match indices.first() {
+        Some(&first) => u16::from(first),
+        None => {
+            writer.write(u32::from(end), size);
+            return writer.finish();
+        },
+    };
+    for &index in &indices[1..] {
+        if let Some(&code) = codes.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+        writer.write(u32::from(prefix), size);
+        if next < MAX_CODES {
+            codes.insert((prefix, index), next);
+            next += 1;
+            if u32::from(next) > 1 << size {
+                size += 1;
+            }
+        } else {
+            // The code table is full, start over
+            writer.write(u32::from(clear), size);
+            codes.clear();
+            next = end + 1;
+            size = u32::from(min_code_size) + 1;
+        }
+        prefix = u16::from(index);
+    }
+    writer.write(u32::from(prefix), size);
+    writer.write(u32::from(end), size);
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::zlib::BitReader;
+    use super::super::ScreenBuilder;
+    use super::*;
+
+    // Decodes GIF LZW codes back into palette indices
+    fn unlzw(data: &[u8], min_code_size: u8) -> Vec<u8> {
+        let clear = 1u16 << min_code_size;
+        let end = clear + 1;
+        let mut reader = BitReader::new(data);
+        let mut table: Vec<Vec<u8>> = (0..clear).map(|i| vec![i as u8]).collect();
+        let mut size = u32::from(min_code_size) + 1;
+        let mut prev: Option<Vec<u8>> = None;
+        let mut indices = vec![];
+        loop {
+            let code = reader.read(size).unwrap() as u16;
+            if code == clear {
+                table.truncate(usize::from(clear));
+                size = u32::from(min_code_size) + 1;
+                prev = None;
+                continue;
+            }
+            if code == end {
+                return indices;
+            }
+            // Codes are added to the table one code late, so the code can be the one
+            // that's about to be added
+            let next = table.len() as u16 + 2;
+            let entry = match (&prev, code) {
+                (_, code) if code < clear => table[usize::from(code)].clone(),
+                (_, code) if code < next => table[usize::from(code) - 2].clone(),
+                (Some(prev), code) if code == next => [&prev[..], &prev[..1]].concat(),
+                _ => panic!("Code {} isn't in the table", code),
+            };
+            indices.extend(&entry);
+            if let Some(prev) = prev {
+                if next < MAX_CODES {
+                    table.push([&prev[..], &entry[..1]].concat());
+                    if u32::from(next + 1) == 1 << size && size < 12 {
+                        size += 1;
+                    }
+                }
+            }
+            prev = Some(entry);
+        }
+    }
+
+    #[test]
+    fn lzw_round_trip() {
+        // Clear, 1, 1 1, 1 and end, 3 bits each
+        assert_eq!(vec![140, 83], lzw(&[1, 1, 1, 1], 2));
+        assert_eq!(vec![1, 1, 1, 1], unlzw(&lzw(&[1, 1, 1, 1], 2), 2));
+        assert_eq!(Vec::<u8>::new(), unlzw(&lzw(&[], 2), 2));
+
+        // Long enough to fill the code table and start over a few times
+        let mut seed = 12345u32;
+        let indices: Vec<u8> = (0..50_000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (seed >> 16) as u8
+            })
+            .collect();
+        assert_eq!(indices, unlzw(&lzw(&indices, 8), 8));
+        // Runs compress
+        let runs: Vec<u8> = (0..10_000).map(|i| (i / 100 % 4) as u8).collect();
+        let data = lzw(&runs, 2);
+        assert!(data.len() < runs.len() / 10);
+        assert_eq!(runs, unlzw(&data, 2));
+    }
+
+    #[test]
+    fn header_and_frame() {
+        let mut encoder = GifEncoder::new(vec![], 3, 2, 0).unwrap();
+        let screen = ScreenBuilder { xres: 3, yres: 2, screen_color: Color::new(10, 20, 30), ..Default::default() }.create();
+        encoder.add_frame(&screen, 7).unwrap();
+        let out = encoder.finish().unwrap();
+
+        assert_eq!(b"GIF89a", &out[..6]);
+        // 3x2 without a global color table
+        assert_eq!(&[3, 0, 2, 0, 0, 0, 0], &out[6..13]);
+        // Loops forever
+        assert_eq!(&[0x21, 0xff, 0x0b], &out[13..16]);
+        assert_eq!(b"NETSCAPE2.0", &out[16..27]);
+        assert_eq!(&[0x03, 0x01, 0, 0, 0x00], &out[27..32]);
+        // 7 hundredths of a second
+        assert_eq!(&[0x21, 0xf9, 0x04, 0x00, 7, 0, 0x00, 0x00], &out[32..40]);
+        // A 2 color table, even though there's only 1 color
+        assert_eq!(&[0x2c, 0, 0, 0, 0, 3, 0, 2, 0, 0x80], &out[40..50]);
+        assert_eq!(&[10, 20, 30, 0, 0, 0], &out[50..56]);
+        let min_code_size = out[56];
+        let length = usize::from(out[57]);
+        assert_eq!(vec![0; 6], unlzw(&out[58..58 + length], min_code_size));
+        assert_eq!(&[0x00, 0x3b], &out[58 + length..]);
+
+        let wrong_size = ScreenBuilder { xres: 2, yres: 2, ..Default::default() }.create();
+        assert!(GifEncoder::new(vec![], 3, 2, 1).unwrap().add_frame(&wrong_size, 7).is_err());
+    }
+
+    #[test]
+    fn quantization() {
+        let distance = |a: Color, b: Color| {
+            let d = |a: u8, b: u8| (i32::from(a) - i32::from(b)).abs();
+            d(a.red, b.red).max(d(a.green, b.green)).max(d(a.blue, b.blue))
+        };
+        // Few enough colors to keep them all
+        let colors = vec![Color::new(1, 2, 3), Color::new(4, 5, 6), Color::new(1, 2, 3)];
+        let (palette, indices) = quantize(&colors);
+        assert_eq!(2, palette.len());
+        assert!(colors.iter().zip(&indices).all(|(&c, &i)| distance(palette[usize::from(i)], c) == 0));
+
+        // 4096 colors are cut down to 256 that are close to them
+        let colors: Vec<Color> =
+            (0..4096).map(|i| Color::new((i % 16 * 17) as u8, (i / 16 % 16 * 17) as u8, (i / 256 * 17) as u8)).collect();
+        let (palette, indices) = quantize(&colors);
+        assert_eq!(MAX_COLORS, palette.len());
+        assert_eq!(colors.len(), indices.len());
+        for (&c, &i) in colors.iter().zip(&indices) {
+            assert!(distance(c, palette[usize::from(i)]) <= 40, "{:?} became {:?}", c, palette[usize::from(i)]);
+        }
+    }
+}
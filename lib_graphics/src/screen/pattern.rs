@@ -0,0 +1,147 @@
+// Procedural patterns. One repetition of the pattern is drawn into a texture, so that
+// it can be filtered like one, and is mapped onto shapes by their position. Every
+// texel of every mip level is the exact average of the pattern over its area, rather
+// than a sample of it, so that edges and distant patterns don't alias
+use super::texture::{Filter, Texture};
+use super::Color;
+use crate::matrix::COLS;
+use std::str::FromStr;
+use std::sync::Arc;
+
+// Width and height of the texture holding one repetition of a pattern
+const TILE: usize = 256;
+// Width of the lines in a grid, as a fraction of the space between them
+const GRID_LINE: f64 = 1. / 16.;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PatternKind {
+    // Squares of alternating colors
+    Checker,
+    // Bands of alternating colors
+    Stripes,
+    // Lines of the second color on top of the first
+    Grid,
+    // Fades from the first color to the second and back
+    Gradient,
+}
+
+#[derive(Debug)]
+pub struct ParsePatternError;
+
+impl FromStr for PatternKind {
+    type Err = ParsePatternError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "checker" => Ok(PatternKind::Checker),
+            "stripes" => Ok(PatternKind::Stripes),
+            "grid" => Ok(PatternKind::Grid),
+            "gradient" => Ok(PatternKind::Gradient),
+            _ => Err(ParsePatternError),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    // The pattern is projected onto shapes along this axis: 0, 1 or 2 for x, y or z
+    pub axis: usize,
+    // Size of a square, stripe or fade, or the space between grid lines
+    pub size: f64,
+    pub colors: [Color; 2],
+    pub texture: Arc<Texture>,
+}
+
+impl Pattern {
+    pub fn new(kind: PatternKind, axis: usize, size: f64, colors: [Color; 2]) -> Pattern {
+        assert!(axis < 3, "Patterns are projected along x, y or z!");
+        assert!(size > 0., "Patterns need a positive size!");
+        let mut mips = vec![];
+        let mut width = TILE;
+        loop {
+            let mut texels = Vec::with_capacity(width * width);
+            let edge = |i: usize| i as f64 / width as f64;
+            // Rows go from the top of the texture to the bottom
+            for row in (0..width).rev() {
+                for col in 0..width {
+                    texels.push(Pattern::average(kind, colors, (edge(col), edge(col + 1)), (edge(row), edge(row + 1))));
+                }
+            }
+            mips.push(texels);
+            if width == 1 {
+                break;
+            }
+            width /= 2;
+        }
+        let texture = Arc::new(Texture::from_mips(TILE, mips, Filter::Trilinear));
+        Pattern { kind, axis, size, colors, texture }
+    }
+
+    // Average color over the rectangle from s0 to s1 and t0 to t1, in one repetition
+    // of the pattern, which covers 0..1
+    fn average(kind: PatternKind, [a, b]: [Color; 2], (s0, s1): (f64, f64), (t0, t1): (f64, f64)) -> Color {
+        // Average of a function from x0 to x1, given its integral from 0
+        let mean = |integral: fn(f64) -> f64, x0: f64, x1: f64| (integral(x1) - integral(x0)) / (x1 - x0);
+        // Integrals of the second half of the repetition, the grid lines at its
+        // edges, and a fade that goes up to 1 in the middle and back down
+        let half: fn(f64) -> f64 = |x| (x - 0.5).max(0.);
+        let lines: fn(f64) -> f64 = |x| x.min(GRID_LINE / 2.) + (x - (1. - GRID_LINE / 2.)).max(0.);
+        let fade: fn(f64) -> f64 = |x| if x < 0.5 { x * x } else { 0.5 - (1. - x) * (1. - x) };
+        // How much of the rectangle is the second color
+        let t = match kind {
+            PatternKind::Checker => {
+                let (s, t) = (mean(half, s0, s1), mean(half, t0, t1));
+                s * (1. - t) + (1. - s) * t
+            },
+            PatternKind::Stripes => mean(half, s0, s1),
+            PatternKind::Grid => 1. - (1. - mean(lines, s0, s1)) * (1. - mean(lines, t0, t1)),
+            PatternKind::Gradient => mean(fade, s0, s1),
+        };
+        let mix = |a: u8, b: u8| (f64::from(a) + (f64::from(b) - f64::from(a)) * t).round() as u8;
+        Color::new(mix(a.red, b.red), mix(a.green, b.green), mix(a.blue, b.blue))
+    }
+
+    // How far one repetition of the pattern stretches
+    fn period(&self) -> f64 {
+        match self.kind {
+            PatternKind::Grid => self.size,
+            _ => 2. * self.size,
+        }
+    }
+
+    // Texture coordinates of a point in object space. The pattern lies in the plane
+    // of the other two axes, in the order x, y, z
+    pub fn coords(&self, point: &[f64; COLS]) -> [f64; 2] {
+        let (s, t) = match self.axis {
+            0 => (point[1], point[2]),
+            1 => (point[0], point[2]),
+            _ => (point[0], point[1]),
+        };
+        [s / self.period(), t / self.period()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn averages() {
+        let colors = [Color::new(0, 0, 0), Color::new(255, 255, 255)];
+        // The 1x1 mip level is the average of the whole pattern
+        let average = |kind| {
+            let texture = &Pattern::new(kind, 2, 1., colors).texture;
+            texture.sample_grad(0.5, 0.5, [1., 0.], [0., 1.]).red
+        };
+        assert_eq!((128, 128, 128), (average(PatternKind::Checker), average(PatternKind::Stripes), average(PatternKind::Gradient)));
+        // The lines cover a sixteenth of each direction
+        let lines = 1. - (1. - GRID_LINE) * (1. - GRID_LINE);
+        assert_eq!((255. * lines).round() as u8, average(PatternKind::Grid));
+
+        // Texels on the edge of a stripe are a mix of both colors
+        let fraction = |s0, s1| Pattern::average(PatternKind::Stripes, colors, (s0, s1), (0., 1.)).red;
+        assert_eq!((0, 85, 255), (fraction(0., 0.5), fraction(0.25, 0.625), fraction(0.5, 1.)));
+        let c = Pattern::average(PatternKind::Gradient, colors, (0.5, 0.5 + 1e-9), (0., 1.));
+        assert_eq!(255, c.red);
+    }
+}
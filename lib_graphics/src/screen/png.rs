@@ -0,0 +1,321 @@
+use super::zlib;
+use std::io::{self, prelude::*};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorType {
+    Gray,
+    Rgb,
+    Rgba,
+}
+
+impl ColorType {
+    pub fn channels(self) -> usize {
+        match self {
+            ColorType::Gray => 1,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+
+    // Value of the color type field in the IHDR chunk
+    fn code(self) -> u8 {
+        match self {
+            ColorType::Gray => 0,
+            ColorType::Rgb => 2,
+            ColorType::Rgba => 6,
+        }
+    }
+}
+
+// Writes an 8 bit per channel image. `data` holds the rows top to bottom
+pub fn encode<W: Write>(
+    out: &mut W,
+    width: usize,
+    height: usize,
+    color: ColorType,
+    data: &[u8],
+) -> io::Result<()> {
+    let stride = width * color.channels();
+    assert_eq!(stride * height, data.len(), "Image data doesn't match its dimensions!");
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&dimension(width)?.to_be_bytes());
+    header.extend_from_slice(&dimension(height)?.to_be_bytes());
+    // bit depth, color type, compression, filter and interlace methods
+    header.extend_from_slice(&[8, color.code(), 0, 0, 0]);
+
+    let filtered = filter(data, stride, color.channels());
+
+    out.write_all(&SIGNATURE)?;
+    write_chunk(out, b"IHDR", &header)?;
+    write_chunk(out, b"IDAT", &zlib::compress(&filtered))?;
+    write_chunk(out, b"IEND", &[])?;
+    out.flush()
+}
+
+// Reads an 8 bit per channel, non-interlaced image. Palette images come out as
+// RGB and gray images with alpha as RGBA. Returns the width, height, color type
+// and rows top to bottom
+pub fn decode(png: &[u8]) -> io::Result<(usize, usize, ColorType, Vec<u8>)> {
+    let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+    if !png.starts_with(&SIGNATURE) {
+        return Err(invalid("Not a PNG!"));
+    }
+
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut compressed = vec![];
+    let mut rest = &png[SIGNATURE.len()..];
+    while rest.len() >= 12 {
+        let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        if rest.len() < 12 + len {
+            break;
+        }
+        let (kind, data) = (&rest[4..8], &rest[8..8 + len]);
+        match kind {
+            b"IHDR" if len == 13 => header = Some(data),
+            b"PLTE" => palette = data,
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {},
+        }
+        rest = &rest[12 + len..];
+    }
+
+    let header = header.ok_or_else(|| invalid("PNG is missing its header!"))?;
+    let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    let (depth, code, interlace) = (header[8], header[9], header[12]);
+    if depth != 8 || interlace != 0 {
+        return Err(invalid("Only 8 bit, non-interlaced PNGs are supported!"));
+    }
+    if width == 0 || height == 0 {
+        return Err(invalid("PNG has no pixels!"));
+    }
+    // Channels stored in the file
+    let channels = match code {
+        0 | 3 => 1,
+        4 => 2,
+        2 => 3,
+        6 => 4,
+        _ => return Err(invalid("Unknown PNG color type!")),
+    };
+
+    // Every row starts with its filter type
+    let stride = width.checked_mul(channels).ok_or_else(|| invalid("PNG is too large!"))?;
+    let size = (stride + 1).checked_mul(height).ok_or_else(|| invalid("PNG is too large!"))?;
+    let filtered = zlib::decompress(&compressed)?;
+    if filtered.len() < size {
+        return Err(invalid("PNG image data is too short!"));
+    }
+    let data = unfilter(&filtered, stride, channels, height)?;
+
+    match code {
+        0 => Ok((width, height, ColorType::Gray, data)),
+        2 => Ok((width, height, ColorType::Rgb, data)),
+        6 => Ok((width, height, ColorType::Rgba, data)),
+        3 => {
+            let mut rgb = Vec::with_capacity(data.len() * 3);
+            for &index in &data {
+                let entry = palette.get(3 * usize::from(index)..3 * usize::from(index) + 3);
+                rgb.extend_from_slice(entry.ok_or_else(|| invalid("Palette index out of range!"))?);
+            }
+            Ok((width, height, ColorType::Rgb, rgb))
+        },
+        _ => {
+            let rgba = data.chunks_exact(2).flat_map(|ga| vec![ga[0], ga[0], ga[0], ga[1]]).collect();
+            Ok((width, height, ColorType::Rgba, rgba))
+        },
+    }
+}
+
+// Undo the filter in front of every row
+fn unfilter(data: &[u8], stride: usize, bpp: usize, height: usize) -> io::Result<Vec<u8>> {
+    let mut out = vec![0; stride * height];
+    for (y, line) in data.chunks_exact(stride + 1).take(height).enumerate() {
+        let (kind, line) = (line[0], &line[1..]);
+        let (done, row) = out.split_at_mut(y * stride);
+        let prior = if y > 0 { &done[(y - 1) * stride..] } else { &[][..] };
+        for i in 0..stride {
+            let left = if i >= bpp { row[i - bpp] } else { 0 };
+            let up = prior.get(i).copied().unwrap_or(0);
+            let up_left = if i >= bpp { prior.get(i - bpp).copied().unwrap_or(0) } else { 0 };
+            let predicted = match kind {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((u16::from(left) + u16::from(up)) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Unknown PNG filter type!")),
+            };
+            row[i] = line[i].wrapping_add(predicted);
+        }
+    }
+    Ok(out)
+}
+
+fn dimension(size: usize) -> io::Result<u32> {
+    match size {
+        0 => Err(io::Error::new(io::ErrorKind::InvalidInput, "PNG images can't be empty!")),
+        size if size > i32::MAX as usize => {
+            Err(io::Error::new(io::ErrorKind::InvalidInput, "Image is too large for a PNG!"))
+        },
+        size => Ok(size as u32),
+    }
+}
+
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let crc = crc32(crc32(!0, kind), data);
+    out.write_all(&(!crc).to_be_bytes())
+}
+
+// Prefix each row with the filter type that gives the smallest sum of
+// absolute differences. That's the heuristic recommended by the PNG spec
+fn filter(data: &[u8], stride: usize, bpp: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / stride.max(1));
+    let zeros = vec![0; stride];
+    let mut candidates = vec![vec![0; stride]; 5];
+
+    let mut prior: &[u8] = &zeros;
+    for row in data.chunks_exact(stride) {
+        for (kind, line) in candidates.iter_mut().enumerate() {
+            for i in 0..stride {
+                let left = if i >= bpp { row[i - bpp] } else { 0 };
+                let up = prior[i];
+                let up_left = if i >= bpp { prior[i - bpp] } else { 0 };
+                let predicted = match kind {
+                    0 => 0,
+                    1 => left,
+                    2 => up,
+                    3 => ((u16::from(left) + u16::from(up)) / 2) as u8,
+                    _ => paeth(left, up, up_left),
+                };
+                line[i] = row[i].wrapping_sub(predicted);
+            }
+        }
+        let score = |line: &Vec<u8>| line.iter().map(|&b| u64::from((b as i8).unsigned_abs())).sum::<u64>();
+        let (kind, best) = candidates.iter().enumerate().min_by_key(|(_, line)| score(line)).unwrap();
+        out.push(kind as u8);
+        out.extend_from_slice(best);
+        prior = row;
+    }
+    out
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let (ia, ib, ic) = (i16::from(a), i16::from(b), i16::from(c));
+    let p = ia + ib - ic;
+    let (pa, pb, pc) = ((p - ia).abs(), (p - ib).abs(), (p - ic).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 == 1 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+// Running CRC without the pre and post conditioning
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc = CRC_TABLE[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::screen::{Filter, Texture};
+
+    #[test]
+    fn checksums() {
+        assert_eq!(0xcbf4_3926, !crc32(!0, b"123456789"));
+        assert_eq!(0x091e_01de, zlib::adler32(b"123456789"));
+    }
+
+    #[test]
+    fn encode_header() {
+        let mut out = vec![];
+        encode(&mut out, 2, 1, ColorType::Rgb, &[255, 0, 0, 0, 0, 255]).unwrap();
+        assert_eq!(&SIGNATURE[..], &out[..8]);
+        assert_eq!(b"IHDR", &out[12..16]);
+        assert_eq!(&[0, 0, 0, 2, 0, 0, 0, 1, 8, 2, 0, 0, 0], &out[16..29]);
+        assert_eq!(b"IEND", &out[out.len() - 8..out.len() - 4]);
+    }
+
+    #[test]
+    fn decode_encoded() {
+        let data: Vec<u8> = (0..5 * 4 * 3).map(|i| (i * 37 % 256) as u8).collect();
+        let mut out = vec![];
+        encode(&mut out, 5, 4, ColorType::Rgb, &data).unwrap();
+        let (width, height, color, decoded) = decode(&out).unwrap();
+        assert_eq!((5, 4, ColorType::Rgb), (width, height, color));
+        assert_eq!(data, decoded);
+    }
+
+    #[test]
+    fn decode_bad_sizes() {
+        // The header of a `width` by `height` RGB image, with no pixels after it
+        let png = |width: u32, height: u32| {
+            let mut header = width.to_be_bytes().to_vec();
+            header.extend_from_slice(&height.to_be_bytes());
+            header.extend_from_slice(&[8, 2, 0, 0, 0]);
+            let mut out = SIGNATURE.to_vec();
+            write_chunk(&mut out, b"IHDR", &header).unwrap();
+            write_chunk(&mut out, b"IDAT", &zlib::compress(&[])).unwrap();
+            write_chunk(&mut out, b"IEND", &[]).unwrap();
+            out
+        };
+        for (width, height) in [(0, 1), (1, 0), (u32::MAX, u32::MAX)] {
+            let png = png(width, height);
+            assert_eq!(io::ErrorKind::InvalidData, decode(&png).unwrap_err().kind());
+            let texture = Texture::from_png(&png, Filter::Nearest);
+            assert_eq!(io::ErrorKind::InvalidData, texture.unwrap_err().kind());
+        }
+    }
+
+    #[test]
+    fn inflate_blocks() {
+        // "abc" as a stored block, then the same thing with the fixed codes
+        let stored = [0x78, 0x01, 0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c', 0x02, 0x4d, 0x01, 0x27];
+        assert_eq!(b"abc", &zlib::decompress(&stored).unwrap()[..]);
+        let repeated = b"abcabcabcabcabcabcabcabcabcabc";
+        assert_eq!(&repeated[..], &zlib::decompress(&zlib::compress(repeated)).unwrap()[..]);
+        assert!(zlib::decompress(&stored[..10]).is_err());
+
+        // Dynamic Huffman codes, from zlib at its highest compression level
+        #[rustfmt::skip]
+        let dynamic = [
+            0x78, 0xda, 0x55, 0xc9, 0x51, 0x02, 0x40, 0x20, 0x10, 0x04, 0xd0, 0xab, 0xcc, 0xd5, 0xc2, 0x50,
+            0x2c, 0x9b, 0xb6, 0x94, 0x4e, 0x9f, 0x5f, 0xbf, 0xef, 0x65, 0x4f, 0xdc, 0x25, 0xcc, 0x07, 0xa6,
+            0xa4, 0xf5, 0xc2, 0xaa, 0x0d, 0x7b, 0x39, 0xa3, 0x41, 0x1f, 0x26, 0xe4, 0xaf, 0xc5, 0xf5, 0x17,
+            0x8b, 0x6e, 0xa8, 0x3e, 0x08, 0xff, 0x64, 0x42, 0x46, 0x1b, 0x7d, 0x29, 0x19, 0x7a,
+        ];
+        let text = &b"the quick brown fox jumps over the lazy dog while the lazy dog sleeps"[..];
+        assert_eq!(text, &zlib::decompress(&dynamic).unwrap()[..]);
+    }
+}
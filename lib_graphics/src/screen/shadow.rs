@@ -0,0 +1,220 @@
+// Shadow maps: the scene's depth as seen from a light. A point is in shadow if
+// something in the shadow map is closer to the light than it is.
+use super::{Screen, ScreenBuilder};
+use crate::matrix::COLS;
+use crate::{Camera, Light, LightKind, Matrix, Vector};
+use std::f64;
+use std::fmt;
+
+// Widest filter radius, in shadow map pixels. Wider ones are clamped to it
+pub const MAX_PCF: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Shadows {
+    // Width and height of each shadow map in pixels
+    pub resolution: usize,
+    // How much closer to the light something has to be to cast a shadow.
+    // Keeps surfaces from shadowing themselves
+    pub bias: f64,
+    // Percentage closer filtering: average the shadow test over the
+    // (2 * pcf + 1) by (2 * pcf + 1) shadow map pixels around a point
+    pub pcf: usize,
+}
+
+impl Default for Shadows {
+    fn default() -> Shadows {
+        Shadows { resolution: 512, bias: 2., pcf: 1 }
+    }
+}
+
+// Only ever used behind an `Arc`, so the size difference doesn't matter
+#[allow(clippy::large_enum_variant)]
+pub enum ShadowMap {
+    // Orthographic projection along the light's direction, scaled so that
+    // every shadow caster fits in the map
+    Directional { view: Camera, min: (f64, f64), scale: f64, map: Screen, settings: Shadows },
+    // One map for each face of a cube around the light
+    Point { faces: Vec<Screen>, settings: Shadows },
+}
+
+impl ShadowMap {
+    // Render the depth of `casters`, which are polygons in world space, as seen from `light`,
+    // with `screen`'s shadow settings and number of threads. Screens without shadows, and
+    // directional lights without a direction, don't have one
+    pub fn new(light: &Light, casters: &Matrix, screen: &Screen) -> Option<ShadowMap> {
+        let settings = screen.shadows?;
+        // Filtering compares points up to `pcf` pixels away with the depth of the
+        // map, so surfaces need to be pushed back further the more they're slanted
+        let slope_bias = settings.pcf as f64 + 1.;
+        let create = |camera| {
+            ScreenBuilder {
+                xres: settings.resolution,
+                yres: settings.resolution,
+                threads: screen.threads,
+                camera,
+                ..Default::default()
+            }
+            .create()
+        };
+        match light.kind {
+            LightKind::Directional => {
+                // Look at the scene from the direction of the light
+                let origin = Vector::new(0., 0., 0.);
+                let view = Camera::new(origin.clone(), origin - &light.pos, None)?;
+                let mut casters: Vec<_> = casters.m.iter().map(|p| view.to_view(p)).collect();
+
+                let (mut min, mut max) = ([f64::INFINITY; 2], [f64::NEG_INFINITY; 2]);
+                for p in &casters {
+                    for i in 0..2 {
+                        min[i] = min[i].min(p[i]);
+                        max[i] = max[i].max(p[i]);
+                    }
+                }
+                // Leave a pixel of space around the edges for filtering
+                let size = (max[0] - min[0]).max(max[1] - min[1]);
+                let scale = if size > 0. { (settings.resolution as f64 - 2.) / size } else { 1. };
+                let min = (min[0] - 1. / scale, min[1] - 1. / scale);
+                for p in &mut casters {
+                    *p = to_map(p, min, scale);
+                }
+
+                let mut map = create(None);
+                map.draw_depth(&Matrix::from(casters.as_slice()), slope_bias);
+                Some(ShadowMap::Directional { view, min, scale, map, settings })
+            },
+            LightKind::Point { .. } | LightKind::Spot { .. } => {
+                // 90 degree field of view for every face of the cube
+                let focal = settings.resolution as f64 / 2.;
+                let axes = [
+                    Vector::new(1., 0., 0.),
+                    Vector::new(-1., 0., 0.),
+                    Vector::new(0., 1., 0.),
+                    Vector::new(0., -1., 0.),
+                    Vector::new(0., 0., 1.),
+                    Vector::new(0., 0., -1.),
+                ];
+                let faces = axes
+                    .iter()
+                    .map(|axis| {
+                        let camera = Camera::new(light.pos.clone(), axis.clone() + &light.pos, Some(focal))
+                            .expect("The axes aren't zero");
+                        let mut face = create(Some(camera));
+                        face.draw_depth(casters, slope_bias);
+                        face
+                    })
+                    .collect();
+                Some(ShadowMap::Point { faces, settings })
+            },
+        }
+    }
+
+    // How much of a light reaches `point`, from 0 (in shadow) to 1 (lit)
+    pub fn visibility(&self, point: &Vector) -> f64 {
+        let world = [point.x, point.y, point.z, 1.];
+        let (map, pos, settings) = match self {
+            ShadowMap::Directional { view, min, scale, map, settings } => {
+                (map, to_map(&view.to_view(&world), *min, *scale), settings)
+            },
+            ShadowMap::Point { faces, settings } => {
+                // Use the face of the cube that the point is in
+                let camera = faces[0].camera.as_ref().unwrap();
+                let d = point - &camera.eye;
+                let face = if d.x.abs() >= d.y.abs() && d.x.abs() >= d.z.abs() {
+                    if d.x > 0. { 0 } else { 1 }
+                } else if d.y.abs() >= d.z.abs() {
+                    if d.y > 0. { 2 } else { 3 }
+                } else if d.z > 0. {
+                    4
+                } else {
+                    5
+                };
+                let map = &faces[face];
+                match map.project(&world) {
+                    Some(pos) => (map, pos, settings),
+                    None => return 1.,
+                }
+            },
+        };
+
+        // Clamping the radius keeps the filter from overflowing or taking forever
+        let pcf = settings.pcf.min(MAX_PCF);
+        let reach = pcf as i32;
+        let (px, py, z) = (pos[0] as i32, pos[1] as i32, map.toward_viewer(pos[2], settings.bias));
+        let mut lit: usize = 0;
+        for dy in -reach..=reach {
+            for dx in -reach..=reach {
+                if !map.depth(px.saturating_add(dx), py.saturating_add(dy)).is_some_and(|depth| z < depth) {
+                    lit += 1;
+                }
+            }
+        }
+        lit as f64 / (2 * pcf + 1).pow(2) as f64
+    }
+}
+
+// Light view space to shadow map pixels. z is left alone so that the bias is in world units
+fn to_map(p: &[f64; COLS], min: (f64, f64), scale: f64) -> [f64; COLS] {
+    [(p[0] - min.0) * scale, (p[1] - min.1) * scale, p[2], 1.]
+}
+
+impl fmt::Debug for ShadowMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShadowMap::Directional { settings, .. } => write!(f, "ShadowMap::Directional({:?})", settings),
+            ShadowMap::Point { settings, .. } => write!(f, "ShadowMap::Point({:?})", settings),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Color;
+
+    // Two triangles making a square facing up, `y` above the ground
+    fn square(m: &mut Vec<[f64; COLS]>, y: f64, half: f64) {
+        let (a, b, c, d) = ([-half, y, half, 1.], [half, y, half, 1.], [half, y, -half, 1.], [-half, y, -half, 1.]);
+        m.extend_from_slice(&[a, b, c, a, c, d]);
+    }
+
+    #[test]
+    fn occluder() {
+        // A small square floating over a large one
+        let mut casters = vec![];
+        square(&mut casters, 0., 50.);
+        square(&mut casters, 10., 10.);
+        let casters = Matrix::from(casters.as_slice());
+        let screen = ScreenBuilder {
+            shadows: Some(Shadows { resolution: 64, ..Default::default() }),
+            ..Default::default()
+        }
+        .create();
+        let white = Color::new(255, 255, 255);
+        let lights = [
+            Light::new(Vector::new(0., 1., 0.), white),
+            Light::point(Vector::new(0., 40., 0.), white, [1., 0., 0.]),
+        ];
+        for light in &lights {
+            let map = ShadowMap::new(light, &casters, &screen).unwrap();
+            let at = |x, z| map.visibility(&Vector::new(x, 0., z));
+            // Right under the small square is in shadow, while the bias keeps the
+            // rest of the large square from shadowing itself
+            assert_eq!(0., at(0., 0.));
+            assert_eq!((1., 1.), (at(40., 40.), at(-30., 0.)));
+            // The top of the small square is lit too
+            assert_eq!(1., map.visibility(&Vector::new(0., 10., 0.)));
+        }
+        // Filtering softens the edge of the shadow
+        let map = ShadowMap::new(&lights[0], &casters, &screen).unwrap();
+        let edge = map.visibility(&Vector::new(10., 0., 0.));
+        assert!(edge > 0. && edge < 1.);
+        // Filters wider than MAX_PCF are clamped to it
+        let (mut wide, mut widest) = (screen.clone(), screen.clone());
+        wide.shadows = Some(Shadows { resolution: 64, pcf: MAX_PCF, ..Default::default() });
+        widest.shadows = Some(Shadows { resolution: 64, pcf: usize::MAX, ..Default::default() });
+        let visibility = |screen: &Screen| {
+            ShadowMap::new(&lights[0], &casters, screen).unwrap().visibility(&Vector::new(0., 0., 0.))
+        };
+        assert_eq!(visibility(&wide), visibility(&widest));
+    }
+}
@@ -0,0 +1,306 @@
+// Images that are mapped onto polygons by their texture coordinates
+use super::png::{self, ColorType};
+use super::Color;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Filter {
+    // Color of the closest texel
+    Nearest,
+    // Weighted average of the 4 closest texels
+    Bilinear,
+    // Bilinear samples from the two mip levels closest in size to the pixel,
+    // blended together
+    Trilinear,
+}
+
+#[derive(Debug)]
+pub struct ParseFilterError;
+
+impl FromStr for Filter {
+    type Err = ParseFilterError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "nearest" => Ok(Filter::Nearest),
+            "bilinear" => Ok(Filter::Bilinear),
+            "trilinear" => Ok(Filter::Trilinear),
+            _ => Err(ParseFilterError),
+        }
+    }
+}
+
+// One level of the mip pyramid. Rows go top to bottom
+struct Level {
+    width: usize,
+    height: usize,
+    texels: Vec<Color>,
+}
+
+impl Level {
+    fn texel(&self, x: i64, y: i64) -> Color {
+        // Textures repeat in both directions
+        let x = x.rem_euclid(self.width as i64) as usize;
+        let y = y.rem_euclid(self.height as i64) as usize;
+        self.texels[y * self.width + x]
+    }
+
+    // Half the size, each texel averaging the 2x2 texels it covers
+    fn downsample(&self) -> Level {
+        let (width, height) = ((self.width / 2).max(1), (self.height / 2).max(1));
+        let mut texels = Vec::with_capacity(width * height);
+        for y in 0..height as i64 {
+            for x in 0..width as i64 {
+                let quad = [
+                    self.texel(2 * x, 2 * y),
+                    self.texel(2 * x + 1, 2 * y),
+                    self.texel(2 * x, 2 * y + 1),
+                    self.texel(2 * x + 1, 2 * y + 1),
+                ];
+                let average = |channel: fn(&Color) -> u8| {
+                    let sum: u32 = quad.iter().map(|c| u32::from(channel(c))).sum();
+                    ((sum + 2) / 4) as u8
+                };
+                texels.push(Color::new(average(|c| c.red), average(|c| c.green), average(|c| c.blue)));
+            }
+        }
+        Level { width, height, texels }
+    }
+
+    fn nearest(&self, u: f64, v: f64) -> Color {
+        let x = u * self.width as f64;
+        let y = (1. - v) * self.height as f64;
+        self.texel(x.floor() as i64, y.floor() as i64)
+    }
+
+    fn bilinear(&self, u: f64, v: f64) -> [f64; 3] {
+        // Texel centers are at half integers
+        let x = u * self.width as f64 - 0.5;
+        let y = (1. - v) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let lerp = |a: Color, b: Color, t: f64| {
+            let mix = |a: u8, b: u8| f64::from(a) + (f64::from(b) - f64::from(a)) * t;
+            [mix(a.red, b.red), mix(a.green, b.green), mix(a.blue, b.blue)]
+        };
+        let top = lerp(self.texel(x0, y0), self.texel(x0 + 1, y0), tx);
+        let bottom = lerp(self.texel(x0, y0 + 1), self.texel(x0 + 1, y0 + 1), tx);
+        let mut out = [0.; 3];
+        for (i, out) in out.iter_mut().enumerate() {
+            *out = top[i] + (bottom[i] - top[i]) * ty;
+        }
+        out
+    }
+}
+
+pub struct Texture {
+    pub width: usize,
+    pub height: usize,
+    // Mip pyramid, from the full size image down to 1x1
+    levels: Vec<Level>,
+    pub filter: Filter,
+}
+
+impl Texture {
+    pub fn new(width: usize, height: usize, texels: Vec<Color>, filter: Filter) -> Texture {
+        assert_eq!(width * height, texels.len(), "Texture doesn't match its dimensions!");
+        assert!(!texels.is_empty(), "Textures can't be empty!");
+        let mut levels = vec![Level { width, height, texels }];
+        while let Some(level) = levels.last().filter(|l| l.width > 1 || l.height > 1) {
+            let next = level.downsample();
+            levels.push(next);
+        }
+        Texture { width, height, levels, filter }
+    }
+
+    // A square texture with every level of its mip pyramid given, from `size` by `size`
+    // down to 1x1, instead of averaging them from the full size one
+    pub(super) fn from_mips(size: usize, mips: Vec<Vec<Color>>, filter: Filter) -> Texture {
+        let levels: Vec<_> = mips
+            .into_iter()
+            .enumerate()
+            .map(|(i, texels)| {
+                let width = (size >> i).max(1);
+                assert_eq!(width * width, texels.len(), "Mip level doesn't match its dimensions!");
+                Level { width, height: width, texels }
+            })
+            .collect();
+        assert_eq!(levels.last().map(|level| level.width), Some(1), "Mip pyramid has to go down to 1x1!");
+        Texture { width: size, height: size, levels, filter }
+    }
+
+    // Load a png or a ppm, depending on the extension
+    pub fn open<P: AsRef<Path>>(path: P, filter: Filter) -> io::Result<Texture> {
+        let data = fs::read(&path)?;
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("png") => Texture::from_png(&data, filter),
+            _ => Texture::from_ppm(&data, filter),
+        }
+    }
+
+    pub fn from_png(data: &[u8], filter: Filter) -> io::Result<Texture> {
+        let (width, height, color, data) = png::decode(data)?;
+        // Alpha is ignored
+        let texels = data
+            .chunks_exact(color.channels())
+            .map(|p| match color {
+                ColorType::Gray => Color::new(p[0], p[0], p[0]),
+                ColorType::Rgb | ColorType::Rgba => Color::new(p[0], p[1], p[2]),
+            })
+            .collect();
+        Ok(Texture::new(width, height, texels, filter))
+    }
+
+    // Plain (P3) or binary (P6) ppm
+    pub fn from_ppm(data: &[u8], filter: Filter) -> io::Result<Texture> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let binary = match data.get(..2) {
+            Some(b"P3") => false,
+            Some(b"P6") => true,
+            _ => return Err(invalid("Not a ppm!")),
+        };
+
+        // Width, height and maximum value, separated by whitespace and comments
+        let mut pos = 2;
+        let next = |pos: &mut usize| -> io::Result<usize> {
+            loop {
+                match data.get(*pos) {
+                    Some(b'#') => {
+                        while data.get(*pos).is_some_and(|&b| b != b'\n') {
+                            *pos += 1;
+                        }
+                    },
+                    Some(b) if b.is_ascii_whitespace() => *pos += 1,
+                    _ => break,
+                }
+            }
+            let start = *pos;
+            while data.get(*pos).is_some_and(u8::is_ascii_digit) {
+                *pos += 1;
+            }
+            let digits = std::str::from_utf8(&data[start..*pos]).unwrap();
+            digits.parse().map_err(|_| invalid("Expected a number in the ppm!"))
+        };
+        let (width, height, max) = (next(&mut pos)?, next(&mut pos)?, next(&mut pos)?);
+        if max == 0 || max > 255 {
+            return Err(invalid("Only 8 bit ppms are supported!"));
+        }
+
+        if width == 0 || height == 0 {
+            return Err(invalid("ppm has no pixels!"));
+        }
+
+        let size = width.checked_mul(height).and_then(|n| n.checked_mul(3));
+        let size = size.ok_or_else(|| invalid("ppm is too large!"))?;
+        let values = if binary {
+            // A single whitespace character comes before the pixels
+            let pixels = data.get(pos + 1..).and_then(|pixels| pixels.get(..size));
+            pixels.ok_or_else(|| invalid("ppm is too short!"))?.to_vec()
+        } else {
+            // Every value takes up at least a byte
+            let mut values = Vec::with_capacity(size.min(data.len()));
+            for _ in 0..size {
+                values.push(next(&mut pos)?.min(max) as u8);
+            }
+            values
+        };
+        // Scale values up to 0..=255
+        let scale = |v: u8| (usize::from(v) * 255 / max) as u8;
+        let texels = values.chunks_exact(3).map(|p| Color::new(scale(p[0]), scale(p[1]), scale(p[2]))).collect();
+        Ok(Texture::new(width, height, texels, filter))
+    }
+
+    // Color at texture coordinates (u, v). (0, 0) is the bottom left corner of the
+    // image and (1, 1) is the top right. Always samples the full size image
+    pub fn sample(&self, u: f64, v: f64) -> Color {
+        match self.filter {
+            Filter::Nearest => self.levels[0].nearest(u, v),
+            Filter::Bilinear | Filter::Trilinear => to_color(self.levels[0].bilinear(u, v)),
+        }
+    }
+
+    // Like `sample`, where `ddx` and `ddy` are how much (u, v) change from one pixel
+    // to the next, horizontally and vertically. Trilinear filtering picks the mip
+    // levels with about one texel per pixel
+    pub fn sample_grad(&self, u: f64, v: f64, ddx: [f64; 2], ddy: [f64; 2]) -> Color {
+        if self.filter != Filter::Trilinear {
+            return self.sample(u, v);
+        }
+        // Texels covered by a pixel along its longer side
+        let (w, h) = (self.width as f64, self.height as f64);
+        let footprint = f64::hypot(ddx[0] * w, ddx[1] * h).max(f64::hypot(ddy[0] * w, ddy[1] * h));
+        let lod = footprint.log2().max(0.).min((self.levels.len() - 1) as f64);
+
+        let (fine, t) = (lod.floor() as usize, lod.fract());
+        let a = self.levels[fine].bilinear(u, v);
+        if t == 0. {
+            return to_color(a);
+        }
+        let b = self.levels[fine + 1].bilinear(u, v);
+        to_color([a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t, a[2] + (b[2] - a[2]) * t])
+    }
+}
+
+fn to_color(c: [f64; 3]) -> Color {
+    Color::new(c[0].round() as u8, c[1].round() as u8, c[2].round() as u8)
+}
+
+// The texels aren't worth printing
+impl fmt::Debug for Texture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Texture({}x{}, {:?})", self.width, self.height, self.filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ppm_and_sampling() {
+        // 2x1: red on the left, blue on the right. Values go up to 15
+        let plain = b"P3\n# comment\n2 1\n15\n15 0 0 0 0 15\n";
+        let mut binary = b"P6 2 1 255\n".to_vec();
+        binary.extend_from_slice(&[255, 0, 0, 0, 0, 255]);
+        for ppm in &[&plain[..], &binary[..]] {
+            let mut texture = Texture::from_ppm(ppm, Filter::Nearest).unwrap();
+            assert_eq!((2, 1), (texture.width, texture.height));
+            let c = texture.sample(0.25, 0.5);
+            assert_eq!((255, 0, 0), (c.red, c.green, c.blue));
+            // Wraps around
+            let c = texture.sample(-0.25, 0.5);
+            assert_eq!((0, 0, 255), (c.red, c.green, c.blue));
+
+            // Halfway between the two texel centers
+            texture.filter = Filter::Bilinear;
+            let c = texture.sample(0.5, 0.5);
+            assert_eq!((128, 0, 128), (c.red, c.green, c.blue));
+        }
+        assert!(Texture::from_ppm(b"P6 2 1 255\n", Filter::Nearest).is_err());
+        // Empty images, and sizes that don't fit in memory, are errors instead of panics
+        for ppm in &[&b"P6 0 0 255\n"[..], b"P3 0 4 255\n", b"P6 99999999999 99999999999 255\n"] {
+            let err = Texture::from_ppm(ppm, Filter::Nearest).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        }
+    }
+
+    #[test]
+    fn trilinear_mips() {
+        let (black, white) = (Color::new(0, 0, 0), Color::new(255, 255, 255));
+        let texture = Texture::new(2, 2, vec![black, white, white, black], Filter::Trilinear);
+        assert_eq!(2, texture.levels.len());
+        let top = &texture.levels[1].texels[0];
+        assert_eq!((128, 128, 128), (top.red, top.green, top.blue));
+
+        // Up close, it's filtered like bilinear
+        let c = texture.sample_grad(0.25, 0.75, [0.01, 0.], [0., 0.01]);
+        assert_eq!((0, 0, 0), (c.red, c.green, c.blue));
+        // Far away, the whole texture fits in a pixel
+        let c = texture.sample_grad(0.25, 0.75, [1., 0.], [0., 1.]);
+        assert_eq!((128, 128, 128), (c.red, c.green, c.blue));
+    }
+}
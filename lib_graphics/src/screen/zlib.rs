@@ -0,0 +1,415 @@
+// zlib (RFC 1950) wrapped deflate (RFC 1951) stream encoder and decoder.
+// The encoder puts everything into one deflate block that uses the fixed Huffman
+// codes, with LZ77 matches found through hash chains. The decoder reads every
+// kind of block.
+
+use std::io;
+
+const WINDOW_SIZE: usize = 1 << 15;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: usize = 15;
+const HASH_MASK: usize = (1 << HASH_BITS) - 1;
+// How many earlier positions to try before settling for the best match so far
+const MAX_CHAIN: usize = 64;
+const NIL: usize = usize::MAX;
+
+// Length codes 257..=285: base length and number of extra bits
+#[rustfmt::skip]
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+#[rustfmt::skip]
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+// Distance codes 0..=29: base distance and number of extra bits
+#[rustfmt::skip]
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+#[rustfmt::skip]
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+// Order that the code lengths of the code length alphabet are stored in
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+const MAX_BITS: usize = 15;
+
+// Deflate (and GIF's LZW) pack bits starting from the least significant bit of each byte
+pub(super) struct BitWriter {
+    out: Vec<u8>,
+    bits: u32,
+    nbits: u32,
+}
+
+impl BitWriter {
+    pub(super) fn new(capacity: usize) -> BitWriter {
+        BitWriter { out: Vec::with_capacity(capacity), bits: 0, nbits: 0 }
+    }
+
+    pub(super) fn write(&mut self, value: u32, n: u32) {
+        self.bits |= value << self.nbits;
+        self.nbits += n;
+        while self.nbits >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.nbits -= 8;
+        }
+    }
+
+    // Huffman codes are stored most significant bit first
+    fn write_code(&mut self, code: u32, n: u32) {
+        let reversed = code.reverse_bits() >> (32 - n);
+        self.write(reversed, n);
+    }
+
+    pub(super) fn finish(mut self) -> Vec<u8> {
+        if self.nbits > 0 {
+            self.out.push(self.bits as u8);
+        }
+        self.out
+    }
+
+    fn literal(&mut self, lit: u16) {
+        let lit = u32::from(lit);
+        match lit {
+            0..=143 => self.write_code(0x30 + lit, 8),
+            144..=255 => self.write_code(0x190 + lit - 144, 9),
+            256..=279 => self.write_code(lit - 256, 7),
+            _ => self.write_code(0xc0 + lit - 280, 8),
+        }
+    }
+
+    fn matched(&mut self, len: usize, dist: usize) {
+        let code = LENGTH_BASE.iter().rposition(|&base| usize::from(base) <= len).unwrap();
+        self.literal(257 + code as u16);
+        self.write((len - usize::from(LENGTH_BASE[code])) as u32, u32::from(LENGTH_EXTRA[code]));
+
+        let code = DIST_BASE.iter().rposition(|&base| usize::from(base) <= dist).unwrap();
+        self.write_code(code as u32, 5);
+        self.write((dist - usize::from(DIST_BASE[code])) as u32, u32::from(DIST_EXTRA[code]));
+    }
+}
+
+fn hash(data: &[u8], i: usize) -> usize {
+    let h = (usize::from(data[i]) << 10) ^ (usize::from(data[i + 1]) << 5) ^ usize::from(data[i + 2]);
+    h & HASH_MASK
+}
+
+struct Matcher {
+    // Most recent position for each hash
+    head: Vec<usize>,
+    // Previous position with the same hash, indexed by position % WINDOW_SIZE
+    prev: Vec<usize>,
+}
+
+impl Matcher {
+    fn new() -> Matcher {
+        Matcher { head: vec![NIL; 1 << HASH_BITS], prev: vec![NIL; WINDOW_SIZE] }
+    }
+
+    fn insert(&mut self, data: &[u8], i: usize) {
+        if i + MIN_MATCH <= data.len() {
+            let h = hash(data, i);
+            self.prev[i % WINDOW_SIZE] = self.head[h];
+            self.head[h] = i;
+        }
+    }
+
+    // Returns (length, distance) of the longest earlier match for `data[i..]`
+    fn longest(&self, data: &[u8], i: usize) -> (usize, usize) {
+        if i + MIN_MATCH > data.len() {
+            return (0, 0);
+        }
+        let max_len = MAX_MATCH.min(data.len() - i);
+        let (mut best_len, mut best_dist) = (0, 0);
+        let mut candidate = self.head[hash(data, i)];
+        let mut chain = 0;
+        while candidate != NIL && i - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+            let len = data[candidate..]
+                .iter()
+                .zip(&data[i..i + max_len])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if len > best_len {
+                best_len = len;
+                best_dist = i - candidate;
+                if len == max_len {
+                    break;
+                }
+            }
+            let next = self.prev[candidate % WINDOW_SIZE];
+            // The slot may have been reused by a newer position
+            if next == NIL || next >= candidate {
+                break;
+            }
+            candidate = next;
+            chain += 1;
+        }
+        (best_len, best_dist)
+    }
+}
+
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new(data.len() / 2 + 16);
+    // CMF: deflate with a 32K window, FLG: default compression, no dictionary
+    writer.write(0x78, 8);
+    writer.write(0x9c, 8);
+    // BFINAL = 1, BTYPE = 01 (fixed Huffman codes)
+    writer.write(1, 1);
+    writer.write(1, 2);
+
+    let mut matcher = Matcher::new();
+    let mut i = 0;
+    while i < data.len() {
+        let (len, dist) = matcher.longest(data, i);
+        if len >= MIN_MATCH {
+            writer.matched(len, dist);
+            for pos in i..i + len {
+                matcher.insert(data, pos);
+            }
+            i += len;
+        } else {
+            writer.literal(u16::from(data[i]));
+            matcher.insert(data, i);
+            i += 1;
+        }
+    }
+    // End of block
+    writer.literal(256);
+
+    let mut out = writer.finish();
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    // Largest number of bytes that can be summed before `b` overflows a u32
+    const CHUNK: usize = 5552;
+    let (mut a, mut b) = (1, 0);
+    for chunk in data.chunks(CHUNK) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// Reads bits the way `BitWriter` writes them
+pub(super) struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u32,
+    nbits: u32,
+}
+
+impl<'a> BitReader<'a> {
+    pub(super) fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader { data, pos: 0, bits: 0, nbits: 0 }
+    }
+
+    pub(super) fn read(&mut self, n: u32) -> io::Result<u32> {
+        while self.nbits < n {
+            let byte = *self.data.get(self.pos).ok_or_else(|| invalid("Deflate stream ended early!"))?;
+            self.pos += 1;
+            self.bits |= u32::from(byte) << self.nbits;
+            self.nbits += 8;
+        }
+        let value = self.bits & ((1 << n) - 1);
+        self.bits >>= n;
+        self.nbits -= n;
+        Ok(value)
+    }
+
+    // Skip to the start of the next byte. There are never more than 7 bits left over
+    fn align(&mut self) {
+        self.bits = 0;
+        self.nbits = 0;
+    }
+
+    fn remaining(&self) -> &'a [u8] {
+        &self.data[self.pos..]
+    }
+}
+
+// Canonical Huffman code, stored as the number of codes of each length and the
+// symbols sorted by their codes
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    // `lengths` has the code length of every symbol. 0 means the symbol isn't used
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0; MAX_BITS + 1];
+        for &len in lengths {
+            counts[usize::from(len)] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0; MAX_BITS + 1];
+        for len in 1..MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[usize::from(offsets[usize::from(len)])] = symbol as u16;
+                offsets[usize::from(len)] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    // Codes are read one bit at a time, most significant bit first. The codes of
+    // each length are consecutive numbers, starting at `first`
+    fn decode(&self, reader: &mut BitReader) -> io::Result<u16> {
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for &count in &self.counts[1..] {
+            code |= reader.read(1)? as i32;
+            let count = i32::from(count);
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("Invalid Huffman code!"))
+    }
+
+    fn fixed() -> (Huffman, Huffman) {
+        let mut lengths = [8; 288];
+        lengths[144..256].iter_mut().for_each(|len| *len = 9);
+        lengths[256..280].iter_mut().for_each(|len| *len = 7);
+        (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+    }
+
+    // Literal/length and distance codes of a block with dynamic Huffman codes
+    fn dynamic(reader: &mut BitReader) -> io::Result<(Huffman, Huffman)> {
+        let literals = reader.read(5)? as usize + 257;
+        let distances = reader.read(5)? as usize + 1;
+        let code_lengths = reader.read(4)? as usize + 4;
+
+        let mut lengths = [0; 19];
+        for &symbol in &CODE_LENGTH_ORDER[..code_lengths] {
+            lengths[symbol] = reader.read(3)? as u8;
+        }
+        let code = Huffman::new(&lengths);
+
+        // The code lengths of both alphabets are run length encoded together
+        let total = literals + distances;
+        let mut lengths = Vec::with_capacity(total);
+        while lengths.len() < total {
+            let (len, repeat) = match code.decode(reader)? {
+                len @ 0..=15 => (len as u8, 1),
+                16 => {
+                    let prev = *lengths.last().ok_or_else(|| invalid("Nothing to repeat!"))?;
+                    (prev, 3 + reader.read(2)?)
+                },
+                17 => (0, 3 + reader.read(3)?),
+                _ => (0, 11 + reader.read(7)?),
+            };
+            lengths.extend((0..repeat).map(|_| len));
+        }
+        if lengths.len() > total {
+            return Err(invalid("Too many code lengths!"));
+        }
+        Ok((Huffman::new(&lengths[..literals]), Huffman::new(&lengths[literals..])))
+    }
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> io::Result<()> {
+    loop {
+        match literals.decode(reader)? {
+            literal @ 0..=255 => out.push(literal as u8),
+            256 => return Ok(()),
+            symbol => {
+                let code = usize::from(symbol - 257);
+                if code >= LENGTH_BASE.len() {
+                    return Err(invalid("Invalid length code!"));
+                }
+                let len = usize::from(LENGTH_BASE[code]) + reader.read(u32::from(LENGTH_EXTRA[code]))? as usize;
+
+                let code = usize::from(distances.decode(reader)?);
+                if code >= DIST_BASE.len() {
+                    return Err(invalid("Invalid distance code!"));
+                }
+                let dist = usize::from(DIST_BASE[code]) + reader.read(u32::from(DIST_EXTRA[code]))? as usize;
+                if dist > out.len() {
+                    return Err(invalid("Distance is too far back!"));
+                }
+                // The match can overlap with what it's copying
+                let start = out.len() - dist;
+                for i in start..start + len {
+                    out.push(out[i]);
+                }
+            },
+        }
+    }
+}
+
+pub fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    if data.len() < 6 || (u16::from(data[0]) << 8 | u16::from(data[1])) % 31 != 0 {
+        return Err(invalid("Invalid zlib header!"));
+    }
+    if data[0] & 0x0f != 8 || data[1] & 0x20 != 0 {
+        return Err(invalid("Unsupported zlib stream!"));
+    }
+
+    let mut reader = BitReader::new(&data[2..]);
+    let mut out = vec![];
+    loop {
+        let last = reader.read(1)? == 1;
+        match reader.read(2)? {
+            // Stored
+            0 => {
+                reader.align();
+                let len = reader.read(16)?;
+                if len != !reader.read(16)? & 0xffff {
+                    return Err(invalid("Corrupt stored block!"));
+                }
+                for _ in 0..len {
+                    out.push(reader.read(8)? as u8);
+                }
+            },
+            1 => {
+                let (literals, distances) = Huffman::fixed();
+                inflate_block(&mut reader, &mut out, &literals, &distances)?;
+            },
+            2 => {
+                let (literals, distances) = Huffman::dynamic(&mut reader)?;
+                inflate_block(&mut reader, &mut out, &literals, &distances)?;
+            },
+            _ => return Err(invalid("Invalid block type!")),
+        }
+        if last {
+            break;
+        }
+    }
+
+    reader.align();
+    match reader.remaining() {
+        [a, b, c, d, ..] if u32::from_be_bytes([*a, *b, *c, *d]) == adler32(&out) => Ok(out),
+        _ => Err(invalid("zlib checksum doesn't match!")),
+    }
+}
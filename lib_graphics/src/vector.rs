@@ -1,5 +1,6 @@
 use crate::matrix::COLS;
-use std::ops::{Mul, Sub};
+use std::collections::HashMap;
+use std::ops::{Add, Mul, Sub};
 
 #[derive(Debug, Clone)]
 pub struct Vector {
@@ -47,6 +48,42 @@ impl Vector {
 
         a.cross_product(&b)
     }
+
+    // Normal at each corner of a list of triangles, averaged with the other triangles
+    // that share that corner. Triangles meeting at a sharp angle aren't averaged
+    // together so that edges like the ones on a box stay sharp
+    pub fn vertex_normals(polygons: &[[f64; COLS]]) -> Vec<Vector> {
+        // Faces meeting at more than 60 degrees form a crease
+        const CREASE: f64 = 0.5;
+        // Positions are compared bit for bit. Adding 0 turns -0 into 0
+        let key = |p: &[f64; COLS]| ((p[0] + 0.).to_bits(), (p[1] + 0.).to_bits(), (p[2] + 0.).to_bits());
+
+        // Face normals are left unnormalized so bigger faces count for more
+        let faces: Vec<Vector> = polygons.chunks_exact(3).map(Vector::calculate_normal).collect();
+        let units: Vec<Vector> = faces
+            .iter()
+            .map(|n| if n.dot_product(n) > 0. { Vector::normalized(n) } else { n.clone() })
+            .collect();
+        let mut shared: HashMap<_, Vec<usize>> = HashMap::new();
+        for (i, point) in polygons.iter().enumerate() {
+            shared.entry(key(point)).or_default().push(i / 3);
+        }
+
+        polygons
+            .iter()
+            .enumerate()
+            .map(|(i, point)| {
+                let face = &units[i / 3];
+                let mut normal = Vector::new(0., 0., 0.);
+                for &other in &shared[&key(point)] {
+                    if other == i / 3 || units[other].dot_product(face) >= CREASE {
+                        normal = normal + &faces[other];
+                    }
+                }
+                normal
+            })
+            .collect()
+    }
 }
 
 impl From<&[f64]> for Vector {
@@ -57,6 +94,13 @@ impl From<&[f64]> for Vector {
     }
 }
 
+impl Add<&Self> for Vector {
+    type Output = Vector;
+    fn add(self, rhs: &Vector) -> Vector {
+        Vector::new(self.x + rhs.x, self.y + rhs.y, self.z + rhs.z)
+    }
+}
+
 impl Sub<&Self> for Vector {
     type Output = Vector;
     fn sub(self, rhs: &Vector) -> Vector {
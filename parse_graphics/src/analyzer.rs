@@ -1,13 +1,13 @@
-use lib_graphics::{Color, Light, Reflection, Shine, Vector};
+use lib_graphics::animation::{self, Easing, Spline};
+use lib_graphics::screen::{shadow, Filter, LineCap, Pattern};
+use lib_graphics::{Color, Light, Reflection, Shading, Shine, Vector};
 use std::convert::TryInto;
-use std::error::Error;
-use std::fmt::{self, Debug, Display};
 use std::fs;
-use std::num::TryFromIntError;
+use std::sync::Arc;
 
-use super::ast::{
-    self, AstIntoError, AstNode, Axis, Expression, Number, ParseAxisError, ParseCommand
-};
+use super::ast::{self, AstNode, Axis, Expression, Number, ParseCommand};
+use super::error::{MdlError, MdlErrors};
+use super::semantic;
 use super::todo::{Symbol, ToDoList, eval_usize};
 
 #[derive(Clone, Debug)]
@@ -16,80 +16,118 @@ pub enum Command {
     Pop(),
     Display(),
     Save(String),
+    SaveCoordSystem(String),
     Translate(Expression, Expression, Expression),
     Scale(Expression, Expression, Expression),
     Rotate(Axis, Expression),
-    Cuboid(Expression, Expression, Expression, Expression, Expression, Expression),
-    Sphere(Expression, Expression, Expression, Expression),
-    Torus(Expression, Expression, Expression, Expression, Expression),
-    Line(Expression, Expression, Expression, Expression, Expression, Expression),
+    // Shapes end with the name of the coordinate system they're drawn in, if it isn't
+    // the current one. Each end of a line can have its own
+    Cuboid(Expression, Expression, Expression, Expression, Expression, Expression, Option<String>),
+    Sphere(Expression, Expression, Expression, Expression, Option<String>),
+    Torus(Expression, Expression, Expression, Expression, Expression, Option<String>),
+    Line(Expression, Expression, Expression, Option<String>, Expression, Expression, Expression, Option<String>, LineOptions),
     Frames(Expression),
     Basename(String),
-    Vary(String, Expression, Expression, Expression, Expression),
-    Mesh(String),
+    // Start and end frames and values, and how to ease between them
+    Vary(String, Expression, Expression, Expression, Expression, Easing),
+    // Keyframes the knob follows a spline through: their frames, values, and slopes
+    // for Hermite splines
+    VarySpline(String, Spline, Vec<(Expression, Expression, Option<Expression>)>),
+    Set(String, Expression),
+    SetKnobs(Expression),
+    SaveKnobs(String),
+    // Start and end frames, and the knob lists to go from and to
+    Tween(Expression, Expression, String, String),
+    Mesh(String, Option<String>),
+    // Eye, aim point, and the near and far planes
+    Camera(Expression, Expression, Expression, Expression, Expression, Expression, Option<(Expression, Expression)>),
+    Focal(Expression),
+    // The shading mode, and whether to draw the wireframe over it
+    Shading(Shading, bool),
+    // Texture file and how to sample it. None turns texturing off
+    Texture(Option<(String, Filter)>),
+    // None turns patterns off
+    Pattern(Option<Arc<Pattern>>),
     Constants(NOOP),
-    Light(NOOP),
+    Light(Light),
+    Ambient(Color),
+    // Shadow bias and filter radius
+    Shadows(Option<f64>, Option<usize>),
 }
 
 #[derive(Clone, Debug)]
 pub struct NOOP;
 
-#[derive(Debug)]
-pub enum ParseError {
-    AstIntoError,
-    ParseError,
-    SemanticError(String),
+// How a single line is drawn. None keeps the screen's setting
+#[derive(Clone, Debug, Default)]
+pub struct LineOptions {
+    pub width: Option<Expression>,
+    pub cap: Option<LineCap>,
+    pub smooth: Option<bool>,
 }
-impl ParseError {
-    pub fn sem_error<T: Debug>(data: &T) -> ParseError {
-        // TODO FIXME: Fix this UGLY HIDEOUS HACK
-        ParseError::SemanticError(format!("{:#?}", data).replace("\n", "NEWLINE"))
-    }
-}
-impl Display for ParseError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
-    }
-}
-impl Error for ParseError {}
-impl From<AstIntoError> for ParseError {
-    fn from(_: AstIntoError) -> ParseError {
-        ParseError::AstIntoError
+
+// Parse and analyze a script, and check it for mistakes before it's run
+pub fn file(filename: &str) -> Result<ToDoList, MdlErrors> {
+    let source = fs::read_to_string(filename)
+        .map_err(|err| MdlError::new(format!("Error reading file: {}", err)).in_file(filename, ""))?;
+    let nodes = ast::parse(&source).map_err(|err| err.in_file(filename, &source))?;
+    let mut todo = analyze_nodes(&nodes).map_err(|errs| errs.in_file(filename, &source))?;
+    let errors = semantic::check(&todo);
+    if !errors.is_empty() {
+        return Err(MdlErrors(errors).in_file(filename, &source));
     }
+    todo.script = Some((filename.to_owned(), source));
+    Ok(todo)
 }
-impl From<ParseAxisError> for ParseError {
-    fn from(_: ParseAxisError) -> ParseError {
-        ParseError::ParseError
+
+pub(crate) fn analyze_nodes(nodes: &[AstNode]) -> Result<ToDoList, MdlErrors> {
+    let mut todo = ToDoList::default();
+    let mut errors = vec![];
+    for node in nodes {
+        let first = todo.ops.len();
+        let result = analyze(node, &mut todo);
+        // Errors point back at their command, including the ones while drawing it
+        if let AstNode::MdlCommand { span, .. } = node {
+            if let Err(err) = result {
+                errors.push(err.or_span(*span));
+            }
+            for operation in &mut todo.ops[first..] {
+                operation.span = *span;
+            }
+        } else if let Err(err) = result {
+            errors.push(err);
+        }
     }
-}
-impl From<TryFromIntError> for ParseError {
-    fn from(err: TryFromIntError) -> ParseError {
-        ParseError::SemanticError(format!("{:?}", err))
+    if errors.is_empty() {
+        Ok(todo)
+    } else {
+        Err(MdlErrors(errors))
     }
 }
 
-pub fn file(filename: &str) -> Result<ToDoList, ParseError> {
-    let file = fs::read_to_string(filename).expect("Error reading file!");
-    let nodes = ast::parse(&file).expect("Failed while performing parsing!");
-    analyze_nodes(&nodes)
+// Name of the coordinate system at the end of a shape, if it has one
+fn coord_system(arg: Option<&AstNode>) -> Result<Option<String>, MdlError> {
+    match arg {
+        None => Ok(None),
+        Some(AstNode::Ident(name)) => Ok(Some(name.to_owned())),
+        Some(arg) => Err(MdlError::sem_error(arg)),
+    }
 }
 
-fn analyze_nodes(nodes: &[AstNode]) -> Result<ToDoList, ParseError> {
-    let mut todo = ToDoList::default();
-    for node in nodes {
-        analyze(node, &mut todo)?;
-    }
-    Ok(todo)
+// Color channels from 0 to 255. Anything outside of that range is clamped
+fn channel(n: &Number) -> u8 {
+    let n: f64 = n.into();
+    n.clamp(0., 255.).round() as u8
 }
 
 #[allow(clippy::many_single_char_names)]
-fn analyze(node: &AstNode, todo: &mut ToDoList) -> Result<(), ParseError> {
+fn analyze(node: &AstNode, todo: &mut ToDoList) -> Result<(), MdlError> {
     use AstNode::*;
     use Command as Cmd;
     use Number::*;
     use ParseCommand as PCmd;
-    use ParseError as PErr;
-    if let AstNode::MdlCommand { command, args } = node {
+    use MdlError as PErr;
+    if let AstNode::MdlCommand { command, args, .. } = node {
         // TODO: Iterate through `args` when we eventually need to do a
         // post order traversal on the Ast
         // In which case, make the `node` argument mutable, then replace
@@ -105,6 +143,13 @@ fn analyze(node: &AstNode, todo: &mut ToDoList) -> Result<(), ParseError> {
                     Err(PErr::sem_error(&node))
                 }
             },
+            PCmd::SaveCoordSystem => {
+                if let Ident(name) = &args[0] {
+                    todo.push_op(Cmd::SaveCoordSystem(name.to_owned()), None, None)
+                } else {
+                    Err(PErr::sem_error(&node))
+                }
+            },
 
             PCmd::Translate | PCmd::Scale => {
                 let mut terms: Vec<Expression> = args[..3].iter().map(|val| match val {
@@ -184,7 +229,8 @@ fn analyze(node: &AstNode, todo: &mut ToDoList) -> Result<(), ParseError> {
                 let z = terms.pop().unwrap();
                 let y = terms.pop().unwrap();
                 let x = terms.pop().unwrap();
-                todo.push_op(Cmd::Cuboid(x, y, z, h, w, d), lighting, None)
+                let system = coord_system(args.get(end))?;
+                todo.push_op(Cmd::Cuboid(x, y, z, h, w, d, system), lighting, None)
             },
 
             PCmd::Sphere => {
@@ -205,7 +251,8 @@ fn analyze(node: &AstNode, todo: &mut ToDoList) -> Result<(), ParseError> {
                 let z = terms.pop().unwrap();
                 let y = terms.pop().unwrap();
                 let x = terms.pop().unwrap();
-                todo.push_op(Cmd::Sphere(x, y, z, r), lighting, None)
+                let system = coord_system(args.get(end))?;
+                todo.push_op(Cmd::Sphere(x, y, z, r, system), lighting, None)
             },
 
             PCmd::Torus => {
@@ -227,23 +274,53 @@ fn analyze(node: &AstNode, todo: &mut ToDoList) -> Result<(), ParseError> {
                 let z = terms.pop().unwrap();
                 let y = terms.pop().unwrap();
                 let x = terms.pop().unwrap();
-                todo.push_op(Cmd::Torus(x, y, z, r0, r1), lighting, None)
+                let system = coord_system(args.get(end))?;
+                todo.push_op(Cmd::Torus(x, y, z, r0, r1, system), lighting, None)
             },
 
             PCmd::Line => {
-                let mut terms: Vec<Expression> = args[..6].iter().map(|val| match val {
-                    Num(i) => Ok(i.into()),
-                    Expr(i) => Ok(i.clone()),
-                    Ident(i) => Ok(i.into()),
-                    _ => Err(PErr::sem_error(val)),
-                }).collect::<Result<_, _>>()?;
-                let z1 = terms.pop().unwrap();
-                let y1 = terms.pop().unwrap();
-                let x1 = terms.pop().unwrap();
-                let z0 = terms.pop().unwrap();
-                let y0 = terms.pop().unwrap();
-                let x0 = terms.pop().unwrap();
-                todo.push_op(Cmd::Line(x0, y0, z0, x1, y1, z1), None, None)
+                let mut options = LineOptions::default();
+                let mut args = args.as_slice();
+                while let Some((option, rest)) = args.split_first() {
+                    match option {
+                        LineWidth(width) => options.width = Some(match width.as_ref() {
+                            Num(i) => i.into(),
+                            Expr(i) => i.clone(),
+                            _ => return Err(PErr::sem_error(&node)),
+                        }),
+                        LineCap(cap) => options.cap = Some(*cap),
+                        Smooth(smooth) => options.smooth = Some(*smooth),
+                        _ => break,
+                    }
+                    args = rest;
+                }
+                // Then an optional lighting constant, and each end point followed by
+                // its optional coordinate system
+                let (lighting, args) = match args {
+                    [Ident(light), args @ ..] => (Some(light.to_owned()), args),
+                    args => (None, args),
+                };
+                let point = |args: &[AstNode]| -> Result<(Vec<Expression>, Option<String>), MdlError> {
+                    let terms = args[..3].iter().map(|val| match val {
+                        Num(i) => Ok(i.into()),
+                        Expr(i) => Ok(i.clone()),
+                        _ => Err(PErr::sem_error(val)),
+                    }).collect::<Result<_, _>>()?;
+                    let system = match args.get(3) {
+                        Some(Ident(name)) => Some(name.to_owned()),
+                        _ => None,
+                    };
+                    Ok((terms, system))
+                };
+                let (mut p0, system0) = point(args)?;
+                let (mut p1, system1) = point(&args[if system0.is_some() { 4 } else { 3 }..])?;
+                let z1 = p1.pop().unwrap();
+                let y1 = p1.pop().unwrap();
+                let x1 = p1.pop().unwrap();
+                let z0 = p0.pop().unwrap();
+                let y0 = p0.pop().unwrap();
+                let x0 = p0.pop().unwrap();
+                todo.push_op(Cmd::Line(x0, y0, z0, system0, x1, y1, z1, system1, options), lighting, None)
             },
 
             PCmd::Constants => {
@@ -269,10 +346,25 @@ fn analyze(node: &AstNode, todo: &mut ToDoList) -> Result<(), ParseError> {
                     },
                     _ => Err(PErr::sem_error(&node)),
                 }?;
+                // Optional emissive color and opacity
+                let (emissive, alpha) = match &args[10..] {
+                    [] => Ok((Color::default(), 1.)),
+                    [Num(a)] => Ok((Color::default(), a.into())),
+                    [Num(r), Num(g), Num(b)] => Ok((Color::new(channel(r), channel(g), channel(b)), 1.)),
+                    [Num(r), Num(g), Num(b), Num(a)] => {
+                        Ok((Color::new(channel(r), channel(g), channel(b)), a.into()))
+                    },
+                    _ => Err(PErr::sem_error(&node)),
+                }?;
+                if !(0. ..=1.).contains(&alpha) {
+                    return Err(PErr::sem_error(&node));
+                }
                 let reflection = Reflection {
                     ambient: Shine::new(ar, ag, ab),
                     diffuse: Shine::new(dr, dg, db),
                     specular: Shine::new(sr, sg, sb),
+                    emissive,
+                    alpha,
                 };
                 let lighting = Symbol::Constant(reflection);
                 todo.add_sym(name, lighting);
@@ -290,7 +382,7 @@ fn analyze(node: &AstNode, todo: &mut ToDoList) -> Result<(), ParseError> {
                     //Ident(i) => Ok(i.into()),
                     _ => Err(PErr::sem_error(&node))
                 }?;
-                todo.add_sym("FRAMES".into(), Symbol::Num(Number::PosInt(eval_usize(&frames, None))));
+                todo.add_sym("FRAMES".into(), Symbol::Num(Number::PosInt(eval_usize(&frames, None)?)));
                 todo.push_op(Cmd::Frames(frames), None, None)
             },
 
@@ -308,32 +400,143 @@ fn analyze(node: &AstNode, todo: &mut ToDoList) -> Result<(), ParseError> {
                 } else {
                     Err(PErr::sem_error(&node))
                 }?;
-                let mut terms: Vec<Expression> = args[1..5].iter().map(|val| match val {
+                let term = |val: &AstNode| match val {
                     Num(i) => Ok(i.into()),
                     Expr(i) => Ok(i.clone()),
                     Ident(i) => Ok(i.into()),
                     _ => Err(PErr::sem_error(val)),
-                }).collect::<Result<_, _>>()?;
-                let val1 = terms.pop().unwrap();
-                let val0 = terms.pop().unwrap();
-                let frame1 = terms.pop().unwrap();
-                let frame0 = terms.pop().unwrap();
-                todo.push_op(Cmd::Vary(knob, frame0, frame1, val0, val1), None, None)
+                };
+                let command = match &args[1..] {
+                    [frame0, frame1, val0, val1, rest @ ..] if !matches!(frame0, Spline(_)) => {
+                        let easing = match rest {
+                            [] => animation::Easing::Linear,
+                            [Easing(easing)] => *easing,
+                            _ => return Err(PErr::sem_error(&node)),
+                        };
+                        Cmd::Vary(knob, term(frame0)?, term(frame1)?, term(val0)?, term(val1)?, easing)
+                    },
+                    // Every keyframe has a frame and a value, and Hermite keyframes also
+                    // have a slope
+                    [Spline(spline), keyframes @ ..] => {
+                        let size = match spline {
+                            animation::Spline::CatmullRom => 2,
+                            animation::Spline::Hermite => 3,
+                        };
+                        if keyframes.len() < 2 * size || keyframes.len() % size != 0 {
+                            return Err(PErr::sem_error(&node));
+                        }
+                        let keyframes = keyframes
+                            .chunks(size)
+                            .map(|key| Ok((term(&key[0])?, term(&key[1])?, key.get(2).map(term).transpose()?)))
+                            .collect::<Result<_, PErr>>()?;
+                        Cmd::VarySpline(knob, *spline, keyframes)
+                    },
+                    _ => return Err(PErr::sem_error(&node)),
+                };
+                todo.push_op(command, None, None)
+            },
+
+            PCmd::Set | PCmd::SetKnobs | PCmd::SaveKnobs => {
+                let value = |val: &AstNode| match val {
+                    Num(i) => Ok(i.into()),
+                    Expr(i) => Ok(i.clone()),
+                    Ident(i) => Ok(i.into()),
+                    _ => Err(PErr::sem_error(&node)),
+                };
+                let command = match (command, args.as_slice()) {
+                    (PCmd::Set, [Ident(knob), val]) => Cmd::Set(knob.to_owned(), value(val)?),
+                    (PCmd::SetKnobs, [val]) => Cmd::SetKnobs(value(val)?),
+                    (PCmd::SaveKnobs, [Ident(list)]) => Cmd::SaveKnobs(list.to_owned()),
+                    _ => return Err(PErr::sem_error(&node)),
+                };
+                todo.push_op(command, None, None)
+            },
+
+            PCmd::Tween => {
+                let (frame0, frame1, list0, list1) = match args.as_slice() {
+                    [frame0, frame1, Ident(list0), Ident(list1)] => (frame0, frame1, list0, list1),
+                    _ => return Err(PErr::sem_error(&node)),
+                };
+                let frame = |val: &AstNode| match val {
+                    Num(i) => Ok(i.into()),
+                    Expr(i) => Ok(i.clone()),
+                    Ident(i) => Ok(i.into()),
+                    _ => Err(PErr::sem_error(val)),
+                };
+                let (frame0, frame1) = (frame(frame0)?, frame(frame1)?);
+                todo.push_op(Cmd::Tween(frame0, frame1, list0.to_owned(), list1.to_owned()), None, None)
             },
 
             PCmd::Light => {
+                // Lights are directional by default
+                let (light_type, args) = match args.as_slice() {
+                    [LightType(light_type), args @ ..] => (*light_type, args),
+                    args => (ast::LightType::Directional, args),
+                };
                 let (r, g, b) = match args[..3] {
                     [Num(PosInt(r)), Num(PosInt(g)), Num(PosInt(b))] => {
                         Ok((r.try_into()?, g.try_into()?, b.try_into()?))
                     },
                     _ => Err(PErr::sem_error(&node)),
                 }?;
-                let (x, y, z) = match &args[3..] {
+                let (x, y, z) = match &args[3..6] {
                     [Num(x), Num(y), Num(z)] => Ok((x.into(), y.into(), z.into())),
                     _ => Err(PErr::sem_error(&node)),
                 }?;
-                let light = Light::new(Vector::new(x, y, z), Color::new(r, g, b));
-                todo.add_light(light)
+                let (pos, color) = (Vector::new(x, y, z), Color::new(r, g, b));
+                // Without attenuation, lights are equally bright everywhere. Negative
+                // terms, or only 0s, would make the light infinitely bright or negative
+                let attenuation = |args: &[AstNode]| {
+                    let terms: [f64; 3] = match args {
+                        [] => [1., 0., 0.],
+                        [Num(c), Num(l), Num(q)] => [c.into(), l.into(), q.into()],
+                        _ => return Err(PErr::sem_error(&node)),
+                    };
+                    if terms.iter().any(|&term| term < 0.) {
+                        Err(PErr::new("Attenuation terms can't be negative"))
+                    } else if terms.iter().all(|&term| term == 0.) {
+                        Err(PErr::new("Attenuation needs a term greater than 0"))
+                    } else {
+                        Ok(terms)
+                    }
+                };
+                let light = match (light_type, &args[6..]) {
+                    (ast::LightType::Directional, []) => Light::new(pos, color),
+                    (ast::LightType::Point, args) => Light::point(pos, color, attenuation(args)?),
+                    (
+                        ast::LightType::Spot,
+                        [Num(dx), Num(dy), Num(dz), Num(inner), Num(outer), args @ ..],
+                    ) => {
+                        let direction = Vector::new(dx.into(), dy.into(), dz.into());
+                        let cone = (inner.into(), outer.into());
+                        Light::spot(pos, color, direction, cone, attenuation(args)?)
+                    },
+                    _ => return Err(PErr::sem_error(&node)),
+                };
+                todo.push_op(Cmd::Light(light), None, None)
+            },
+
+            PCmd::Ambient => {
+                let (r, g, b) = match args[..] {
+                    [Num(PosInt(r)), Num(PosInt(g)), Num(PosInt(b))] => {
+                        Ok((r.try_into()?, g.try_into()?, b.try_into()?))
+                    },
+                    _ => Err(PErr::sem_error(&node)),
+                }?;
+                todo.push_op(Cmd::Ambient(Color::new(r, g, b)), None, None)
+            },
+
+            PCmd::Shadows => {
+                let (bias, pcf) = match args.as_slice() {
+                    [] => Ok((None, None)),
+                    [Num(bias)] => Ok((Some(bias.into()), None)),
+                    [Num(bias), Num(PosInt(pcf))] => Ok((Some(bias.into()), Some(*pcf))),
+                    _ => Err(PErr::sem_error(&node)),
+                }?;
+                if pcf.is_some_and(|pcf| pcf > shadow::MAX_PCF) {
+                    return Err(PErr::new(format!("Shadow filters can't be wider than {} pixels", shadow::MAX_PCF)));
+                }
+                todo.push_op(Cmd::Shadows(bias, pcf), None, None)
             },
 
             PCmd::Mesh => {
@@ -347,7 +550,97 @@ fn analyze(node: &AstNode, todo: &mut ToDoList) -> Result<(), ParseError> {
                 } else {
                     Err(PErr::sem_error(&node))
                 }?;
-                todo.push_op(Cmd::Mesh(meshfile), lighting, None)
+                let system = coord_system(args.get(mesh + 1))?;
+                todo.push_op(Cmd::Mesh(meshfile, system), lighting, None)
+            },
+
+            PCmd::Camera => {
+                let mut terms: Vec<Expression> = args.iter().map(|val| match val {
+                    Num(i) => Ok(i.into()),
+                    Expr(i) => Ok(i.clone()),
+                    Ident(i) => Ok(i.into()),
+                    _ => Err(PErr::sem_error(val)),
+                }).collect::<Result<_, _>>()?;
+                let depth = if terms.len() == 8 {
+                    let far = terms.pop().unwrap();
+                    Some((terms.pop().unwrap(), far))
+                } else {
+                    None
+                };
+                let az = terms.pop().unwrap();
+                let ay = terms.pop().unwrap();
+                let ax = terms.pop().unwrap();
+                let ez = terms.pop().unwrap();
+                let ey = terms.pop().unwrap();
+                let ex = terms.pop().unwrap();
+                todo.push_op(Cmd::Camera(ex, ey, ez, ax, ay, az, depth), None, None)
+            },
+
+            PCmd::Focal => {
+                let focal = match &args[0] {
+                    Num(i) => Ok(i.into()),
+                    Expr(i) => Ok(i.clone()),
+                    _ => Err(PErr::sem_error(&node)),
+                }?;
+                todo.push_op(Cmd::Focal(focal), None, None)
+            },
+
+            PCmd::Shading => {
+                use lib_graphics::Shading as S;
+                match args.as_slice() {
+                    [Shading(shading)] => todo.push_op(Cmd::Shading(*shading, false), None, None),
+                    [Shading(S::Wireframe), Shading(S::HiddenLine)] => {
+                        todo.push_op(Cmd::Shading(S::HiddenLine, false), None, None)
+                    },
+                    [Shading(shading @ S::Flat), Shading(S::Wireframe)]
+                    | [Shading(shading @ S::Gouraud), Shading(S::Wireframe)]
+                    | [Shading(shading @ S::Phong), Shading(S::Wireframe)] => {
+                        todo.push_op(Cmd::Shading(*shading, true), None, None)
+                    },
+                    _ => Err(PErr::sem_error(&node)),
+                }
+            },
+
+            PCmd::Texture => {
+                use lib_graphics::screen::Filter as F;
+                let texture = match args.as_slice() {
+                    [] => Ok(None),
+                    [Str(file)] => Ok(Some((file.to_owned(), F::Bilinear))),
+                    [Str(file), Filter(filter)] => Ok(Some((file.to_owned(), *filter))),
+                    _ => Err(PErr::sem_error(&node)),
+                }?;
+                todo.push_op(Cmd::Texture(texture), None, None)
+            },
+
+            PCmd::Pattern => {
+                let (kind, axis, args) = match args.as_slice() {
+                    [] => return todo.push_op(Cmd::Pattern(None), None, None),
+                    [PatternKind(kind), Axis(axis), args @ ..] => (*kind, *axis, args),
+                    [PatternKind(kind), args @ ..] => (*kind, ast::Axis::Z, args),
+                    _ => return Err(PErr::sem_error(&node)),
+                };
+                let (size, colors) = match args {
+                    [Num(size), colors @ ..] if colors.len() == 6 => (f64::from(size), colors),
+                    _ => return Err(PErr::sem_error(&node)),
+                };
+                if size <= 0. {
+                    return Err(PErr::new("Patterns need a size greater than 0"));
+                }
+                let colors = colors.iter().map(|c| match c {
+                    Num(n) => Ok(channel(n)),
+                    _ => Err(PErr::sem_error(&node)),
+                }).collect::<Result<Vec<_>, _>>()?;
+                let colors = [
+                    Color::new(colors[0], colors[1], colors[2]),
+                    Color::new(colors[3], colors[4], colors[5]),
+                ];
+                let axis = match axis {
+                    ast::Axis::X => 0,
+                    ast::Axis::Y => 1,
+                    ast::Axis::Z => 2,
+                };
+                let pattern = Pattern::new(kind, axis, size, colors);
+                todo.push_op(Cmd::Pattern(Some(Arc::new(pattern))), None, None)
             },
         }
     } else {
@@ -368,7 +661,7 @@ mod tests {
     }
 
     #[test]
-    fn test_analyze() -> Result<(), ParseError> {
+    fn test_analyze() -> Result<(), MdlErrors> {
         let text = "
 push
 pop
@@ -380,7 +673,7 @@ rotate z -12.
 box 1 2 3 4 5 6
 box foobar 1 2 3 4 5 6
 ";
-        let nodes = ast::parse(&text).expect("Failed while performing parsing!");
+        let nodes = ast::parse(text).expect("Failed while performing parsing!");
         //dbg!(&nodes);
         let todo = analyze_nodes(&nodes)?;
         dbg!(&todo);
@@ -388,7 +681,104 @@ box foobar 1 2 3 4 5 6
     }
 
     #[test]
-    fn mdl_analyze() -> Result<(), ParseError> {
+    fn coord_systems() -> Result<(), MdlErrors> {
+        let text = "
+save_coord_system foo
+sphere 1 2 3 4 foo
+mesh shiny :teapot.obj foo
+line shiny 0 0 0 foo 1 1 1
+line 0 0 0 1 1 1 bar
+";
+        let todo = analyze_nodes(&ast::parse(text).unwrap())?;
+        let commands: Vec<_> = todo.ops.iter().map(|op| &op.command).collect();
+        match commands.as_slice() {
+            [
+                Command::SaveCoordSystem(name),
+                Command::Sphere(.., s),
+                Command::Mesh(file, m),
+                Command::Line(.., l0, _, _, _, l1, _),
+                Command::Line(.., l2, _, _, _, l3, _),
+            ] => {
+                assert_eq!(("foo", Some("foo")), (name.as_str(), s.as_deref()));
+                assert_eq!(("teapot.obj", Some("foo")), (file.as_str(), m.as_deref()));
+                assert_eq!((Some("foo"), None), (l0.as_deref(), l1.as_deref()));
+                assert_eq!((None, Some("bar")), (l2.as_deref(), l3.as_deref()));
+            },
+            commands => panic!("{:?}", commands),
+        }
+        assert_eq!(Some("shiny"), todo.ops[3].light_const.as_deref());
+        Ok(())
+    }
+
+    #[test]
+    fn variables() -> Result<(), MdlErrors> {
+        let text = "
+move x y z
+rotate y angle spin
+box (x) 2 3 4 5 6
+";
+        let todo = analyze_nodes(&ast::parse(text).unwrap())?;
+        let var = |expr: &Expression| match expr {
+            Expression::Var(name) => name.to_owned(),
+            expr => panic!("{:?}", expr),
+        };
+        match &todo.ops[0].command {
+            Command::Translate(x, y, z) => assert_eq!(["x", "y", "z"], [var(x), var(y), var(z)]),
+            command => panic!("{:?}", command),
+        }
+        match &todo.ops[1].command {
+            Command::Rotate(_, degrees) => assert_eq!("angle", var(degrees)),
+            command => panic!("{:?}", command),
+        }
+        assert_eq!(Some("spin"), todo.ops[1].knob.as_deref());
+        // Shapes start with an optional lighting constant, so they need parentheses
+        assert!(ast::parse("\nbox x 2 3 4 5 6\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn light_attenuation() {
+        let cases = [
+            ("point 255 255 255 0 0 0 0 0 0", "Attenuation needs a term greater than 0"),
+            ("point 255 255 255 0 0 0 1 -0.5 0", "Attenuation terms can't be negative"),
+            ("spot 255 255 255 0 0 0 0 -1 0 10 20 0 0 -1", "Attenuation terms can't be negative"),
+        ];
+        for (light, message) in &cases {
+            let text = format!("\nlight {}\n", light);
+            let errors = analyze_nodes(&ast::parse(&text).unwrap()).unwrap_err().0;
+            assert_eq!(1, errors.len());
+            assert_eq!(*message, errors[0].message);
+            assert_eq!(Some(2), errors[0].span.map(|span| span.line));
+        }
+        assert!(analyze_nodes(&ast::parse("light point 255 255 255 0 0 0 0 0 1").unwrap()).is_ok());
+    }
+
+    #[test]
+    fn shadow_filter() {
+        for pcf in &["17", "100000", "99999999999"] {
+            let text = format!("\nshadows 2 {}\n", pcf);
+            let errors = analyze_nodes(&ast::parse(&text).unwrap()).unwrap_err().0;
+            assert_eq!(1, errors.len());
+            assert_eq!("Shadow filters can't be wider than 16 pixels", errors[0].message);
+            assert_eq!(Some(2), errors[0].span.map(|span| span.line));
+        }
+        assert!(analyze_nodes(&ast::parse("shadows 2 16").unwrap()).is_ok());
+    }
+
+    #[test]
+    fn pattern_size() {
+        for size in &["0", "-2.5"] {
+            let text = format!("\npattern checker {} 255 255 255 0 0 0\n", size);
+            let errors = analyze_nodes(&ast::parse(&text).unwrap()).unwrap_err().0;
+            assert_eq!(1, errors.len());
+            assert_eq!("Patterns need a size greater than 0", errors[0].message);
+            assert_eq!(Some(2), errors[0].span.map(|span| span.line));
+        }
+        assert!(analyze_nodes(&ast::parse("pattern checker 0.5 255 255 255 0 0 0").unwrap()).is_ok());
+    }
+
+    #[test]
+    fn mdl_analyze() -> Result<(), MdlErrors> {
         let nodes = ast::parse(&get_mdl()).unwrap();
         //dbg!(&nodes);
         let todo = analyze_nodes(&nodes)?;
@@ -1,13 +1,14 @@
 use lazy_static::lazy_static;
+use lib_graphics::animation::{Easing, Spline};
+use lib_graphics::screen::{Filter, LineCap, PatternKind};
+use lib_graphics::Shading;
+use super::error::{MdlError, Span};
 use super::{MDLParser, Rule};
-use pest::error::Error;
 use pest::iterators::{Pair, Pairs};
 use pest::Parser;
 use pest::prec_climber::*;
-use std::{f64, isize};
 use std::str::FromStr;
 use std::convert::{TryFrom, TryInto};
-use std::ops::{Add, Div, Mul, Sub};
 
 // TODO: Rename this to `ParseStatement`
 #[derive(Clone, Debug)]
@@ -16,6 +17,7 @@ pub enum ParseCommand {
     Pop,
     Display,
     Save,
+    SaveCoordSystem,
     Translate,
     Scale,
     Rotate,
@@ -27,8 +29,19 @@ pub enum ParseCommand {
     Frames,
     Basename,
     Vary,
+    Set,
+    SetKnobs,
+    SaveKnobs,
+    Tween,
     Light,
+    Ambient,
+    Shadows,
     Mesh,
+    Camera,
+    Focal,
+    Shading,
+    Texture,
+    Pattern,
 }
 
 impl From<&Rule> for ParseCommand {
@@ -40,6 +53,7 @@ impl From<&Rule> for ParseCommand {
             pop => Pcmd::Pop,
             display => Pcmd::Display,
             save => Pcmd::Save,
+            save_coord_system => Pcmd::SaveCoordSystem,
             translate => Pcmd::Translate,
             scale => Pcmd::Scale,
             rotate => Pcmd::Rotate,
@@ -51,15 +65,29 @@ impl From<&Rule> for ParseCommand {
             frames => Pcmd::Frames,
             basename => Pcmd::Basename,
             vary => Pcmd::Vary,
+            set => Pcmd::Set,
+            setknobs => Pcmd::SetKnobs,
+            save_knobs => Pcmd::SaveKnobs,
+            tween => Pcmd::Tween,
             light => Pcmd::Light,
+            ambient => Pcmd::Ambient,
+            shadows => Pcmd::Shadows,
             mesh => Pcmd::Mesh,
+            camera => Pcmd::Camera,
+            focal => Pcmd::Focal,
+            shading => Pcmd::Shading,
+            texture => Pcmd::Texture,
+            pattern => Pcmd::Pattern,
 
             // The following aren't commands
             expr | add | subtract | multiply | divide | intdivide | number
                 // Primitve `Rule`s
-                | float | posint | negint | axis | ident | string
+                | float | posint | negint | axis | light_type | shading_type | filter | pattern_type
+                | easing | spline
+                | line_width | line_cap | smoothing
+                | ident | string
                 // These are silent
-                | program | statement | term | operation | WHITESPACE | COMMENT 
+                | program | statement | term | shape_term | operation | line_option | WHITESPACE | COMMENT 
                 // we don't parse the end of input
                 | EOI
                 => unreachable!("`{:?}` not a command!", r),
@@ -75,9 +103,18 @@ pub enum Axis {
     Z,
 }
 
+#[derive(Clone, Copy, Debug)]
+pub enum LightType {
+    Directional,
+    Point,
+    Spot,
+}
+
 #[derive(Debug)]
 pub struct ParseAxisError;
 #[derive(Debug)]
+pub struct ParseLightTypeError;
+#[derive(Debug)]
 pub struct TryFromNumError;
 
 impl FromStr for Axis {
@@ -92,6 +129,18 @@ impl FromStr for Axis {
     }
 }
 
+impl FromStr for LightType {
+    type Err = ParseLightTypeError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "directional" => Ok(LightType::Directional),
+            "point" => Ok(LightType::Point),
+            "spot" => Ok(LightType::Spot),
+            _ => Err(ParseLightTypeError),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum Operation {
     Add,
@@ -108,182 +157,63 @@ pub enum Number {
     PosInt(usize),
 }
 
-// TODO: Macros would really make this way shorter
-impl Add for Number {
-    type Output = Self;
-    fn add(self, other: Self) -> Self {
-        use Number::*;
-        match self {
-            Float(l) => {
-                let r = match other {
-                    Float(r) => r,
-                    Int(r) => r as f64,
-                    PosInt(r) => r as f64,
-                };
-                Float(l + r)
-            },
-
-            Int(l) => {
-                match other {
-                    Float(r) => Float(l as f64 + r),
-                    Int(r) => Int(l + r),
-                    PosInt(r) => Int(l + isize::try_from(r).unwrap()),
-                }
-            },
-
-            PosInt(l) => {
-                match other {
-                    Float(r) => Float(l as f64 + r),
-                    Int(r) => Int(isize::try_from(l).unwrap() + r),
-                    PosInt(r) => PosInt(l + r),
-                }
-            },
-
-        }
-    }
-}
-impl Div for Number {
-    type Output = Self;
-    fn div(self, other: Self) -> Self {
-        use Number::*;
-        match self {
-            Float(l) => {
-                let r = match other {
-                    Float(r) => r,
-                    Int(r) => r as f64,
-                    PosInt(r) => r as f64,
-                };
-                Float(l / r)
-            },
-
-            Int(l) => {
-                match other {
-                    Float(r) => Float(l as f64 / r),
-                    Int(r) => Int(l / r),
-                    PosInt(r) => Int(l / isize::try_from(r).unwrap()),
-                }
-            },
-
-            PosInt(l) => {
-                match other {
-                    Float(r) => Float(l as f64 / r),
-                    Int(r) => Int(isize::try_from(l).unwrap() / r),
-                    PosInt(r) => PosInt(l / r),
-                }
-            },
-
-        }
-    }
-}
-impl Mul for Number {
-    type Output = Self;
-    fn mul(self, other: Self) -> Self {
+impl Number {
+    // `self op other`, or None if integer arithmetic overflows or divides by 0.
+    // Anything with a float is a float, and integers stay integers
+    pub fn apply(self, op: &Operation, other: Number) -> Option<Number> {
         use Number::*;
-        match self {
-            Float(l) => {
-                let r = match other {
-                    Float(r) => r,
-                    Int(r) => r as f64,
-                    PosInt(r) => r as f64,
-                };
-                Float(l * r)
-            },
-
-            Int(l) => {
-                match other {
-                    Float(r) => Float(l as f64 * r),
-                    Int(r) => Int(l * r),
-                    PosInt(r) => Int(l * isize::try_from(r).unwrap()),
-                }
-            },
-
-            PosInt(l) => {
-                match other {
-                    Float(r) => Float(l as f64 * r),
-                    Int(r) => Int(isize::try_from(l).unwrap() * r),
-                    PosInt(r) => PosInt(l * r),
-                }
+        match (self, other) {
+            (Float(l), r) => Some(floats(op, l, f64::from(r))),
+            (l, Float(r)) => Some(floats(op, f64::from(l), r)),
+            (PosInt(l), PosInt(r)) => match op {
+                Operation::Add => l.checked_add(r).map(PosInt),
+                // Going below 0 makes it negative
+                Operation::Subtract => match l.checked_sub(r) {
+                    Some(n) => Some(PosInt(n)),
+                    None => ints(op, isize::try_from(l).ok()?, isize::try_from(r).ok()?),
+                },
+                Operation::Multiply => l.checked_mul(r).map(PosInt),
+                Operation::Divide | Operation::IntDivide => l.checked_div(r).map(PosInt),
             },
-
+            (Int(l), PosInt(r)) => ints(op, l, isize::try_from(r).ok()?),
+            (PosInt(l), Int(r)) => ints(op, isize::try_from(l).ok()?, r),
+            (Int(l), Int(r)) => ints(op, l, r),
         }
     }
 }
-impl Sub for Number {
-    type Output = Self;
-    fn sub(self, other: Self) -> Self {
-        use Number::*;
-        match self {
-            Float(l) => {
-                let r = match other {
-                    Float(r) => r,
-                    Int(r) => r as f64,
-                    PosInt(r) => r as f64,
-                };
-                Float(l - r)
-            },
-
-            Int(l) => {
-                match other {
-                    Float(r) => Float(l as f64 - r),
-                    Int(r) => Int(l - r),
-                    PosInt(r) => Int(l - isize::try_from(r).unwrap()),
-                }
-            },
-
-            PosInt(l) => {
-                match other {
-                    Float(r) => Float(l as f64 - r),
-                    Int(r) => Int(isize::try_from(l).unwrap() - r),
-                    PosInt(r) => PosInt(l - r),
-                }
-            },
 
-        }
-    }
+fn floats(op: &Operation, l: f64, r: f64) -> Number {
+    Number::Float(match op {
+        Operation::Add => l + r,
+        Operation::Subtract => l - r,
+        Operation::Multiply => l * r,
+        Operation::Divide => l / r,
+        Operation::IntDivide => return Number::Int((l / r) as isize),
+    })
 }
-impl Number {
-    pub fn intdiv(self, other: Self) -> Self {
-        use Number::*;
-        match self {
-            Float(l) => {
-                match other {
-                    Float(r) => Int((l / r) as isize),
-                    Int(r) => Int((l / r as f64) as isize),
-                    PosInt(r) => Int((l / r as f64) as isize),
-                }
-            },
 
-            Int(l) => {
-                match other {
-                    Float(r) => Int((l as f64 / r) as isize),
-                    Int(r) => Int(l / r),
-                    PosInt(r) => Int(l / isize::try_from(r).unwrap()),
-                }
-            },
-
-            PosInt(l) => {
-                match other {
-                    Float(r) => Int((l as f64 / r) as isize),
-                    Int(r) => Int(isize::try_from(l).unwrap() / r),
-                    PosInt(r) => PosInt(l / r),
-                }
-            },
-
-        }
+fn ints(op: &Operation, l: isize, r: isize) -> Option<Number> {
+    match op {
+        Operation::Add => l.checked_add(r),
+        Operation::Subtract => l.checked_sub(r),
+        Operation::Multiply => l.checked_mul(r),
+        Operation::Divide | Operation::IntDivide => l.checked_div(r),
     }
+    .map(Number::Int)
 }
 
 impl<'i> TryFrom<Pair<'i, Rule>> for Number {
-    type Error = AstIntoError;
+    type Error = MdlError;
     fn try_from(pair: Pair<Rule>) -> Result<Self, Self::Error> {
         use Number::*;
+        // If the parser claims that a token is a float/integer, then str::parse
+        // only fails when the integer is too large
+        let too_large = |_| MdlError::at(pair.as_span().into(), format!("`{}` is too large a number", pair.as_str()));
         match pair.as_rule() {
-            // If the parser claims that a token is a float/integer,
-            // then str::parse will always succeed
             Rule::float => Ok(Float(pair.as_str().parse().unwrap())),
-            Rule::posint => Ok(PosInt(pair.as_str().parse().unwrap())),
-            Rule::negint => Ok(Int(pair.as_str().parse().unwrap())),
-            _ => Err(AstIntoError)
+            Rule::posint => Ok(PosInt(pair.as_str().parse().map_err(too_large)?)),
+            Rule::negint => Ok(Int(pair.as_str().parse().map_err(too_large)?)),
+            _ => Err(AstIntoError.into())
         }
     }
 }
@@ -360,20 +290,30 @@ pub enum AstNode {
     Ident(String),
     Str(String),
     Axis(Axis),
+    LightType(LightType),
+    Shading(Shading),
+    Filter(Filter),
+    PatternKind(PatternKind),
+    Easing(Easing),
+    Spline(Spline),
+    // Line options
+    LineWidth(Box<AstNode>),
+    LineCap(LineCap),
+    Smooth(bool),
     Expr(Expression),
-    MdlCommand { command: ParseCommand, args: Vec<AstNode> },
+    MdlCommand { command: ParseCommand, args: Vec<AstNode>, span: Span },
 }
 #[derive(Debug)]
 pub struct AstIntoError;
 
-pub fn parse(source: &str) -> Result<Vec<AstNode>, Error<Rule>> {
+pub fn parse(source: &str) -> Result<Vec<AstNode>, MdlError> {
     let mut ast: Vec<AstNode> = vec![];
 
     let pairs = MDLParser::parse(Rule::program, source)?;
     for pair in pairs {
         match pair.as_rule() {
             Rule::EOI => break,
-            _ => ast.push(node_from_statement(pair)),
+            _ => ast.push(node_from_statement(pair)?),
         }
     }
     Ok(ast)
@@ -391,38 +331,50 @@ lazy_static! {
     };
 }
 
-fn eval_expr(expr: Pairs<Rule>) -> Expression {
+fn eval_expr(expr: Pairs<Rule>) -> Result<Expression, MdlError> {
     use Expression::*;
     use Operation as Op;
     PREC_CLIMBER.climb(
         expr,
         |pair: Pair<Rule>| match pair.as_rule() {
             Rule::float | Rule::negint | Rule::posint => {
-                Num(pair.try_into().unwrap())
+                Ok(Num(pair.try_into()?))
             },
-            Rule::ident => Var(pair.as_str().to_owned()),
+            Rule::ident => Ok(Var(pair.as_str().to_owned())),
             Rule::expr => eval_expr(pair.into_inner()),
             _ => unreachable!("{:?}", pair),
         },
-        |lhs: Expression, op: Pair<Rule>, rhs: Expression| match op.as_rule() {
-            Rule::add => Action(Box::new(lhs), Op::Add, Box::new(rhs)),
-            Rule::subtract => Action(Box::new(lhs), Op::Subtract, Box::new(rhs)),
-            Rule::multiply => Action(Box::new(lhs), Op::Multiply, Box::new(rhs)),
-            Rule::divide => Action(Box::new(lhs), Op::Divide, Box::new(rhs)),
-            Rule::intdivide => Action(Box::new(lhs), Op::IntDivide, Box::new(rhs)),
-            _ => unimplemented!()
+        |lhs: Result<Expression, MdlError>, op: Pair<Rule>, rhs: Result<Expression, MdlError>| {
+            let op = match op.as_rule() {
+                Rule::add => Op::Add,
+                Rule::subtract => Op::Subtract,
+                Rule::multiply => Op::Multiply,
+                Rule::divide => Op::Divide,
+                Rule::intdivide => Op::IntDivide,
+                _ => unimplemented!()
+            };
+            Ok(Action(Box::new(lhs?), op, Box::new(rhs?)))
         }
     )
 }
 
-fn node_from_statement(pair: Pair<Rule>) -> AstNode {
+fn node_from_statement(pair: Pair<Rule>) -> Result<AstNode, MdlError> {
     use self::Axis as PAxis;
     use AstNode::*;
 
-    match pair.as_rule() {
+    Ok(match pair.as_rule() {
         // Primitives
-        Rule::float | Rule::posint | Rule::negint => Num(pair.try_into().unwrap()),
+        Rule::float | Rule::posint | Rule::negint => Num(pair.try_into()?),
         Rule::axis => Axis(pair.as_str().parse::<PAxis>().unwrap()),
+        Rule::light_type => LightType(pair.as_str().parse().unwrap()),
+        Rule::shading_type => Shading(pair.as_str().parse().unwrap()),
+        Rule::filter => Filter(pair.as_str().parse().unwrap()),
+        Rule::pattern_type => PatternKind(pair.as_str().parse().unwrap()),
+        Rule::easing => Easing(pair.as_str().parse().unwrap()),
+        Rule::spline => Spline(pair.as_str().parse().unwrap()),
+        Rule::line_cap => LineCap(pair.as_str().parse().unwrap()),
+        Rule::smoothing => Smooth(pair.as_str().eq_ignore_ascii_case("smooth")),
+        Rule::line_width => LineWidth(Box::new(get_args(pair)?.pop().unwrap_or_else(|| unreachable!()))),
         Rule::ident => Ident(pair.as_str().to_owned()),
         Rule::string => Str(pair.as_str().to_owned()),
         // These are silent or already unwrapped
@@ -433,14 +385,17 @@ fn node_from_statement(pair: Pair<Rule>) -> AstNode {
         Rule::term => {
             // terms can only have 1 pair in them, either a `number` or an `expr`
             // therefore this unwrap will never panic
-            get_args(pair).pop().unwrap_or_else(|| unreachable!())
+            get_args(pair)?.pop().unwrap_or_else(|| unreachable!())
         },
-        Rule::expr => Expr(eval_expr(pair.into_inner())),
+        Rule::expr => Expr(eval_expr(pair.into_inner())?),
         // Commands
-        rule => AstNode::MdlCommand { command: ParseCommand::from(&rule), args: get_args(pair) },
-    }
+        rule => {
+            let span = pair.as_span().into();
+            AstNode::MdlCommand { command: ParseCommand::from(&rule), args: get_args(pair)?, span }
+        },
+    })
 }
 
-fn get_args(pair: Pair<Rule>) -> Vec<AstNode> {
+fn get_args(pair: Pair<Rule>) -> Result<Vec<AstNode>, MdlError> {
     pair.into_inner().map(node_from_statement).collect()
 }
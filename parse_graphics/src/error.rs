@@ -0,0 +1,187 @@
+// Errors in MDL scripts, from parsing them all the way to drawing them. They point at
+// where in the script they happened, and are shown like rustc's errors:
+//
+// error: Variable `spin` is undefined
+//  --> scripts/spin.mdl:3:1
+//   |
+// 3 | rotate y (360 * spin)
+//   | ^^^^^^^^^^^^^^^^^^^^^
+use super::ast::{AstIntoError, ParseAxisError, TryFromNumError};
+use super::Rule;
+use pest::error::{Error as PestError, ErrorVariant, LineColLocation};
+use std::error::Error;
+use std::fmt::{self, Debug, Display};
+use std::io;
+use std::num::TryFromIntError;
+
+// Where a command is in its script. Lines and columns start at 1
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+    // Columns past the end of the line are cut off when the error is shown
+    pub end_col: usize,
+}
+
+impl From<pest::Span<'_>> for Span {
+    fn from(span: pest::Span) -> Span {
+        let (line, col) = span.start_pos().line_col();
+        let end_col = match span.end_pos().line_col() {
+            (end_line, end_col) if end_line == line => end_col,
+            _ => usize::MAX,
+        };
+        Span { line, col, end_col }
+    }
+}
+
+#[derive(Debug)]
+pub struct MdlError {
+    pub message: String,
+    pub file: Option<String>,
+    pub span: Option<Span>,
+    // The line of the script that `span` is on
+    line: Option<String>,
+}
+
+impl MdlError {
+    pub fn new<S: Into<String>>(message: S) -> MdlError {
+        MdlError { message: message.into(), file: None, span: None, line: None }
+    }
+
+    pub fn at<S: Into<String>>(span: Span, message: S) -> MdlError {
+        MdlError { span: Some(span), ..MdlError::new(message) }
+    }
+
+    // Arguments a command can't take, which the grammar should have already caught
+    pub fn sem_error<T: Debug>(data: &T) -> MdlError {
+        MdlError::new(format!("Unexpected argument: {:?}", data))
+    }
+
+    // Point at `span`, unless the error already points somewhere more specific
+    pub fn or_span(mut self, span: Span) -> MdlError {
+        self.span = self.span.or(Some(span));
+        self
+    }
+
+    // Name the script the error is in, and keep the line it happened on to show it
+    pub fn in_file(mut self, file: &str, source: &str) -> MdlError {
+        self.file = self.file.or_else(|| Some(file.to_owned()));
+        if let (Some(span), None) = (self.span, &self.line) {
+            self.line = source.lines().nth(span.line - 1).map(str::to_owned);
+        }
+        self
+    }
+}
+
+impl Display for MdlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "error: {}", self.message)?;
+        let span = match (&self.file, self.span) {
+            (Some(file), Some(span)) => {
+                writeln!(f, " --> {}:{}:{}", file, span.line, span.col)?;
+                span
+            },
+            (Some(file), None) => return writeln!(f, " --> {}", file),
+            (None, Some(span)) => {
+                writeln!(f, " --> line {}, column {}", span.line, span.col)?;
+                span
+            },
+            (None, None) => return Ok(()),
+        };
+        if let Some(line) = &self.line {
+            let number = span.line.to_string();
+            let gutter = " ".repeat(number.len());
+            let length = line.chars().count();
+            let (start, end) = (span.col - 1, (span.end_col - 1).min(length));
+            writeln!(f, "{} |", gutter)?;
+            writeln!(f, "{} | {}", number, line)?;
+            writeln!(f, "{} | {}{}", gutter, " ".repeat(start), "^".repeat(end.saturating_sub(start).max(1)))?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for MdlError {}
+
+// Every error found in a script, so that they can all be fixed at once
+#[derive(Debug)]
+pub struct MdlErrors(pub Vec<MdlError>);
+
+impl MdlErrors {
+    pub fn in_file(self, file: &str, source: &str) -> MdlErrors {
+        MdlErrors(self.0.into_iter().map(|err| err.in_file(file, source)).collect())
+    }
+}
+
+impl Display for MdlErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, err) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", err)?;
+        }
+        if self.0.len() > 1 {
+            writeln!(f, "\nerror: aborting due to {} previous errors", self.0.len())?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for MdlErrors {}
+
+impl From<MdlError> for MdlErrors {
+    fn from(err: MdlError) -> MdlErrors {
+        MdlErrors(vec![err])
+    }
+}
+
+impl From<PestError<Rule>> for MdlError {
+    fn from(err: PestError<Rule>) -> MdlError {
+        let message = match &err.variant {
+            ErrorVariant::ParsingError { positives, .. } if !positives.is_empty() => {
+                let expected: Vec<_> = positives.iter().map(|rule| format!("{:?}", rule)).collect();
+                format!("Expected {}", expected.join(", "))
+            },
+            ErrorVariant::ParsingError { .. } => String::from("Unexpected input"),
+            ErrorVariant::CustomError { message } => message.to_owned(),
+        };
+        let span = match err.line_col {
+            LineColLocation::Pos((line, col)) => Span { line, col, end_col: col + 1 },
+            LineColLocation::Span((line, col), (end_line, end_col)) => {
+                Span { line, col, end_col: if end_line == line { end_col } else { usize::MAX } }
+            },
+        };
+        MdlError::at(span, message)
+    }
+}
+
+impl From<io::Error> for MdlError {
+    fn from(err: io::Error) -> MdlError {
+        MdlError::new(err.to_string())
+    }
+}
+
+impl From<AstIntoError> for MdlError {
+    fn from(_: AstIntoError) -> MdlError {
+        MdlError::new("Expected a number")
+    }
+}
+
+impl From<ParseAxisError> for MdlError {
+    fn from(_: ParseAxisError) -> MdlError {
+        MdlError::new("Expected an axis: x, y or z")
+    }
+}
+
+impl From<TryFromNumError> for MdlError {
+    fn from(_: TryFromNumError) -> MdlError {
+        MdlError::new("Expected a positive integer")
+    }
+}
+
+impl From<TryFromIntError> for MdlError {
+    fn from(err: TryFromIntError) -> MdlError {
+        MdlError::new(err.to_string())
+    }
+}
@@ -1,9 +1,12 @@
 pub mod analyzer;
 pub mod ast;
+pub mod error;
+mod semantic;
 pub mod todo;
 
-pub use analyzer::{file, Command, ParseError, NOOP};
+pub use analyzer::{file, Command, NOOP};
 pub use ast::{AstIntoError, AstNode, Axis, ParseAxisError, ParseCommand};
+pub use error::{MdlError, MdlErrors, Span};
 pub use todo::{Operation, Symbol, ToDoList};
 
 use pest_derive::Parser;
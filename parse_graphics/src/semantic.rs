@@ -0,0 +1,248 @@
+// Checks a whole script before anything is drawn, so that every mistake in it is
+// reported at once, instead of one at a time partway through drawing
+use super::analyzer::Command;
+use super::ast::Expression;
+use super::error::{MdlError, Span};
+use super::todo::{eval_usize, Symbol, ToDoList};
+use std::collections::HashSet;
+
+// Variables that the screen adds when the script is run
+const SCREEN_VARIABLES: [&str; 2] = ["XRES", "YRES"];
+
+pub(crate) fn check(todo: &ToDoList) -> Vec<MdlError> {
+    use Command::*;
+
+    let mut errors = vec![];
+    let frames = frame_count(todo, &mut errors);
+    // `setknobs` gives every knob in the script a value, so they're all defined
+    let setknobs = todo.ops.iter().any(|operation| matches!(operation.command, SetKnobs(_)));
+    let knobs: HashSet<_> = todo
+        .ops
+        .iter()
+        .filter_map(|operation| match &operation.command {
+            Vary(knob, ..) | VarySpline(knob, ..) | Set(knob, _) => Some(knob),
+            _ => None,
+        })
+        .collect();
+    // Coordinate systems and knob lists can only be used after they're saved
+    let (mut coord_systems, mut knob_lists) = (HashSet::new(), HashSet::new());
+
+    for operation in &todo.ops {
+        let span = operation.span;
+        let mut error = |message: String| errors.push(MdlError::at(span, message));
+
+        for name in expressions(&operation.command).into_iter().flat_map(variables) {
+            match todo.get_sym(name) {
+                Some(Symbol::Num(_)) => {},
+                None if SCREEN_VARIABLES.contains(&name.as_str()) => {},
+                None => error(format!("Variable `{}` is undefined", name)),
+                Some(_) => error(format!("Variable `{}` must refer to a numerical value", name)),
+            }
+        }
+        if let Some(knob) = &operation.knob {
+            match todo.get_sym(knob) {
+                Some(Symbol::Knob(_)) => {},
+                None if setknobs || knobs.contains(knob) => {},
+                None => error(format!("Knob `{}` is never set or varied", knob)),
+                Some(_) => error(format!("`{}` isn't a knob", knob)),
+            }
+        }
+        if let Some(name) = &operation.light_const {
+            if !matches!(todo.get_sym(name), Some(Symbol::Constant(_))) {
+                error(format!("Lighting constants `{}` are undefined", name));
+            }
+        }
+
+        let mut saved = |system: &Option<String>| match system {
+            Some(name) if !coord_systems.contains(name) => {
+                error(format!("Coordinate system `{}` hasn't been saved", name))
+            },
+            _ => {},
+        };
+        match &operation.command {
+            Cuboid(.., system) | Sphere(.., system) | Torus(.., system) | Mesh(_, system) => saved(system),
+            Line(_, _, _, system0, _, _, _, system1, _) => {
+                saved(system0);
+                saved(system1);
+            },
+            SaveCoordSystem(name) => {
+                coord_systems.insert(name);
+            },
+            SaveKnobs(name) => {
+                knob_lists.insert(name);
+            },
+            Tween(start, end, list0, list1) => {
+                for list in &[list0, list1] {
+                    if !knob_lists.contains(list) {
+                        error(format!("Knob list `{}` hasn't been saved", list));
+                    }
+                }
+                let range = (frame(todo, start, span, &mut errors), frame(todo, end, span, &mut errors));
+                check_range(range, frames, span, &mut errors);
+            },
+            Vary(_, start, end, ..) => {
+                let range = (frame(todo, start, span, &mut errors), frame(todo, end, span, &mut errors));
+                check_range(range, frames, span, &mut errors);
+            },
+            VarySpline(_, _, keyframes) => {
+                let keyframes: Vec<_> = keyframes.iter().map(|key| frame(todo, &key.0, span, &mut errors)).collect();
+                if keyframes.windows(2).any(|pair| matches!(pair, [Some(f0), Some(f1)] if f0 >= f1)) {
+                    errors.push(MdlError::at(span, "Keyframes must be in order"));
+                }
+                let last = keyframes.last().cloned().flatten();
+                check_range((keyframes[0], last), frames, span, &mut errors);
+            },
+            _ => {},
+        }
+    }
+    errors
+}
+
+// The number of frames in an animation, if the script is animated. An animation
+// without a `frames` command is an error
+fn frame_count(todo: &ToDoList, errors: &mut Vec<MdlError>) -> Option<usize> {
+    use Command::*;
+
+    let vary = todo.ops.iter().find(|operation| matches!(operation.command, Vary(..) | VarySpline(..) | Tween(..)));
+    // The last `frames` is the one that's used
+    let frames = todo.ops.iter().rev().find_map(|operation| match &operation.command {
+        Frames(frames) => Some((frames, operation.span)),
+        _ => None,
+    });
+    match (vary, frames) {
+        (None, _) => None,
+        (Some(vary), None) => {
+            errors.push(MdlError::at(vary.span, "`frames` command must also be given if `vary` or `tween` command given"));
+            None
+        },
+        (Some(_), Some((frames, span))) => match frame(todo, frames, span, errors) {
+            Some(0) => {
+                errors.push(MdlError::at(span, "Animations need at least 1 frame"));
+                None
+            },
+            frames => frames,
+        },
+    }
+}
+
+// The frame `expr` is, if it can be worked out before the script is run
+fn frame(todo: &ToDoList, expr: &Expression, span: Span, errors: &mut Vec<MdlError>) -> Option<usize> {
+    // Undefined variables have already been reported
+    if !variables(expr).iter().all(|name| matches!(todo.get_sym(name), Some(Symbol::Num(_)))) {
+        return None;
+    }
+    eval_usize(expr, Some(&todo.symbols)).map_err(|err| errors.push(err.or_span(span))).ok()
+}
+
+// Frames from `start` to `end` have to be in order, and in the animation
+fn check_range(range: (Option<usize>, Option<usize>), frames: Option<usize>, span: Span, errors: &mut Vec<MdlError>) {
+    match (range, frames) {
+        ((Some(start), Some(end)), _) if start > end => {
+            errors.push(MdlError::at(span, format!("Start frame {} is after the end frame {}", start, end)));
+        },
+        ((_, Some(end)), Some(frames)) if end >= frames => {
+            let message = format!("Frame {} is past the last frame of the animation, {}", end, frames - 1);
+            errors.push(MdlError::at(span, message));
+        },
+        _ => {},
+    }
+}
+
+// Every expression in a command
+fn expressions(command: &Command) -> Vec<&Expression> {
+    use Command::*;
+    match command {
+        Translate(x, y, z) | Scale(x, y, z) => vec![x, y, z],
+        Rotate(_, degrees) => vec![degrees],
+        Cuboid(x, y, z, h, w, d, _) => vec![x, y, z, h, w, d],
+        Sphere(x, y, z, r, _) => vec![x, y, z, r],
+        Torus(x, y, z, r0, r1, _) => vec![x, y, z, r0, r1],
+        Line(x0, y0, z0, _, x1, y1, z1, _, options) => {
+            let mut expressions = vec![x0, y0, z0, x1, y1, z1];
+            expressions.extend(&options.width);
+            expressions
+        },
+        Frames(frames) => vec![frames],
+        Vary(_, start, end, val0, val1, _) => vec![start, end, val0, val1],
+        VarySpline(_, _, keyframes) => keyframes
+            .iter()
+            .flat_map(|(frame, val, slope)| vec![frame, val].into_iter().chain(slope))
+            .collect(),
+        Set(_, val) | SetKnobs(val) => vec![val],
+        Tween(start, end, ..) => vec![start, end],
+        Camera(ex, ey, ez, ax, ay, az, depth) => {
+            let mut expressions = vec![ex, ey, ez, ax, ay, az];
+            if let Some((near, far)) = depth {
+                expressions.extend(&[near, far]);
+            }
+            expressions
+        },
+        Focal(focal) => vec![focal],
+        Push() | Pop() | Display() | Save(_) | SaveCoordSystem(_) | Basename(_) | SaveKnobs(_) | Mesh(..) => vec![],
+        Shading(..) | Texture(_) | Pattern(_) | Constants(_) | Light(_) | Ambient(_) | Shadows(..) => vec![],
+    }
+}
+
+// Every variable in an expression
+fn variables(expr: &Expression) -> Vec<&String> {
+    match expr {
+        Expression::Num(_) => vec![],
+        Expression::Var(name) => vec![name],
+        Expression::Action(lhs, _, rhs) => {
+            let mut names = variables(lhs);
+            names.extend(variables(rhs));
+            names
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{analyzer, ast};
+
+    fn check_text(text: &str) -> Vec<(usize, String)> {
+        let todo = analyzer::analyze_nodes(&ast::parse(text).unwrap()).unwrap();
+        check(&todo).into_iter().map(|err| (err.span.unwrap().line, err.message)).collect()
+    }
+
+    #[test]
+    fn every_error() {
+        let text = "
+frames 10
+constants shiny 0.3 0.0 0.0 0.3 0.2 0.8 0.3 0.2 0.8
+vary spin 0 10 0 1
+rotate y (360 * turns) spin
+box dull 0 0 0 1 1 1
+sphere 0 0 0 50 corner
+save_coord_system corner
+sphere shiny 0 0 0 (50 + shiny) corner
+move 1 1 1 slide
+tween 5 2 start end
+vary wobble catmull_rom 0 0 5 1 3 0
+";
+        let expected = vec![
+            (4, String::from("Frame 10 is past the last frame of the animation, 9")),
+            (5, String::from("Variable `turns` is undefined")),
+            (6, String::from("Lighting constants `dull` are undefined")),
+            (7, String::from("Coordinate system `corner` hasn't been saved")),
+            (9, String::from("Variable `shiny` must refer to a numerical value")),
+            (10, String::from("Knob `slide` is never set or varied")),
+            (11, String::from("Knob list `start` hasn't been saved")),
+            (11, String::from("Knob list `end` hasn't been saved")),
+            (11, String::from("Start frame 5 is after the end frame 2")),
+            (12, String::from("Keyframes must be in order")),
+        ];
+        assert_eq!(expected, check_text(text));
+    }
+
+    #[test]
+    fn animation_frames() {
+        assert_eq!(
+            vec![(2, String::from("`frames` command must also be given if `vary` or `tween` command given"))],
+            check_text("\nvary spin 0 9 0 1\nrotate y 360 spin\n")
+        );
+        // Screen variables, and knobs given values by `setknobs`, are fine
+        assert!(check_text("frames 5\nsetknobs 1\nmove (XRES / 2) (YRES / 2) 0 slide\n").is_empty());
+    }
+}
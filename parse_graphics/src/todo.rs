@@ -1,64 +1,88 @@
-use super::{Axis, Command, ParseError};
+use super::error::{MdlError, Span};
+use super::{Axis, Command};
 // TODO: Re-export these and instead import from super
 use crate::ast::{Expression, Number, Operation as Op};
-use lib_graphics::PICTURE_DIR;
-use lib_graphics::{draw, Light, Matrix, MatrixMult, Reflection, Screen, SquareMatrix};
+use lib_graphics::animation::{self, Easing, Keyframe, Spline};
+use lib_graphics::screen::{self, gif::GifEncoder, Filter, Pattern, ShadowMap, Texture};
+use lib_graphics::{draw, Camera, Light, LightKind, Matrix, MatrixMult, Reflection, Screen, SquareMatrix, Vector};
 use parse_obj::ObjParser;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryInto;
-use std::fs;
-use std::io::ErrorKind;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
 use std::path::PathBuf;
-use std::process::Command as SubProcess;
-use std::cell::RefCell;
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::thread;
 
-type SymbolTable = RefCell<HashMap<String, Symbol>>;
+type SymbolTable = RwLock<HashMap<String, Symbol>>;
+// Textures that have already been loaded, so that they're only read once
+type TextureCache = Mutex<HashMap<(String, Filter), Arc<Texture>>>;
+// A mesh's polygons and their texture coordinates
+type Mesh = (Matrix, Vec<[f64; 2]>);
+// Meshes that have already been parsed, so that they're only read once instead
+// of on every frame and shadow pass
+type MeshCache = Mutex<HashMap<String, Arc<Mesh>>>;
+// Symbols that change from frame to frame: the value of every knob, and the
+// coordinate systems saved while drawing the frame
+type FrameSymbols = HashMap<String, Symbol>;
 
-fn evalb(expr: &Expression, symtab: Option<&SymbolTable>) -> Number {
+// What happens to the screen at `display` and `save`
+enum Output<'a> {
+    // Nothing, like when going through a frame to find what casts shadows
+    Skip,
+    // It's shown or saved right away
+    Now,
+    // A copy of it is kept along with the index of the command, for frames drawn on other
+    // threads, which are shown and saved once the frames ahead of them are
+    Later(&'a mut Vec<(usize, Screen)>),
+}
+
+// Time each frame of an animation is shown for, in hundredths of a second
+const FRAME_DELAY: u16 = 2;
+
+fn evalb(expr: &Expression, symtab: Option<&SymbolTable>) -> Result<Number, MdlError> {
     use Expression::*;
     use Op::*;
     if let Num(n) = expr {
-        n.to_owned()
+        Ok(n.to_owned())
     } else if let Action(lhs, op, rhs) = expr {
         // recursive post order traversal of the Expression tree
-        let lhs = evalb(lhs, symtab);
-        let rhs = evalb(rhs, symtab);
-        match op {
-            Add => lhs + rhs,
-            Divide => lhs / rhs,
-            Multiply => lhs * rhs,
-            Subtract => lhs - rhs,
-            IntDivide => lhs.intdiv(rhs),
+        let lhs = evalb(lhs, symtab)?;
+        let rhs = evalb(rhs, symtab)?;
+        // Integers can't be divided by 0
+        if let (Divide | IntDivide, Number::Int(0) | Number::PosInt(0)) = (op, &rhs) {
+            return Err(MdlError::new("Division by zero"));
         }
-    } else if let Var(sym) = expr {
+        lhs.apply(op, rhs).ok_or_else(|| MdlError::new("Arithmetic overflow"))
+    } else if let Var(name) = expr {
         let sym = symtab
-            .expect("Need symbol table!")
-            .borrow()
-            .get(sym)
-            .cloned()
-            .unwrap_or_else(|| panic!("Variable `{:?}` is undefined!", sym));
+            .and_then(|symtab| symtab.read().unwrap().get(name).cloned())
+            .ok_or_else(|| MdlError::new(format!("Variable `{}` is undefined", name)))?;
         if let Symbol::Num(n) = sym {
-            n
+            Ok(n)
         } else {
-            panic!("Variable must refer to a numerical value!")
+            Err(MdlError::new(format!("Variable `{}` must refer to a numerical value", name)))
         }
     } else {
         // Expression only has the variants, Num, Action, and Var
         unreachable!();
     }
 }
-pub fn eval_f64(expr: &Expression, symtab: Option<&SymbolTable>) -> f64 {
-    evalb(expr, symtab).into()
+pub fn eval_f64(expr: &Expression, symtab: Option<&SymbolTable>) -> Result<f64, MdlError> {
+    evalb(expr, symtab).map(f64::from)
 }
-pub fn eval_usize(expr: &Expression, symtab: Option<&SymbolTable>) -> usize {
-    evalb(expr, symtab).try_into().unwrap()
+pub fn eval_usize(expr: &Expression, symtab: Option<&SymbolTable>) -> Result<usize, MdlError> {
+    Ok(evalb(expr, symtab)?.try_into()?)
 }
 
 
 #[derive(Clone, Debug)]
 pub enum Symbol {
     Constant(Reflection),
+    CoordSystem(SquareMatrix),
     Knob(f64),
+    // Knob values saved by `save_knobs`
+    KnobList(HashMap<String, f64>),
     Num(Number),
 }
 
@@ -67,10 +91,12 @@ pub struct Operation {
     pub command: Command,
     pub light_const: Option<String>,
     pub knob: Option<String>,
+    // Where the command is in the script
+    pub span: Span,
 }
 impl Operation {
     pub fn new(command: Command, light_const: Option<String>, knob: Option<String>) -> Operation {
-        Operation { command, light_const, knob }
+        Operation { command, light_const, knob, span: Span::default() }
     }
 }
 
@@ -78,7 +104,12 @@ impl Operation {
 pub struct ToDoList {
     pub ops: Vec<Operation>,
     pub symbols: SymbolTable,
-    pub lights: Option<Vec<Light>>,
+    textures: TextureCache,
+    meshes: MeshCache,
+    // Frames of an animation are drawn this many at a time, each on its own thread
+    pub workers: usize,
+    // The name and text of the script, to show where errors are in it
+    pub(crate) script: Option<(String, String)>,
 }
 impl ToDoList {
     pub fn push_op(
@@ -86,7 +117,7 @@ impl ToDoList {
         command: Command,
         light_const: Option<String>,
         knob: Option<String>,
-    ) -> Result<(), ParseError> {
+    ) -> Result<(), MdlError> {
         let op = Operation::new(command, light_const, knob);
         self.ops.push(op);
         Ok(())
@@ -94,310 +125,933 @@ impl ToDoList {
 
     pub fn add_sym(&self, k: String, v: Symbol) {
         //self.symbols.insert(k, v);
-        self.symbols.borrow_mut().insert(k, v);
+        self.symbols.write().unwrap().insert(k, v);
     }
     pub fn get_sym(&self, key: &str) -> Option<Symbol> {
-        self.symbols.borrow().get(key).cloned()
-    }
-
-    pub fn add_light(&mut self, mut light: Light) -> Result<(), ParseError> {
-        // push the light after normalizing its vector
-        light.pos.normalize();
-        // replace the None with a Some before pushing
-        self.lights.get_or_insert_with(|| Vec::with_capacity(1));
-        self.lights.as_mut().unwrap().push(light);
-        Ok(())
+        self.symbols.read().unwrap().get(key).cloned()
     }
 
-    fn first_pass(&self) -> Option<(usize, String)> {
+    fn first_pass(&self) -> Result<Option<(usize, String)>, MdlError> {
         use Command::*;
 
         let (mut basename, mut frames) = (None, None);
-        let mut vary = false;
+        let mut vary = None;
         for operation in &self.ops {
             match &operation.command {
                 Basename(s) => basename = Some(s.to_owned()),
-                Frames(n) => frames = Some(n),
-                Vary(..) => vary = true,
+                Frames(n) => frames = Some((n, operation.span)),
+                Vary(..) | VarySpline(..) | Tween(..) => vary = vary.or(Some(operation.span)),
                 _ => {},
             }
         }
-        // TODO: Move these checks to semantic analyzer
-        // If `vary` wasn't found, we're not animating
-        if !vary {
-            None
-        }
-        // If `vary` was found but `frames` wasn't, user error
-        else if frames.is_none() {
-            panic!("`frames` command must also be given if `vary` command given!")
-        } else {
-            // Let's animate. Set a default basename if applicable
-            let base = "gif";
-            let basename = basename.unwrap_or_else(|| {
-                println!("`basename` command not found. Using {} as a default", base);
-                String::from(base)
-            });
-            let frames = eval_usize(&frames.unwrap(), Some(&self.symbols));
-            Some((frames, basename))
+        // Scripts from `analyzer::file` have already been checked for these
+        match (vary, frames) {
+            // If `vary` or `tween` weren't found, we're not animating
+            (None, _) => Ok(None),
+            // If `vary` was found but `frames` wasn't, user error
+            (Some(span), None) => {
+                Err(MdlError::at(span, "`frames` command must also be given if `vary` or `tween` command given"))
+            },
+            (Some(_), Some((frames, span))) => {
+                // Let's animate. Set a default basename if applicable
+                let base = "gif";
+                let basename = basename.unwrap_or_else(|| {
+                    println!("`basename` command not found. Using {} as a default", base);
+                    String::from(base)
+                });
+                let frames = eval_usize(frames, Some(&self.symbols)).map_err(|err| err.or_span(span))?;
+                Ok(Some((frames, basename)))
+            },
         }
     }
 
-    fn second_pass(&self, frames: usize) -> Vec<HashMap<String, f64>> {
+    // Work out the knobs in every frame. `set` and `setknobs` give knobs the value they
+    // have outside of the frames where they're varied or tweened
+    fn second_pass(&self, frames: usize) -> Result<Vec<FrameSymbols>, MdlError> {
+        use Command::*;
+
         let mut knob_table = vec![HashMap::new(); frames];
+        // Every knob in the script, for `setknobs`
+        let knobs: Vec<_> = self
+            .ops
+            .iter()
+            .filter_map(|operation| match &operation.command {
+                Vary(knob, ..) | VarySpline(knob, ..) | Set(knob, _) => Some(knob),
+                _ => operation.knob.as_ref(),
+            })
+            .collect();
+        // Knobs given a value by `set`, which they have before they're varied
+        let mut set = HashSet::new();
+        // Errors point back at the command they're in
+        let mut vary_knobs = |command: &Command| -> Result<(), MdlError> {
+            match command {
+                Vary(knob, frame_start, frame_end, val_start, val_end, easing) => {
+                    // TODO: Learn to write macros to reduce verbosity
+                    let frame_start: usize = eval_usize(frame_start, Some(&self.symbols))?;
+                    let frame_end: usize = eval_usize(frame_end, Some(&self.symbols))?;
+                    let val_start: f64 = eval_f64(val_start, Some(&self.symbols))?;
+                    let val_end: f64 = eval_f64(val_end, Some(&self.symbols))?;
+                    vary(&mut knob_table, knob, (frame_start, frame_end), (val_start, val_end), *easing)?;
+                },
+                VarySpline(knob, spline, keyframes) => {
+                    let keyframes: Vec<_> = keyframes
+                        .iter()
+                        .map(|(frame, val, slope)| {
+                            let slope = slope.as_ref().map(|slope| eval_f64(slope, Some(&self.symbols))).transpose()?;
+                            Ok((eval_usize(frame, Some(&self.symbols))?, eval_f64(val, Some(&self.symbols))?, slope))
+                        })
+                        .collect::<Result<_, MdlError>>()?;
+                    vary_spline(&mut knob_table, knob, *spline, &keyframes)?;
+                },
+                Set(knob, val) => {
+                    self.add_sym(knob.to_owned(), Symbol::Knob(eval_f64(val, Some(&self.symbols))?));
+                    set.insert(knob.to_owned());
+                },
+                SetKnobs(val) => {
+                    let val = eval_f64(val, Some(&self.symbols))?;
+                    for &knob in &knobs {
+                        self.add_sym(knob.to_owned(), Symbol::Knob(val));
+                    }
+                },
+                SaveKnobs(name) => {
+                    let list = self
+                        .symbols
+                        .read()
+                        .unwrap()
+                        .iter()
+                        .filter_map(|(knob, symbol)| match symbol {
+                            Symbol::Knob(val) => Some((knob.to_owned(), *val)),
+                            _ => None,
+                        })
+                        .collect();
+                    self.add_sym(name.to_owned(), Symbol::KnobList(list));
+                },
+                // Every knob in either list goes from its value in the first to its value
+                // in the second. Knobs missing from one list keep the same value
+                Tween(frame_start, frame_end, list0, list1) => {
+                    let frames = (eval_usize(frame_start, Some(&self.symbols))?, eval_usize(frame_end, Some(&self.symbols))?);
+                    let (list0, list1) = (self.knob_list(list0)?, self.knob_list(list1)?);
+                    let mut tweened: Vec<_> = list0.keys().chain(list1.keys()).collect();
+                    tweened.sort();
+                    tweened.dedup();
+                    for knob in tweened {
+                        let val_start = list0.get(knob).or_else(|| list1.get(knob)).unwrap();
+                        let val_end = list1.get(knob).unwrap_or(val_start);
+                        vary(&mut knob_table, knob, frames, (*val_start, *val_end), Easing::Linear)?;
+                    }
+                },
+                _ => {},
+            }
+            Ok(())
+        };
         for operation in &self.ops {
-            if let Command::Vary(knob, frame_start, frame_end, val_start, val_end) =
-                &operation.command
-            {
-                // TODO: Learn to write macros to reduce verbosity
-                let frame_start: usize = eval_usize(frame_start, Some(&self.symbols));
-                let frame_end: usize = eval_usize(frame_end, Some(&self.symbols));
-                let val_start: f64 = eval_f64(val_start, Some(&self.symbols));
-                let val_end: f64 = eval_f64(val_end, Some(&self.symbols));
-
-                // TODO: Move these checks to semantic analyzer
-                if frame_start > frame_end || frame_end > (frames - 1) {
-                    panic!(
-                        "Vary: start frame must be larger than end frame!
-                           Start: {}, End: {}",
-                        frame_start, frame_end
-                    );
+            vary_knobs(&operation.command).map_err(|err| err.or_span(operation.span))?;
+        }
+        // Knobs keep their last value in the frames after they're varied
+        for frame in 1..frames {
+            let (done, rest) = knob_table.split_at_mut(frame);
+            for (knob, val) in &done[frame - 1] {
+                rest[0].entry(knob.to_owned()).or_insert_with(|| val.clone());
+            }
+        }
+        // and their first value in the frames before, unless `set` gave them a value there
+        for frame in (0..frames.saturating_sub(1)).rev() {
+            let (rest, done) = knob_table.split_at_mut(frame + 1);
+            for (knob, val) in &done[0] {
+                if !set.contains(knob) {
+                    rest[frame].entry(knob.to_owned()).or_insert_with(|| val.clone());
                 }
-                let diff = (val_end - val_start) / (frame_end - frame_start) as f64;
-                let mut val = val_start;
-                #[allow(clippy::needless_range_loop)]
-                for frame in frame_start..frame_end {
-                    knob_table[frame].insert(knob.to_owned(), val);
-                    val += diff;
+            }
+        }
+        Ok(knob_table)
+    }
+
+    // Knobs saved by `save_knobs` as `name`
+    fn knob_list(&self, name: &str) -> Result<HashMap<String, f64>, MdlError> {
+        match self.get_sym(name) {
+            Some(Symbol::KnobList(list)) => Ok(list),
+            _ => Err(MdlError::new(format!("Knob list `{}` hasn't been saved", name))),
+        }
+    }
+
+    // Build the camera from the `camera` and `focal` commands. A `focal` without a
+    // `camera` looks at the center of the screen from `focal` units away
+    fn camera(&self, screen: &Screen) -> Result<Option<Camera>, MdlError> {
+        let (mut eye_aim, mut focal) = (None, None);
+        for operation in &self.ops {
+            let eval = |expr| eval_f64(expr, Some(&self.symbols)).map_err(|err| err.or_span(operation.span));
+            match &operation.command {
+                Command::Camera(ex, ey, ez, ax, ay, az, depth) => {
+                    let eye = Vector::new(eval(ex)?, eval(ey)?, eval(ez)?);
+                    let aim = Vector::new(eval(ax)?, eval(ay)?, eval(az)?);
+                    let depth = match depth {
+                        Some((near, far)) => Some((eval(near)?, eval(far)?)),
+                        None => None,
+                    };
+                    eye_aim = Some((eye, aim, depth, operation.span));
+                },
+                Command::Focal(f) => focal = Some((eval(f)?, operation.span)),
+                _ => {},
+            }
+        }
+        let (camera, depth, span) = match (eye_aim, focal) {
+            (Some((eye, aim, depth, span)), focal) => (Camera::new(eye, aim, focal.map(|f| f.0)), depth, span),
+            (None, Some((focal, span))) => {
+                let (x, y) = (screen.xres as f64 / 2., screen.yres as f64 / 2.);
+                (Camera::new(Vector::new(x, y, focal), Vector::new(x, y, 0.), Some(focal)), None, span)
+            },
+            (None, None) => return Ok(None),
+        };
+        let mut camera = camera.ok_or_else(|| MdlError::at(span, "The camera can't be aimed at the point it's at"))?;
+        if let Some((near, far)) = depth {
+            if near <= 0. || far <= near {
+                return Err(MdlError::at(span, "The near plane must be in front of the camera and closer than the far plane"));
+            }
+            camera.near = near;
+            camera.far = far;
+        }
+        Ok(Some(camera))
+    }
+
+    // The value of the operation's knob in the current frame
+    fn knob(&self, operation: &Operation, symbols: &FrameSymbols) -> Result<Option<f64>, MdlError> {
+        let knob = |k: &String| match symbols.get(k).cloned().or_else(|| self.get_sym(k)) {
+            Some(Symbol::Knob(v)) => Ok(v),
+            Some(_) => Err(MdlError::at(operation.span, format!("`{}` isn't a knob", k))),
+            None => Err(MdlError::at(operation.span, format!("Knob `{}` is never set or varied", k))),
+        };
+        operation.knob.as_ref().map(knob).transpose()
+    }
+
+    // Transform `m` by the coordinate system saved as `name`, or the top of the stack
+    // without one
+    fn apply_coord_system(
+        &self,
+        m: &mut Matrix,
+        name: Option<&String>,
+        cstack: &[SquareMatrix],
+        symbols: &FrameSymbols,
+    ) -> Result<(), MdlError> {
+        match name {
+            Some(name) => match symbols.get(name).cloned().or_else(|| self.get_sym(name)) {
+                Some(Symbol::CoordSystem(system)) => system.mult(m),
+                _ => return Err(MdlError::new(format!("Coordinate system `{}` hasn't been saved", name))),
+            },
+            None => m.apply_rcs(cstack),
+        }
+        Ok(())
+    }
+
+    // Load a mesh from the objects directory
+    fn mesh(&self, filename: &str) -> Result<Arc<Mesh>, MdlError> {
+        let mut meshes = self.meshes.lock().unwrap();
+        if let Some(mesh) = meshes.get(filename) {
+            return Ok(Arc::clone(mesh));
+        }
+        // get the file
+        let mut file = PathBuf::from("objects");
+        file.push(filename);
+        // read the file and parse it
+        let file = fs::read_to_string(&file)
+            .map_err(|err| MdlError::new(format!("Error reading mesh file `{}`: {}", file.display(), err)))?;
+        let (mut polygons, mut uvs) = (Matrix::default(), vec![]);
+        ObjParser::load(&mut polygons, &mut uvs, &file)
+            .map_err(|err| MdlError::new(format!("Error parsing mesh file `{}`: {}", filename, err)))?;
+        Ok(Arc::clone(meshes.entry(filename.to_owned()).or_insert_with(|| Arc::new((polygons, uvs)))))
+    }
+
+    // Load a texture from the textures directory
+    fn texture(&self, filename: &str, filter: Filter) -> Result<Arc<Texture>, MdlError> {
+        let key = (filename.to_owned(), filter);
+        let mut textures = self.textures.lock().unwrap();
+        if let Some(texture) = textures.get(&key) {
+            return Ok(Arc::clone(texture));
+        }
+        let mut file = PathBuf::from("textures");
+        file.push(filename);
+        let texture = Texture::open(&file, filter)
+            .map_err(|err| MdlError::new(format!("Error loading texture `{}`: {}", file.display(), err)))?;
+        Ok(Arc::clone(textures.entry(key).or_insert_with(|| Arc::new(texture))))
+    }
+
+    // Apply `push`, `pop`, `move`, `scale` and `rotate` to the coordinate stack.
+    // Every other command is ignored
+    fn transform(&self, command: &Command, knob: Option<f64>, cstack: &mut Vec<SquareMatrix>) -> Result<(), MdlError> {
+        use Command::*;
+        match command {
+            Push() => {
+                // push a copy of the last item
+                let copy = cstack.last().unwrap_or_default().clone();
+                cstack.push(copy);
+            },
+
+            Pop() => {
+                cstack.pop();
+                // Make sure that the stack is never empty
+                if cstack.is_empty() {
+                    cstack.push(SquareMatrix::default());
                 }
+            },
+
+            Translate(x, y, z) => {
+                let (x, y, z) = (eval_f64(x, Some(&self.symbols))?, eval_f64(y, Some(&self.symbols))?, eval_f64(z, Some(&self.symbols))?);
+                let (x, y, z) = match knob {
+                    Some(k) => (x * k, y * k, z * k),
+                    None => (x, y, z),
+                };
+                let mut tr = SquareMatrix::new_translate(x, y, z);
+                tr.apply_rcs(cstack);
+                cstack.pop();
+                cstack.push(tr);
+            },
+
+            Scale(x, y, z) => {
+                let (x, y, z) = (eval_f64(x, Some(&self.symbols))?, eval_f64(y, Some(&self.symbols))?, eval_f64(z, Some(&self.symbols))?);
+                let (x, y, z) = match knob {
+                    Some(k) => (x * k, y * k, z * k),
+                    None => (x, y, z),
+                };
+                let mut tr = SquareMatrix::new_scale(x, y, z);
+                tr.apply_rcs(cstack);
+                cstack.pop();
+                cstack.push(tr);
+            },
+
+            Rotate(axis, degrees) => {
+                let degrees = eval_f64(degrees, Some(&self.symbols))?;
+                let degrees = match knob {
+                    Some(k) => degrees * k,
+                    None => degrees,
+                };
+                let mut tr = match axis {
+                    Axis::X => SquareMatrix::new_rot_x(degrees),
+                    Axis::Y => SquareMatrix::new_rot_y(degrees),
+                    Axis::Z => SquareMatrix::new_rot_z(degrees),
+                };
+                tr.apply_rcs(cstack);
+                cstack.pop();
+                cstack.push(tr);
+            },
+
+            _ => {},
+        }
+        Ok(())
+    }
+
+    // Every light in the current frame. Point lights are placed by the coordinate system
+    // at the `light` command, but light everything. Without any `light`s, use the screen's
+    fn lights(&self, screen: &Screen, cstack: &[SquareMatrix], symbols: &FrameSymbols) -> Result<Vec<Light>, MdlError> {
+        let mut cstack = cstack.to_vec();
+        let mut lights = vec![];
+        for operation in &self.ops {
+            match &operation.command {
+                Command::Light(light) => {
+                    let mut light = light.clone();
+                    // Points have a w of 1 and directions have a w of 0
+                    let transform = |v: &Vector, w| {
+                        let mut m = Matrix::from(&[[v.x, v.y, v.z, w]][..]);
+                        m.apply_rcs(&cstack);
+                        Vector::from(&m.m[0][..3])
+                    };
+                    match &mut light.kind {
+                        LightKind::Directional => light.pos.normalize(),
+                        LightKind::Point { .. } => light.pos = transform(&light.pos, 1.),
+                        LightKind::Spot { direction, .. } => {
+                            light.pos = transform(&light.pos, 1.);
+                            *direction = Vector::normalized(&transform(direction, 0.));
+                        },
+                    }
+                    lights.push(light);
+                },
+                command => {
+                    let knob = self.knob(operation, symbols)?;
+                    self.transform(command, knob, &mut cstack).map_err(|err| err.or_span(operation.span))?
+                },
             }
         }
-        knob_table
+        Ok(if lights.is_empty() {
+            screen.lights.clone()
+        } else {
+            lights
+        })
     }
 
+    // Run every command in the frame, with `output` deciding what `display` and `save` do
     #[allow(clippy::many_single_char_names)]
-    pub fn run(self, screen: &mut Screen, cstack: &mut Vec<SquareMatrix>) {
+    fn draw_frame(
+        &self,
+        screen: &mut Screen,
+        cstack: &mut Vec<SquareMatrix>,
+        symbols: &FrameSymbols,
+        lights: &[Light],
+        mut output: Output,
+    ) -> Result<(), MdlError> {
         use Command::*;
 
         // Temporary edge/polygon matrix
         let mut draw = Matrix::default();
         // Temporary point matrix used for sphere and torus
         let mut points = Matrix::default();
+        // Texture coordinates of every point in `draw`
+        let mut uvs = vec![];
+        // Pattern for the shapes being drawn. Like `texture`, it lasts until the end of the frame
+        let mut pattern = None;
+        // Coordinate systems are saved along with the frame's knobs
+        let mut symbols = symbols.clone();
+
+        // Errors point back at the command they're in
+        let mut draw_operation = |i: usize, operation: &Operation| -> Result<(), MdlError> {
+            // clear matrix for every operation
+            draw.clear();
+            points.clear();
+            uvs.clear();
+            let command = &operation.command;
+
+            // From an Option<String>, get the symbol with that name from the hashmap,
+            // and extract the reflection from the Constant
+            let light_const = match &operation.light_const {
+                Some(name) => match self.get_sym(name) {
+                    Some(Symbol::Constant(r)) => Some(r),
+                    _ => return Err(MdlError::new(format!("Lighting constants `{}` are undefined", name))),
+                },
+                None => None,
+            };
+
+            let knob = self.knob(operation, &symbols)?;
+
+            match command {
+                Push() | Pop() | Translate(..) | Scale(..) | Rotate(..) => {
+                    self.transform(command, knob, cstack)?
+                },
+
+                Shading(shading, overlay) => {
+                    screen.shading = *shading;
+                    screen.overlay = *overlay;
+                },
+
+                Texture(texture) => {
+                    screen.texture = texture.as_ref().map(|(file, filter)| self.texture(file, *filter)).transpose()?;
+                    pattern = None;
+                },
+
+                Pattern(new) => {
+                    screen.texture = new.as_ref().map(|p| Arc::clone(&p.texture));
+                    pattern = new.clone();
+                },
+
+                SaveCoordSystem(name) => {
+                    let system = cstack.last().unwrap_or_default().clone();
+                    symbols.insert(name.to_owned(), Symbol::CoordSystem(system));
+                },
+
+                Display() | Save(_) => match &mut output {
+                    Output::Skip => {},
+                    Output::Now => ToDoList::output(operation, screen)?,
+                    Output::Later(copies) => copies.push((i, screen.clone())),
+                },
+
+                Mesh(filename, system) => {
+                    // add the mesh to the polygon matrix
+                    let mesh = self.mesh(filename)?;
+                    draw.m.extend_from_slice(&mesh.0.m);
+                    uvs.extend_from_slice(&mesh.1);
+                    pattern_coords(pattern.as_deref(), &draw, &mut uvs);
+                    // draw the polygon matrix
+                    self.apply_coord_system(&mut draw, system.as_ref(), cstack, &symbols)?;
+                    screen.draw_polygons(&draw, Some(&uvs), light_const.as_ref(), lights);
+                },
+
+                Cuboid(x, y, z, h, w, d, system) => {
+                    let (x, y, z) = (eval_f64(x, Some(&self.symbols))?, eval_f64(y, Some(&self.symbols))?, eval_f64(z, Some(&self.symbols))?);
+                    let (h, w, d) = (eval_f64(h, Some(&self.symbols))?, eval_f64(w, Some(&self.symbols))?, eval_f64(d, Some(&self.symbols))?);
+                    draw::add_box(&mut draw, &mut uvs, x, y, z, w, h, d);
+                    pattern_coords(pattern.as_deref(), &draw, &mut uvs);
+                    self.apply_coord_system(&mut draw, system.as_ref(), cstack, &symbols)?;
+                    screen.draw_polygons(&draw, Some(&uvs), light_const.as_ref(), lights);
+                },
+
+                Sphere(x, y, z, r, system) => {
+                    let (x, y, z, r) = (eval_f64(x, Some(&self.symbols))?, eval_f64(y, Some(&self.symbols))?, eval_f64(z, Some(&self.symbols))?, eval_f64(r, Some(&self.symbols))?);
+                    draw::add_sphere(&mut draw, &mut points, &mut uvs, x, y, z, r, screen.steps_3d);
+                    pattern_coords(pattern.as_deref(), &draw, &mut uvs);
+                    self.apply_coord_system(&mut draw, system.as_ref(), cstack, &symbols)?;
+                    screen.draw_polygons(&draw, Some(&uvs), light_const.as_ref(), lights);
+                },
+
+                Torus(x, y, z, r0, r1, system) => {
+                    let (x, y, z) = (eval_f64(x, Some(&self.symbols))?, eval_f64(y, Some(&self.symbols))?, eval_f64(z, Some(&self.symbols))?);
+                    let (r0, r1) = (eval_f64(r0, Some(&self.symbols))?, eval_f64(r1, Some(&self.symbols))?);
+                    draw::add_torus(&mut draw, &mut points, &mut uvs, x, y, z, r0, r1, screen.steps_3d);
+                    pattern_coords(pattern.as_deref(), &draw, &mut uvs);
+                    self.apply_coord_system(&mut draw, system.as_ref(), cstack, &symbols)?;
+                    screen.draw_polygons(&draw, Some(&uvs), light_const.as_ref(), lights);
+                },
+
+                Line(x0, y0, z0, system0, x1, y1, z1, system1, options) => {
+                    let (x0, y0, z0) = (eval_f64(x0, Some(&self.symbols))?, eval_f64(y0, Some(&self.symbols))?, eval_f64(z0, Some(&self.symbols))?);
+                    let (x1, y1, z1) = (eval_f64(x1, Some(&self.symbols))?, eval_f64(y1, Some(&self.symbols))?, eval_f64(z1, Some(&self.symbols))?);
+                    // Each end is transformed on its own, since they can be in different systems
+                    let mut p0 = Matrix::from(&[[x0, y0, z0, 1.]][..]);
+                    let mut p1 = Matrix::from(&[[x1, y1, z1, 1.]][..]);
+                    self.apply_coord_system(&mut p0, system0.as_ref(), cstack, &symbols)?;
+                    self.apply_coord_system(&mut p1, system1.as_ref(), cstack, &symbols)?;
+                    draw.push(p0.m[0]);
+                    draw.push(p1.m[0]);
+                    let style = screen.line_style;
+                    if let Some(width) = &options.width {
+                        screen.line_style.width = eval_f64(width, Some(&self.symbols))?;
+                    }
+                    screen.line_style.cap = options.cap.unwrap_or(style.cap);
+                    screen.line_style.smooth = options.smooth.unwrap_or(style.smooth);
+                    screen.draw_lines(&draw, screen.line_color);
+                    screen.line_style = style;
+                },
+
+                Constants(_) | Frames(_) | Basename(_) | Vary(..) | VarySpline(..) | Light(..) | Ambient(_) | Shadows(..) | Camera(..) | Focal(_) => {},
+                Set(..) | SetKnobs(_) | SaveKnobs(_) | Tween(..) => {},
+                //_ => unimplemented!("{:?}", command),
+            }
+            Ok(())
+        };
+        for (i, operation) in self.ops.iter().enumerate() {
+            draw_operation(i, operation).map_err(|err| err.or_span(operation.span))?;
+        }
+        Ok(())
+    }
+
+    // Show or save `screen` for a `display` or `save` command
+    fn output(operation: &Operation, screen: &Screen) -> Result<(), MdlError> {
+        match &operation.command {
+            Command::Display() => screen.display()?,
+            Command::Save(filename) => screen.write(&[filename.as_str()])?,
+            _ => {},
+        }
+        Ok(())
+    }
+
+    // Draw the script, or its animation. Errors point at where they are in the script
+    pub fn run(self, screen: &mut Screen, cstack: &mut Vec<SquareMatrix>) -> Result<(), MdlError> {
+        self.animate(screen, cstack).map_err(|err| match &self.script {
+            Some((file, source)) => err.in_file(file, source),
+            None => err,
+        })
+    }
+
+    #[allow(clippy::many_single_char_names)]
+    fn animate(&self, screen: &mut Screen, cstack: &mut Vec<SquareMatrix>) -> Result<(), MdlError> {
+        use Command::*;
 
         // Add variables to symbol table
         self.add_sym("XRES".into(), Symbol::Num(Number::PosInt(screen.xres)));
         self.add_sym("YRES".into(), Symbol::Num(Number::PosInt(screen.yres)));
 
         // Check for animation code in script
-        let animation = self.first_pass();
+        let animation = self.first_pass()?;
         // get the number of frames for first_pass, or default to 1 frame otherwise
         let frames = animation.as_ref().map_or(1, |s| s.0);
-        // If animating, generate the knob table
-        let knob_table = animation.as_ref().map(|s| self.second_pass(s.0));
+        // Generate the knob table, with a single frame when not animating
+        let knob_table = self.second_pass(frames)?;
         // extract basename, consuming `animation` in process
         let basename = animation.map(|s| s.1);
 
-        // first delete old output frames
-        if let Some(base) = &basename {
-            let mut path = PathBuf::from(PICTURE_DIR);
-            // gif dir
-            path.push(base);
-            dbg!(&path);
-            let status = fs::remove_dir_all(&path);
-            if let Err(e) = &status {
-                if e.kind() != ErrorKind::NotFound {
-                    status.unwrap();
-                }
+        // When animating, frames are encoded straight into `<basename>.gif`
+        let mut gif = match &basename {
+            Some(base) => {
+                let gif_name = format!("{}.gif", base);
+                let path = screen::picture_path(&[gif_name.as_str()])
+                    .map_err(|err| MdlError::new(format!("Error creating gif `{}`: {}", gif_name, err)))?;
+                println!("Making gif: {}", path.display());
+                let file = File::create(&path)
+                    .map_err(|err| MdlError::new(format!("Error creating gif `{}`: {}", path.display(), err)))?;
+                Some(GifEncoder::new(BufWriter::new(file), screen.xres, screen.yres, 0)?)
+            },
+            None => None,
+        };
+
+        if let Some(camera) = self.camera(screen)? {
+            screen.camera = Some(camera);
+        }
+        // The last `ambient` lights the whole scene, and `shadows` turns on shadows for it
+        for operation in &self.ops {
+            match operation.command {
+                Ambient(color) => screen.ambient_light = color,
+                Shadows(bias, pcf) => {
+                    let shadows = screen.shadows.get_or_insert_with(Default::default);
+                    shadows.bias = bias.unwrap_or(shadows.bias);
+                    shadows.pcf = pcf.unwrap_or(shadows.pcf);
+                },
+                _ => {},
             }
         }
 
-        // Get the list of light sources
-        let lights = self.lights.as_ref().unwrap_or(&screen.lights).clone();
-        let lights = lights.as_slice();
-
-        for frame in 0..frames {
-            dbg!(&frame);
+        self.draw_frames(screen, cstack, &knob_table, gif.as_mut())?;
 
-            if let Some(knob_table) = &knob_table {
-                for (knob, val) in knob_table[frame].iter() {
-                    self.add_sym(knob.to_owned(), Symbol::Knob(*val));
-                }
-            }
+        if let Some(gif) = gif {
+            gif.finish()?;
+        }
+        Ok(())
+    }
 
-            for operation in &self.ops {
-                // clear matrix for every operation
-                draw.clear();
-                points.clear();
-                let command = &operation.command;
-
-                // From an Option<String>, get the symbol with that name from the hashmap,
-                // and extract the reflection from the Constant
-                let light_const = operation
-                    .light_const
-                    .as_ref()
-                    // TODO: Use self.symbols[s] instead to panic when symbol isnt found
-                    .and_then(|s| self.get_sym(s))
-                    .map(|s| match s {
-                        Symbol::Constant(r) => r,
-                        _ => panic!("Expected light constant!"),
-                    });
-
-                // ditto but for the knob
-                let knob = match &operation.knob {
-                    Some(k) => {
-                        let symbol = self.get_sym(k).unwrap();
-                        match symbol {
-                            Symbol::Knob(v) => Some(v),
-                            _ => panic!("Expected knob!"),
-                        }
-                    },
-                    None => None,
-                };
+    // Draw every frame in the knob table, adding them to `gif` when animating
+    fn draw_frames<W: Write>(
+        &self,
+        screen: &mut Screen,
+        cstack: &mut Vec<SquareMatrix>,
+        knob_table: &[FrameSymbols],
+        mut gif: Option<&mut GifEncoder<W>>,
+    ) -> Result<(), MdlError> {
+        let workers = self.workers.min(knob_table.len());
+        if let (Some(gif), true) = (&mut gif, workers > 1) {
+            return self.run_parallel(screen, cstack, knob_table, workers, gif);
+        }
+        // `shading` and `texture` only last until the end of the frame
+        let (shading, overlay, texture) = (screen.shading, screen.overlay, screen.texture.clone());
 
-                match command {
-                    Push() => {
-                        // push a copy of the last item
-                        let copy = cstack.last().unwrap_or_default().clone();
-                        cstack.push(copy);
-                    },
-
-                    Pop() => {
-                        cstack.pop();
-                        // Make sure that the stack is never empty
-                        if cstack.is_empty() {
-                            cstack.push(SquareMatrix::default());
-                        }
-                    },
-
-                    Display() => screen.display(),
-                    Save(filename) => screen.write(&[filename.as_str()]).unwrap(),
-
-                    Mesh(filename) => {
-                        // get the file
-                        let mut file = PathBuf::from("objects");
-                        file.push(filename);
-                        // read the file and parse it, adding to the polygon matrix
-                        let file = fs::read_to_string(file).expect("Error reading mesh file!");
-                        ObjParser::load(&mut draw, &file).expect("Error parsing mesh file!");
-                        // draw the polygon matrix
-                        draw.apply_rcs(cstack);
-                        screen.draw_polygons(&draw, light_const.as_ref(), lights);
-                    },
-
-                    Translate(x, y, z) => {
-                        let (x, y, z) = (eval_f64(x, Some(&self.symbols)), eval_f64(y, Some(&self.symbols)), eval_f64(z, Some(&self.symbols)));
-                        let (x, y, z) = match knob {
-                            Some(k) => (x * k, y * k, z * k),
-                            None => (x, y, z),
-                        };
-                        let mut tr = SquareMatrix::new_translate(x, y, z);
-                        tr.apply_rcs(cstack);
-                        cstack.pop();
-                        cstack.push(tr);
-                    },
-
-                    Scale(x, y, z) => {
-                        let (x, y, z) = (eval_f64(x, Some(&self.symbols)), eval_f64(y, Some(&self.symbols)), eval_f64(z, Some(&self.symbols)));
-                        let (x, y, z) = match knob {
-                            Some(k) => (x * k, y * k, z * k),
-                            None => (x, y, z),
-                        };
-                        let mut tr = SquareMatrix::new_scale(x, y, z);
-                        tr.apply_rcs(cstack);
-                        cstack.pop();
-                        cstack.push(tr);
-                    },
-
-                    Rotate(axis, degrees) => {
-                        let degrees = eval_f64(degrees, Some(&self.symbols));
-                        let degrees = match knob {
-                            Some(k) => degrees * k,
-                            None => degrees,
-                        };
-                        let mut tr = match axis {
-                            Axis::X => SquareMatrix::new_rot_x(degrees),
-                            Axis::Y => SquareMatrix::new_rot_y(degrees),
-                            Axis::Z => SquareMatrix::new_rot_z(degrees),
-                        };
-                        tr.apply_rcs(cstack);
-                        cstack.pop();
-                        cstack.push(tr);
-                    },
-
-                    Cuboid(x, y, z, h, w, d) => {
-                        let (x, y, z) = (eval_f64(x, Some(&self.symbols)), eval_f64(y, Some(&self.symbols)), eval_f64(z, Some(&self.symbols)));
-                        let (h, w, d) = (eval_f64(h, Some(&self.symbols)), eval_f64(w, Some(&self.symbols)), eval_f64(d, Some(&self.symbols)));
-                        draw::add_box(&mut draw, x, y, z, w, h, d);
-                        draw.apply_rcs(cstack);
-                        screen.draw_polygons(&draw, light_const.as_ref(), lights);
-                    },
-
-                    Sphere(x, y, z, r) => {
-                        let (x, y, z, r) = (eval_f64(x, Some(&self.symbols)), eval_f64(y, Some(&self.symbols)), eval_f64(z, Some(&self.symbols)), eval_f64(r, Some(&self.symbols)));
-                        draw::add_sphere(&mut draw, &mut points, x, y, z, r, screen.steps_3d);
-                        draw.apply_rcs(cstack);
-                        screen.draw_polygons(&draw, light_const.as_ref(), lights);
-                    },
-
-                    Torus(x, y, z, r0, r1) => {
-                        let (x, y, z) = (eval_f64(x, Some(&self.symbols)), eval_f64(y, Some(&self.symbols)), eval_f64(z, Some(&self.symbols)));
-                        let (r0, r1) = (eval_f64(r0, Some(&self.symbols)), eval_f64(r1, Some(&self.symbols)));
-                        draw::add_torus(&mut draw, &mut points, x, y, z, r0, r1, screen.steps_3d);
-                        draw.apply_rcs(cstack);
-                        screen.draw_polygons(&draw, light_const.as_ref(), lights);
-                    },
-
-                    Line(x0, y0, z0, x1, y1, z1) => {
-                        let (x0, y0, z0) = (eval_f64(x0, Some(&self.symbols)), eval_f64(y0, Some(&self.symbols)), eval_f64(z0, Some(&self.symbols)));
-                        let (x1, y1, z1) = (eval_f64(x1, Some(&self.symbols)), eval_f64(y1, Some(&self.symbols)), eval_f64(z1, Some(&self.symbols)));
-                        draw::add_edge(&mut draw, x0, y0, z0, x1, y1, z1);
-                        draw.apply_rcs(cstack);
-                        screen.draw_lines(&draw, screen.line_color);
-                    },
-
-                    Constants(_) | Frames(_) | Basename(_) | Vary(..) | Light(..) => {},
-                    //_ => unimplemented!("{:?}", command),
-                }
-            }
+        for symbols in knob_table {
+            screen.shading = shading;
+            screen.overlay = overlay;
+            screen.texture = texture.clone();
+            self.render(screen, cstack, symbols, Output::Now)?;
 
             // When animating, at the end of every frame:
-            if let Some(base) = &basename {
-                // Save the screen
-                let file_name = format!("{:03}.png", frame); // pad filename with 3 zeros
-                let path = &[base.as_str(), file_name.as_str()][..];
-                screen.write(path).expect("Error writing file!");
+            if let Some(gif) = &mut gif {
+                // Add the screen to the gif
+                gif.add_frame(screen, FRAME_DELAY)?;
 
                 // Reset the screen and coordinate systems
                 screen.clear();
                 cstack.clear();
             }
         }
+        Ok(())
+    }
 
-        // When animating, at the end of all frames, convert the images to a gif
-        if let Some(base) = &basename {
-            let pic_frames = format!("{}/{}/*", PICTURE_DIR, base);
-            let gif_name = format!("{}/{}.gif", PICTURE_DIR, base);
-            let convert = SubProcess::new("convert")
-                .arg("-delay")
-                .arg("1.7")
-                .arg(&pic_frames)
-                .arg(&gif_name)
-                .spawn();
-            match convert {
-                Ok(mut proc) => {
-                    println!("Making gif: {}", &gif_name);
-                    proc.wait().unwrap();
-                },
-                Err(err) => panic!(err),
-            };
+    // Draw a frame, along with the shadow maps for its lights
+    fn render(&self, screen: &mut Screen, cstack: &mut Vec<SquareMatrix>, symbols: &FrameSymbols, output: Output) -> Result<(), MdlError> {
+        // Point lights can move between frames
+        let mut lights = self.lights(screen, cstack, symbols)?;
+        if screen.shadows.is_some() {
+            // Go through the frame without drawing anything, to find what casts shadows
+            screen.collect_casters();
+            self.draw_frame(screen, &mut cstack.clone(), symbols, &lights, Output::Skip)?;
+            let casters = screen.take_casters().unwrap_or_default();
+            for light in &mut lights {
+                light.shadow = ShadowMap::new(light, &casters, screen).map(Arc::new);
+            }
         }
+        self.draw_frame(screen, cstack, symbols, &lights, output)
+    }
+
+    // Draw the frames of an animation on `workers` threads, each with its own copy of the
+    // screen and coordinate stack. Frames are added to the gif in order as they finish, and
+    // `display` and `save` show what the screen looked like when the script got to them
+    fn run_parallel<W: Write>(
+        &self,
+        screen: &Screen,
+        cstack: &[SquareMatrix],
+        knob_table: &[FrameSymbols],
+        workers: usize,
+        gif: &mut GifEncoder<W>,
+    ) -> Result<(), MdlError> {
+        // Share the cores between the workers instead of each screen using all of them
+        let mut screen = screen.clone();
+        screen.threads = (screen.threads / workers).max(1);
+        let screen = &screen;
+
+        // Frames are handed out in order, and no more than `window` of them are drawn or
+        // waiting for the ones ahead of them at once, so that a slow frame doesn't leave
+        // every frame after it sitting in memory. Frames past the end stop the workers
+        let window = 2 * workers;
+        let (next_sender, next) = mpsc::channel();
+        let next = Mutex::new(next);
+        let (sender, receiver) = mpsc::channel();
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                let (next, sender) = (&next, sender.clone());
+                scope.spawn(move || loop {
+                    let frame = match next.lock().unwrap().recv() {
+                        Ok(frame) => frame,
+                        Err(_) => break,
+                    };
+                    let symbols = match knob_table.get(frame) {
+                        Some(symbols) => symbols,
+                        None => break,
+                    };
+                    let mut screen = screen.clone();
+                    let mut copies = vec![];
+                    let result = self.render(&mut screen, &mut cstack.to_vec(), symbols, Output::Later(&mut copies));
+                    if sender.send((frame, result.map(|_| (screen, copies)))).is_err() {
+                        break;
+                    }
+                });
+            }
+            drop(sender);
+            // Dropped along with the receiver if there's an error, which stops the workers
+            let next_sender = next_sender;
+            for frame in 0..window {
+                let _ = next_sender.send(frame);
+            }
+
+            // Frames that finished before the ones ahead of them
+            let mut finished = BTreeMap::new();
+            let mut frame = 0;
+            for (i, screen) in receiver {
+                finished.insert(i, screen?);
+                while let Some((screen, copies)) = finished.remove(&frame) {
+                    for (i, copy) in copies {
+                        let operation = &self.ops[i];
+                        ToDoList::output(operation, &copy).map_err(|err| err.or_span(operation.span))?;
+                    }
+                    gif.add_frame(&screen, FRAME_DELAY)?;
+                    // Which makes room for another one
+                    let _ = next_sender.send(frame + window);
+                    frame += 1;
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+// Go from `val_start` to `val_end` over the frames from `frame_start` to `frame_end`
+fn vary(knob_table: &mut [FrameSymbols], knob: &str, (frame_start, frame_end): (usize, usize), (val_start, val_end): (f64, f64), easing: Easing) -> Result<(), MdlError> {
+    // Checked before the script is run, except for frames that depend on `XRES` or `YRES`
+    if frame_start > frame_end || frame_end >= knob_table.len() {
+        return Err(MdlError::new(format!(
+            "Vary: end frame must be after the start frame and no later than the last frame. Start: {}, End: {}",
+            frame_start, frame_end
+        )));
+    }
+    // Both ends are included, so the knob ends up at exactly `val_end`
+    for (i, symbols) in knob_table[frame_start..=frame_end].iter_mut().enumerate() {
+        let t = if frame_end > frame_start { i as f64 / (frame_end - frame_start) as f64 } else { 1. };
+        symbols.insert(knob.to_owned(), Symbol::Knob(val_start + (val_end - val_start) * easing.apply(t)));
+    }
+    Ok(())
+}
+
+// Follow a spline through `(frame, value, slope)` keyframes. Catmull-Rom splines work
+// out their own slopes
+fn vary_spline(knob_table: &mut [FrameSymbols], knob: &str, spline: Spline, keyframes: &[(usize, f64, Option<f64>)]) -> Result<(), MdlError> {
+    // Checked before the script is run, except for frames that depend on `XRES` or `YRES`
+    let frames: Vec<_> = keyframes.iter().map(|key| key.0).collect();
+    if frames.windows(2).any(|pair| pair[0] >= pair[1]) || frames.iter().any(|&frame| frame >= knob_table.len()) {
+        return Err(MdlError::new(format!("Vary: keyframes must be in order and no later than the last frame. Frames: {:?}", frames)));
+    }
+    let keyframes = match spline {
+        Spline::CatmullRom => {
+            let points: Vec<_> = keyframes.iter().map(|&(frame, value, _)| (frame as f64, value)).collect();
+            animation::catmull_rom(&points)
+        },
+        Spline::Hermite => keyframes
+            .iter()
+            .map(|&(frame, value, slope)| Keyframe { frame: frame as f64, value, slope: slope.unwrap_or(0.) })
+            .collect(),
+    };
+    let first = frames[0];
+    for (frame, symbols) in knob_table[first..=frames[frames.len() - 1]].iter_mut().enumerate() {
+        let val = animation::interpolate(&keyframes, (first + frame) as f64);
+        symbols.insert(knob.to_owned(), Symbol::Knob(val));
+    }
+    Ok(())
+}
+
+// Patterns are mapped by position in object space, before any transformations
+fn pattern_coords(pattern: Option<&Pattern>, polygons: &Matrix, uvs: &mut Vec<[f64; 2]>) {
+    if let Some(pattern) = pattern {
+        uvs.clear();
+        uvs.extend(polygons.m.iter().map(|p| pattern.coords(p)));
     }
 }
 
 impl Default for ToDoList {
     fn default() -> Self {
         let ops = vec![];
-        let lights = None;
-        let symbols = RefCell::new(HashMap::new());
-        ToDoList { ops, symbols, lights }
+        let symbols = RwLock::new(HashMap::new());
+        let (textures, meshes) = (Mutex::new(HashMap::new()), Mutex::new(HashMap::new()));
+        ToDoList { ops, symbols, textures, meshes, workers: 1, script: None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{analyzer, ast};
+
+    fn knob(symbols: &FrameSymbols, knob: &str) -> Option<f64> {
+        match symbols.get(knob) {
+            Some(Symbol::Knob(val)) => Some(*val),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn tween() {
+        let text = "
+frames 5
+set a 1
+save_knobs start
+set a 3
+set b 2
+save_knobs end
+setknobs 0
+tween 0 4 start end
+rotate x 10 c
+";
+        let todo = analyzer::analyze_nodes(&ast::parse(text).unwrap()).unwrap();
+        let knob_table = todo.second_pass(5).unwrap();
+        let a: Vec<_> = knob_table[..4].iter().map(|symbols| knob(symbols, "a")).collect();
+        assert_eq!(vec![Some(1.), Some(1.5), Some(2.), Some(2.5)], a);
+        // `b` isn't in the first list, so it doesn't change
+        assert_eq!(Some(2.), knob(&knob_table[2], "b"));
+        // `setknobs` sets every knob, even ones that are only used by transformations
+        let globals = todo.symbols.read().unwrap();
+        assert_eq!((Some(0.), Some(0.)), (knob(&globals, "a"), knob(&globals, "c")));
+    }
+
+    // Runs a script that analyzes fine on a small screen, as if it were read from `script.mdl`
+    fn run_script(text: &str) -> Result<(), MdlError> {
+        let mut todo = analyzer::analyze_nodes(&ast::parse(text)?).unwrap();
+        todo.script = Some(("script.mdl".into(), text.into()));
+        let screen = lib_graphics::screen::ScreenBuilder { xres: 10, yres: 10, ..Default::default() };
+        todo.run(&mut screen.create(), &mut vec![lib_graphics::IDENTITY])
+    }
+
+    #[test]
+    fn error_caret() {
+        let err = run_script("push\nmove 1 2 3\nrotate y (360 * spin)\n").unwrap_err();
+        assert_eq!(Some(Span { line: 3, col: 1, end_col: 22 }), err.span);
+        let expected = "\
+error: Variable `spin` is undefined
+ --> script.mdl:3:1
+  |
+3 | rotate y (360 * spin)
+  | ^^^^^^^^^^^^^^^^^^^^^
+";
+        assert_eq!(expected, err.to_string());
+    }
+
+    #[test]
+    fn parse_error_span() {
+        // Parse errors point at where parsing stopped
+        let err = ast::parse("push\nbox 1 2\n").unwrap_err();
+        assert_eq!(Some(2), err.span.map(|span| span.line));
+    }
+
+    #[test]
+    fn camera_aimed_at_itself() {
+        // The camera has nowhere to look
+        let err = run_script("sphere 0 0 0 5\ncamera 1 2 3 1 2 3\n").unwrap_err();
+        assert_eq!(Some(2), err.span.map(|span| span.line));
+        assert_eq!("The camera can't be aimed at the point it's at", err.message);
+    }
+
+    #[test]
+    fn camera_planes() {
+        let err = run_script("camera 0 0 10 0 0 0 5 2\n").unwrap_err();
+        assert_eq!("The near plane must be in front of the camera and closer than the far plane", err.message);
+    }
+
+    #[test]
+    fn overflow_errors() {
+        // Numbers too large to fit, written out or worked out, point at where they are
+        let err = ast::parse("push\nmove 99999999999999999999 0 0\n").unwrap_err();
+        assert_eq!(Some(Span { line: 2, col: 6, end_col: 26 }), err.span);
+        assert_eq!("`99999999999999999999` is too large a number", err.message);
+        let err = run_script("push\nmove (9223372036854775807 * 4) 0 0\n").unwrap_err();
+        assert_eq!(("Arithmetic overflow", Some(2)), (err.message.as_str(), err.span.map(|span| span.line)));
+    }
+
+    #[test]
+    fn arithmetic() {
+        use Number::*;
+        let apply = |l: Number, op: Op, r: Number| l.apply(&op, r).map(f64::from);
+        // Integers stay integers, and go negative instead of wrapping around
+        assert_eq!(Some(-2.), apply(PosInt(3), Op::Subtract, PosInt(5)));
+        assert_eq!(Some(-3.), apply(Int(-7), Op::Divide, PosInt(2)));
+        assert_eq!(Some(3.5), apply(Int(7), Op::Divide, Float(2.)));
+        assert_eq!(None, apply(PosInt(usize::MAX), Op::Add, PosInt(1)));
+        assert_eq!(None, apply(Int(isize::MIN), Op::IntDivide, Int(-1)));
+        // An animation without frames has nothing to vary
+        assert!(super::vary(&mut [], "a", (0, 0), (0., 1.), Easing::Linear).is_err());
+    }
+
+    #[test]
+    fn light_transforms() {
+        let text = "
+push
+move 10 0 0
+rotate z 90
+light point 255 255 255 1 2 3
+pop
+light spot 255 255 255 0 0 0 1 0 0 10 20
+";
+        let todo = analyzer::analyze_nodes(&ast::parse(text).unwrap()).unwrap();
+        let screen = lib_graphics::screen::ScreenBuilder { xres: 10, yres: 10, ..Default::default() }.create();
+        let lights = todo.lights(&screen, &[lib_graphics::IDENTITY], &HashMap::new()).unwrap();
+        let close = |v: &Vector, (x, y, z): (f64, f64, f64)| (v.x - x).abs() + (v.y - y).abs() + (v.z - z).abs() < 1e-9;
+        // Point lights are moved by the coordinate system they're in
+        assert!(close(&lights[0].pos, (8., 1., 3.)));
+        // Which is popped before the spot light
+        assert!(close(&lights[1].pos, (0., 0., 0.)));
+    }
+
+    #[test]
+    fn workers() {
+        let text = "
+frames 7
+basename spin
+vary spin 0 6 0 1
+shading phong
+push
+move 32 32 0
+rotate y 360 spin
+rotate x 30
+box -15 15 15 30 30 30
+save todo_workers.png
+box -5 5 20 10 10 10
+";
+        let mut todo = analyzer::analyze_nodes(&ast::parse(text).unwrap()).unwrap();
+        let knob_table = todo.second_pass(7).unwrap();
+        let saved = lib_graphics::screen::picture_path(&["todo_workers.png"]).unwrap();
+        let gif = |todo: &mut ToDoList, workers| {
+            todo.workers = workers;
+            let mut screen = lib_graphics::screen::ScreenBuilder { xres: 64, yres: 64, ..Default::default() }.create();
+            let mut gif = GifEncoder::new(vec![], 64, 64, 0).unwrap();
+            todo.draw_frames(&mut screen, &mut vec![lib_graphics::IDENTITY], &knob_table, Some(&mut gif)).unwrap();
+            (gif.finish().unwrap(), fs::read(&saved).unwrap())
+        };
+        let serial = gif(&mut todo, 1);
+        // The same with fewer frames allowed at once than there are, and a worker for each,
+        // with `save` keeping the frame from before the sphere is drawn either way
+        let (fewer, each) = (gif(&mut todo, 3), gif(&mut todo, 10));
+        fs::remove_file(&saved).unwrap();
+        assert!(serial == fewer && serial == each);
+    }
+
+    #[test]
+    fn vary() {
+        let text = "
+frames 6
+vary a 1 3 0 10 ease_in
+vary b catmull_rom 0 0 2 4 4 0
+vary c hermite 0 0 0 4 8 0
+set d 7
+vary d 2 4 0 1
+setknobs 3
+vary e 2 4 0 1
+";
+        let todo = analyzer::analyze_nodes(&ast::parse(text).unwrap()).unwrap();
+        let knob_table = todo.second_pass(6).unwrap();
+        let values = |name| knob_table.iter().map(|symbols| knob(symbols, name)).collect::<Vec<_>>();
+        // Held before and after the frames they're varied over, and the end frame is reached
+        assert_eq!(vec![Some(0.), Some(0.), Some(2.5), Some(10.), Some(10.), Some(10.)], values("a"));
+        assert_eq!((Some(4.), Some(0.)), (knob(&knob_table[2], "b"), knob(&knob_table[5], "b")));
+        // The keyframes have no slope, so the spline eases between them
+        assert_eq!(Some(4.), knob(&knob_table[2], "c"));
+        // `set` gives `d` its value before it's varied
+        assert_eq!(vec![None, None, Some(0.), Some(0.5), Some(1.), Some(1.)], values("d"));
+        // but `setknobs` doesn't, since it's only for knobs that aren't varied
+        assert_eq!(vec![Some(0.), Some(0.), Some(0.), Some(0.5), Some(1.), Some(1.)], values("e"));
     }
 }
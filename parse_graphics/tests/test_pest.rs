@@ -72,6 +72,7 @@ fn parse_translate() {
         "move 1 2 3 foobarbaz",
         as_str(MDLParser::parse(Rule::translate, "move 1 2 3 foobarbaz"))
     );
+    assert_eq!("move x y z", as_str(MDLParser::parse(Rule::translate, "move x y z")));
 }
 
 #[test]
@@ -79,6 +80,7 @@ fn parse_rotate() {
     assert_eq!("rotate x 20", as_str(MDLParser::parse(Rule::rotate, "rotate x 20")));
     assert_eq!("rotate y -2 foo", as_str(MDLParser::parse(Rule::rotate, "rotate y -2 foo")));
     assert_eq!("", as_str(MDLParser::parse(Rule::rotate, "rotate xy 5")));
+    assert_eq!("rotate y angle spin", as_str(MDLParser::parse(Rule::rotate, "rotate y angle spin")));
 }
 
 #[test]
@@ -109,8 +111,165 @@ fn parse_constants() {
         "constants foo 1 2 3 4 5 6 7 8 9 10 11 12",
         as_str(MDLParser::parse(Rule::constants, "constants foo 1 2 3 4 5 6 7 8 9 10 11 12"))
     );
+    // Opacity, with or without an emissive color
     assert_eq!(
-        "constants foo 1 2 3 4 5 6 7 8 9 ",
+        "constants foo 1 2 3 4 5 6 7 8 9 0.5",
+        as_str(MDLParser::parse(Rule::constants, "constants foo 1 2 3 4 5 6 7 8 9 0.5"))
+    );
+    assert_eq!(
+        "constants foo 1 2 3 4 5 6 7 8 9 10 11 12 0.5",
+        as_str(MDLParser::parse(Rule::constants, "constants foo 1 2 3 4 5 6 7 8 9 10 11 12 0.5"))
+    );
+    assert_eq!(
+        "constants foo 1 2 3 4 5 6 7 8 9 10",
         as_str(MDLParser::parse(Rule::constants, "constants foo 1 2 3 4 5 6 7 8 9 10 11"))
     );
 }
+
+#[test]
+fn parse_light() {
+    assert_eq!(
+        "light 255 0 0 1 1 1",
+        as_str(MDLParser::parse(Rule::light, "light 255 0 0 1 1 1"))
+    );
+    assert_eq!(
+        "light directional 255 0 0 1 1 1",
+        as_str(MDLParser::parse(Rule::light, "light directional 255 0 0 1 1 1"))
+    );
+    assert_eq!(
+        "light point 255 0 0 250 250 100 1 0.01 0",
+        as_str(MDLParser::parse(Rule::light, "light point 255 0 0 250 250 100 1 0.01 0"))
+    );
+    assert_eq!(
+        "light spot 255 0 0 250 250 100 0 0 -1 10 20",
+        as_str(MDLParser::parse(Rule::light, "light spot 255 0 0 250 250 100 0 0 -1 10 20"))
+    );
+    assert_eq!(
+        "light spot 255 0 0 250 250 100 0 0 -1 10 20 1 0 0.0001",
+        as_str(MDLParser::parse(Rule::light, "light spot 255 0 0 250 250 100 0 0 -1 10 20 1 0 0.0001"))
+    );
+}
+
+#[test]
+fn parse_ambient() {
+    assert_eq!("ambient 50 50 50", as_str(MDLParser::parse(Rule::ambient, "ambient 50 50 50")));
+    assert_eq!("", as_str(MDLParser::parse(Rule::ambient, "ambient 50 50")));
+}
+
+#[test]
+fn parse_shadows() {
+    assert_eq!("shadows", as_str(MDLParser::parse(Rule::shadows, "shadows")));
+    assert_eq!("shadows 1.5", as_str(MDLParser::parse(Rule::shadows, "shadows 1.5")));
+    assert_eq!("shadows 1.5 2", as_str(MDLParser::parse(Rule::shadows, "shadows 1.5 2")));
+}
+
+#[test]
+fn parse_camera() {
+    assert_eq!(
+        "camera 0 0 500 (XRES / 2) 0 0",
+        as_str(MDLParser::parse(Rule::camera, "camera 0 0 500 (XRES / 2) 0 0"))
+    );
+    assert_eq!("camera 0 0 500 0 0 0 10 1000", as_str(MDLParser::parse(Rule::camera, "camera 0 0 500 0 0 0 10 1000")));
+    assert_eq!("", as_str(MDLParser::parse(Rule::camera, "camera 0 0 500")));
+    assert_eq!("focal 1.5", as_str(MDLParser::parse(Rule::focal, "focal 1.5")));
+}
+
+#[test]
+fn parse_shading() {
+    assert_eq!("shading phong", as_str(MDLParser::parse(Rule::shading, "shading phong")));
+    assert_eq!("shading Gouraud", as_str(MDLParser::parse(Rule::shading, "shading Gouraud")));
+    assert_eq!("", as_str(MDLParser::parse(Rule::shading, "shading phongs")));
+    assert_eq!(
+        "shading wireframe hidden",
+        as_str(MDLParser::parse(Rule::shading, "shading wireframe hidden"))
+    );
+    assert_eq!(
+        "shading flat wireframe",
+        as_str(MDLParser::parse(Rule::shading, "shading flat wireframe"))
+    );
+}
+
+#[test]
+fn parse_texture() {
+    assert_eq!("texture", as_str(MDLParser::parse(Rule::texture, "texture")));
+    assert_eq!("texture :grid.png", as_str(MDLParser::parse(Rule::texture, "texture :grid.png")));
+    assert_eq!(
+        "texture :grid.png nearest",
+        as_str(MDLParser::parse(Rule::texture, "texture :grid.png nearest"))
+    );
+    assert_eq!("bilinear", as_str(MDLParser::parse(Rule::filter, "bilinear")));
+    assert_eq!("", as_str(MDLParser::parse(Rule::filter, "bilinears")));
+    assert_eq!("trilinear", as_str(MDLParser::parse(Rule::filter, "trilinear")));
+}
+
+#[test]
+fn parse_pattern() {
+    assert_eq!("pattern", as_str(MDLParser::parse(Rule::pattern, "pattern")));
+    assert_eq!(
+        "pattern checker 10 255 255 255 0 0 0",
+        as_str(MDLParser::parse(Rule::pattern, "pattern checker 10 255 255 255 0 0 0"))
+    );
+    assert_eq!(
+        "pattern stripes y 2.5 255 0 0 0 0 255",
+        as_str(MDLParser::parse(Rule::pattern, "pattern stripes y 2.5 255 0 0 0 0 255"))
+    );
+    // Missing the second color
+    assert_eq!("pattern ", as_str(MDLParser::parse(Rule::pattern, "pattern grid 10 255 255 255")));
+    assert_eq!("gradient", as_str(MDLParser::parse(Rule::pattern_type, "gradient")));
+}
+
+#[test]
+fn parse_line() {
+    assert_eq!("line 0 0 0 100 100 100", as_str(MDLParser::parse(Rule::line, "line 0 0 0 100 100 100")));
+    assert_eq!(
+        "line width 3 round smooth 0 0 0 100 100 100",
+        as_str(MDLParser::parse(Rule::line, "line width 3 round smooth 0 0 0 100 100 100"))
+    );
+    assert_eq!(
+        "line width (w * 2) butt glowing 0 0 0 100 100 100",
+        as_str(MDLParser::parse(Rule::line, "line width (w * 2) butt glowing 0 0 0 100 100 100"))
+    );
+    assert_eq!("sharp", as_str(MDLParser::parse(Rule::smoothing, "sharp")));
+    assert_eq!("", as_str(MDLParser::parse(Rule::line_cap, "rounds")));
+}
+
+#[test]
+fn parse_coord_system() {
+    assert_eq!("save_coord_system foo", as_str(MDLParser::parse(Rule::save_coord_system, "save_coord_system foo")));
+    assert_eq!("", as_str(MDLParser::parse(Rule::save_coord_system, "save_coord_system")));
+    assert_eq!(
+        "mesh shiny :teapot.obj foo",
+        as_str(MDLParser::parse(Rule::mesh, "mesh shiny :teapot.obj foo"))
+    );
+    assert_eq!(
+        "line 0 0 0 foo 100 100 100 bar",
+        as_str(MDLParser::parse(Rule::line, "line 0 0 0 foo 100 100 100 bar"))
+    );
+    // Saving a picture named like a coordinate system still works
+    assert_eq!("save coord_system", as_str(MDLParser::parse(Rule::save, "save coord_system")));
+}
+
+#[test]
+fn parse_knobs() {
+    assert_eq!("set spin 0.5", as_str(MDLParser::parse(Rule::set, "set spin 0.5")));
+    assert_eq!("set spin (FRAMES / 2)", as_str(MDLParser::parse(Rule::set, "set spin (FRAMES / 2)")));
+    assert_eq!("setknobs 1", as_str(MDLParser::parse(Rule::setknobs, "setknobs 1")));
+    assert_eq!("save_knobs pose", as_str(MDLParser::parse(Rule::save_knobs, "save_knobs pose")));
+    assert_eq!("tween 0 10 pose0 pose1", as_str(MDLParser::parse(Rule::tween, "tween 0 10 pose0 pose1")));
+    assert_eq!("", as_str(MDLParser::parse(Rule::tween, "tween 0 10 pose0")));
+    // Statements are tried in order, so `setknobs` isn't read as `set knobs`
+    let program = MDLParser::parse(Rule::program, "setknobs 1").unwrap();
+    assert_eq!(Rule::setknobs, program.into_iter().next().unwrap().as_rule());
+}
+
+#[test]
+fn parse_vary() {
+    assert_eq!("vary spin 0 9 0 1", as_str(MDLParser::parse(Rule::vary, "vary spin 0 9 0 1")));
+    assert_eq!("vary spin 0 9 0 1 ease_in_out", as_str(MDLParser::parse(Rule::vary, "vary spin 0 9 0 1 ease_in_out")));
+    assert_eq!("vary spin 0 9 0 1 ease_in", as_str(MDLParser::parse(Rule::vary, "vary spin 0 9 0 1 ease_in")));
+    assert_eq!("vary spin catmull_rom 0 0 5 1 9 0", as_str(MDLParser::parse(Rule::vary, "vary spin catmull_rom 0 0 5 1 9 0")));
+    assert_eq!("vary spin hermite 0 0 1 9 1 0", as_str(MDLParser::parse(Rule::vary, "vary spin hermite 0 0 1 9 1 0")));
+    let program = MDLParser::parse(Rule::program, "vary spin 0 9 0 1 ease_in_out").unwrap();
+    let vary = program.into_iter().next().unwrap().into_inner();
+    assert_eq!(Some(Rule::easing), vary.last().map(|pair| pair.as_rule()));
+}
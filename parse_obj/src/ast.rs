@@ -6,6 +6,7 @@ use pest::Parser;
 #[derive(Clone, Debug)]
 pub enum ParseCommand {
     Vertex,
+    TexCoord,
     Face,
     NOOP,
 }
@@ -16,14 +17,15 @@ impl From<&Rule> for ParseCommand {
         use Rule::*;
         match r {
             vertex => Pcmd::Vertex,
+            tex_coord => Pcmd::TexCoord,
             face => Pcmd::Face,
 
             // ignored commands
-            group => Pcmd::NOOP,
+            group | normal => Pcmd::NOOP,
 
             // Statements that are handled by `node_from_statement`
             // Primitve `Rule`s aren't converted to `ParseCommand`s
-            float | whole => panic!("{:?} is not a command!", r),
+            float | whole | face_vertex | tex_index => panic!("{:?} is not a command!", r),
             // These are already unwrapped
             statement | SKIP | EOI => unreachable!(),
             // These are silent
@@ -36,9 +38,12 @@ impl From<&Rule> for ParseCommand {
 pub enum AstNode {
     Float(f64),
     Whole(usize),
+    // Index of a vertex and of its texture coordinates
+    FaceVertex(usize, Option<usize>),
     ObjCommand { command: ParseCommand, args: Vec<AstNode> },
 }
 
+#[allow(clippy::result_large_err)]
 pub fn parse(source: &str) -> Result<Vec<AstNode>, Error<Rule>> {
     let mut ast: Vec<AstNode> = vec![];
 
@@ -67,6 +72,15 @@ fn node_from_statement(pair: Pair<Rule>) -> AstNode {
         // Primitives
         Rule::float => AstNode::Float(pair.as_str().parse::<f64>().unwrap()),
         Rule::whole => AstNode::Whole(pair.as_str().parse::<usize>().unwrap()),
+        Rule::face_vertex => {
+            let mut inner = pair.into_inner();
+            let vertex = inner.next().unwrap().as_str().parse::<usize>().unwrap();
+            let tex_coord = inner
+                .next()
+                .filter(|p| p.as_rule() == Rule::tex_index)
+                .map(|p| p.as_str().parse::<usize>().unwrap());
+            AstNode::FaceVertex(vertex, tex_coord)
+        },
         // These are silent or already unwrapped
         Rule::EOI | Rule::program | Rule::WHITESPACE | Rule::COMMENT => unreachable!(),
         // Commands
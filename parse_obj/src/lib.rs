@@ -4,9 +4,10 @@ use ast::AstNode::*;
 use ast::ParseCommand as PCmd;
 use lib_graphics::Matrix;
 use std::error::Error;
-use std::f64::NEG_INFINITY as NEG_INF;
 use std::fmt;
 
+const NEG_INF: f64 = f64::NEG_INFINITY;
+
 use pest_derive::Parser;
 #[derive(Parser)]
 #[grammar = "obj.pest"]
@@ -22,12 +23,16 @@ impl fmt::Display for SemanticError {
 impl Error for SemanticError {}
 
 impl ObjParser {
-    pub fn load(matrix: &mut Matrix, mesh: &str) -> Result<(), Box<dyn Error>> {
+    // Adds the mesh's triangles to `matrix`, and the texture coordinates of each of
+    // their points to `uvs`. Points without texture coordinates get (0, 0)
+    pub fn load(matrix: &mut Matrix, uvs: &mut Vec<[f64; 2]>, mesh: &str) -> Result<(), Box<dyn Error>> {
         let nodes = ast::parse(mesh)?;
         // Create a vector for the vertices.
         // The .obj format one-indexes the vertex list, so push a dummy vertex for
         // index 0
         let mut vertices = vec![[NEG_INF, NEG_INF, NEG_INF, 1.0]];
+        // Same for the texture coordinates
+        let mut tex_coords = vec![[NEG_INF, NEG_INF]];
         for node in nodes {
             if let ObjCommand { command, args } = node {
                 match command {
@@ -39,15 +44,28 @@ impl ObjParser {
                             return Err(Box::new(SemanticError));
                         }
                     },
+                    // add texture coordinates to their list
+                    PCmd::TexCoord => {
+                        if let [Float(u), Float(v), ..] = args[..] {
+                            tex_coords.push([u, v]);
+                        } else {
+                            return Err(Box::new(SemanticError));
+                        }
+                    },
                     // push a triangle to the matrix
                     PCmd::Face => {
-                        if let [Whole(x), Whole(y), Whole(z)] = args[..] {
-                            for &vertex in &[x, y, z] {
+                        for arg in &args {
+                            if let FaceVertex(vertex, tex_coord) = *arg {
                                 let point = vertices.get(vertex).ok_or(SemanticError)?;
                                 matrix.push(*point);
+                                let uv = match tex_coord {
+                                    Some(i) => tex_coords.get(i).ok_or(SemanticError)?,
+                                    None => &[0., 0.],
+                                };
+                                uvs.push(*uv);
+                            } else {
+                                return Err(Box::new(SemanticError));
                             }
-                        } else {
-                            return Err(Box::new(SemanticError));
                         }
                     },
                     // do nothing
@@ -1,11 +1,9 @@
-use parse_obj::{ast, ObjParser, Rule};
+use lib_graphics::Matrix;
+use parse_obj::{ObjParser, Rule};
 use pest::error::Error;
 use pest::iterators::Pairs;
 use pest::Parser;
 
-use std::fs;
-use std::path::PathBuf;
-
 #[test]
 fn parse_float() {
     assert_eq!("", as_str(ObjParser::parse(Rule::float, "-NUMBER HERE 1.0")));
@@ -39,10 +37,31 @@ fn parse_vertex() {
     assert_eq!("v 0.5 -.5 -5.2", as_str(ObjParser::parse(Rule::vertex, "v 0.5 -.5 -5.2")));
 }
 
+#[test]
+fn parse_tex_coord() {
+    assert_eq!("vt 0.5 1", as_str(ObjParser::parse(Rule::tex_coord, "vt 0.5 1")));
+    assert_eq!("vt 0.5 1 0", as_str(ObjParser::parse(Rule::tex_coord, "vt 0.5 1 0")));
+    assert_eq!("", as_str(ObjParser::parse(Rule::tex_coord, "vt 0.5")));
+}
+
 #[test]
 fn parse_face() {
     assert_eq!("f 1 2 3", as_str(ObjParser::parse(Rule::face, "f 1 2 3")));
     assert_eq!("", as_str(ObjParser::parse(Rule::face, "f 0 -.5 5.2")));
+    assert_eq!("f 1/4 2/5 3/6", as_str(ObjParser::parse(Rule::face, "f 1/4 2/5 3/6")));
+    assert_eq!("f 1/4/7 2/5/8 3/6/9", as_str(ObjParser::parse(Rule::face, "f 1/4/7 2/5/8 3/6/9")));
+    assert_eq!("f 1//7 2//8 3//9", as_str(ObjParser::parse(Rule::face, "f 1//7 2//8 3//9")));
+    assert_eq!("1", as_str(ObjParser::parse(Rule::face_vertex, "1 /4")));
+}
+
+#[test]
+fn load_tex_coords() {
+    let mesh = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 0 1\nvn 0 0 1\nf 1/1/1 2/2/1 3/3/1\nf 3 2 1";
+    let (mut matrix, mut uvs) = (Matrix::default(), vec![]);
+    ObjParser::load(&mut matrix, &mut uvs, mesh).unwrap();
+    assert_eq!(6, matrix.m.len());
+    assert_eq!(vec![[0., 0.], [1., 0.], [0., 1.], [0., 0.], [0., 0.], [0., 0.]], uvs);
+    assert!(ObjParser::load(&mut matrix, &mut uvs, "v 0 0 0\nf 1/2 1/2 1/2").is_err());
 }
 
 fn as_str(parsed: Result<Pairs<'_, Rule>, Error<Rule>>) -> &str {